@@ -4,7 +4,6 @@ extern crate criterion;
 use criterion::{BatchSize, Criterion};
 use kvs::{KvStore, KvsEngine, SledKvsEngine};
 use rand::prelude::*;
-use serde_json;
 use std::env;
 use std::fs;
 use tempfile::TempDir;
@@ -39,14 +38,13 @@ fn load_kvs() -> Vec<(String, String)> {
                 let test_kvs: Vec<(String, String)> = gen_kvs(100000, 100000, 100);
 
                 fs::write(&bench_kv_file, serde_json::to_string(&test_kvs).unwrap()).unwrap();
-                return test_kvs;
+                test_kvs
             }
             _ => unimplemented!(),
         },
         Ok(_) => {
             let test_kvs_string = fs::read_to_string(&bench_kv_file).unwrap();
-            let test_kvs = serde_json::from_str(&test_kvs_string).unwrap();
-            return test_kvs;
+            serde_json::from_str(&test_kvs_string).unwrap()
         }
     }
 }
@@ -65,7 +63,7 @@ fn kvs_write_benchmark(c: &mut Criterion) {
                 fs::create_dir_all(&db_path).unwrap();
                 KvStore::open(&db_path).unwrap()
             },
-            |mut store| {
+            |store| {
                 for (k, v) in &test_kvs {
                     store.set(k.clone(), v.clone()).unwrap();
                 }
@@ -89,7 +87,7 @@ fn sled_write_benchmark(c: &mut Criterion) {
                 fs::create_dir_all(&db_path).unwrap();
                 SledKvsEngine::open(&db_path).unwrap()
             },
-            |mut store| {
+            |store| {
                 for (k, v) in &test_kvs {
                     store.set(k.clone(), v.clone()).unwrap();
                 }
@@ -103,18 +101,18 @@ fn kvs_read_benchmark(c: &mut Criterion) {
     let test_kvs = load_kvs();
     let temp_dir = TempDir::new().unwrap();
     {
-        let mut store = KvStore::open(&temp_dir.path()).unwrap();
+        let store = KvStore::open(temp_dir.path()).unwrap();
         for (k, v) in &test_kvs {
             store.set(k.clone(), v.clone()).unwrap();
         }
     }
     c.bench_function("kvs read", move |b| {
         b.iter_batched(
-            || KvStore::open(&temp_dir.path()).unwrap(),
-            |mut store| {
-                for x in 0..10 {
+            || KvStore::open(temp_dir.path()).unwrap(),
+            |store| {
+                for _ in 0..10 {
                     // 10 * 100 = 1000
-                    for (k, v) in &test_kvs {
+                    for (k, _) in &test_kvs {
                         store.get(k.clone()).unwrap();
                     }
                 }
@@ -130,15 +128,15 @@ fn sled_read_benchmark(c: &mut Criterion) {
     c.bench_function("sled read", move |b| {
         b.iter_batched(
             || {
-                let mut store = SledKvsEngine::open(&temp_dir.path()).unwrap();
+                let store = SledKvsEngine::open(temp_dir.path()).unwrap();
                 for (k, v) in &test_kvs {
                     store.set(k.clone(), v.clone()).unwrap();
                 }
                 store
             },
-            |mut store| {
+            |store| {
                 for _ in 0..10 {
-                    for (k, v) in &test_kvs {
+                    for (k, _) in &test_kvs {
                         store.get(k.clone()).unwrap();
                     }
                 }
@@ -1,17 +1,92 @@
 #[macro_use]
 extern crate criterion;
 
-use criterion::{BatchSize, Criterion, ParameterizedBenchmark};
+use criterion::{BatchSize, Bencher, Criterion, ParameterizedBenchmark};
 use crossbeam::channel::unbounded;
-use kvs::network::{KvsClient, KvsServer};
-use kvs::thread_pool::{RayonThreadPool, SharedQueueThreadPool, ThreadPool};
+use kvs::network::{KvsClient, KvsReactorServer, KvsServer};
+use kvs::thread_pool::{
+    RayonThreadPool, SharedQueueThreadPool, ThreadPool, WorkStealingThreadPool,
+};
 use kvs::{KvStore, SledKvsEngine};
 use std::fs;
-use std::sync::atomic::{AtomicI32, Ordering};
-use std::sync::Arc;
 use std::thread;
 use tempfile::TempDir;
 
+// Clients on a `P` setting keys on a server running on a `P`
+fn write_with_pool<P: ThreadPool + Send + Sync + 'static>(
+    b: &mut Bencher,
+    num: u32,
+    addr: &'static str,
+) {
+    let temp_dir = TempDir::new().unwrap();
+    let mut idx = 0;
+
+    // do setup here
+    let mut kvs = vec![];
+    for i in 0..1000 {
+        let k = format!("{:0>8}", i);
+        let v = "value".to_owned();
+        kvs.push((k, v));
+    }
+
+    let (c_tx, s_rx) = unbounded();
+    let (s_tx, c_rx) = unbounded();
+
+    b.iter_batched(
+        || {
+            // setup store
+            let db_path = temp_dir.path().join(format!("{}", idx));
+            idx += 1;
+
+            fs::create_dir_all(&db_path).unwrap();
+            let store = KvStore::open(&db_path).unwrap();
+            let pool = P::new(num).unwrap();
+
+            let mut server = KvsServer::new(store, pool)
+                .rx(c_rx.clone())
+                .tx(c_tx.clone());
+            thread::spawn(move || {
+                server.listen(addr.parse().unwrap()).unwrap();
+            });
+            P::new(num).unwrap()
+        },
+        |client_pool| {
+            let mut handles = vec![];
+            for (k, v) in &kvs {
+                let _k = k.clone();
+                let _v = v.clone();
+                handles.push(client_pool.spawn_with_handle(move || {
+                    let mut client = KvsClient::new(addr.parse().unwrap()).unwrap();
+                    client.handshake().unwrap();
+                    client.set(_k, _v).unwrap();
+                    client.quit().unwrap();
+                }));
+            }
+            // all jobs done
+            for handle in handles {
+                handle.join().unwrap();
+            }
+
+            let mut check_client = KvsClient::new(addr.parse().unwrap()).unwrap();
+            check_client.handshake().unwrap();
+            for (k, v) in &kvs {
+                let _k = k.clone();
+                let _v = check_client.get(_k).unwrap().unwrap();
+                assert_eq!(_v, *v);
+            }
+            check_client.quit().unwrap();
+
+            // send shutdown to server
+            s_tx.send(()).unwrap();
+            // trigger quit
+            KvsClient::new(addr.parse().unwrap()).unwrap();
+            // wait for shutdown ack
+            s_rx.recv().unwrap();
+        },
+        BatchSize::SmallInput,
+    )
+}
+
 fn write_with_different_threadpool(c: &mut Criterion) {
     let inputs = &[1, 2, 4, 8];
 
@@ -19,159 +94,14 @@ fn write_with_different_threadpool(c: &mut Criterion) {
         "write_with_different_threadpool",
         ParameterizedBenchmark::new(
             "SharedQueueThreadPool",
-            move |b, &&num| {
-                let temp_dir = TempDir::new().unwrap();
-                let mut idx = 0;
-
-                // do setup here
-                let mut kvs = vec![];
-                for i in 0..1000 {
-                    let k = format!("{:0>8}", i);
-                    let v = "value".to_owned();
-                    kvs.push((k, v));
-                }
-
-                let (c_tx, s_rx) = unbounded();
-                let (s_tx, c_rx) = unbounded();
-
-                b.iter_batched(
-                    || {
-                        // setup store
-                        let db_path = temp_dir.path().join(format!("{}", idx));
-                        idx += 1;
-
-                        fs::create_dir_all(&db_path).unwrap();
-                        let store = KvStore::open(&db_path).unwrap();
-                        let pool = SharedQueueThreadPool::new(num).unwrap();
-
-                        let mut server = KvsServer::new(store, pool)
-                            .rx(c_rx.clone())
-                            .tx(c_tx.clone());
-                        thread::spawn(move || {
-                            server.listen("127.0.0.1:4001".parse().unwrap()).unwrap();
-                        });
-                        let client_pool = SharedQueueThreadPool::new(num).unwrap();
-                        client_pool
-                    },
-                    |client_pool| {
-                        let done_jobs = Arc::new(AtomicI32::new(0));
-                        for (k, v) in &kvs {
-                            let _k = k.clone();
-                            let _v = v.clone();
-                            let _done_jobs = done_jobs.clone();
-                            client_pool.spawn(move || {
-                                let mut client =
-                                    KvsClient::new("127.0.0.1:4001".parse().unwrap()).unwrap();
-                                client.handshake().unwrap();
-                                client.set(_k, _v).unwrap();
-                                client.quit().unwrap();
-                                _done_jobs.fetch_add(1, Ordering::Relaxed);
-                            })
-                        }
-                        // all jobs done
-                        loop {
-                            if done_jobs.load(Ordering::Relaxed) == 1000 {
-                                break;
-                            }
-                        }
-
-                        let mut check_client =
-                            KvsClient::new("127.0.0.1:4001".parse().unwrap()).unwrap();
-                        check_client.handshake().unwrap();
-                        for (k, v) in &kvs {
-                            let _k = k.clone();
-                            let _v = check_client.get(_k).unwrap().unwrap();
-                            assert_eq!(_v, *v);
-                        }
-                        check_client.quit().unwrap();
-
-                        // send shutdown to server
-                        s_tx.send(()).unwrap();
-                        // trigger quit
-                        KvsClient::new("127.0.0.1:4001".parse().unwrap()).unwrap();
-                        // wait for shutdown ack
-                        s_rx.recv().unwrap();
-                    },
-                    BatchSize::SmallInput,
-                )
-            },
+            move |b, &&num| write_with_pool::<SharedQueueThreadPool>(b, num, "127.0.0.1:4001"),
             inputs,
         )
         .with_function("RayonThreadPool", move |b, &&num| {
-            let temp_dir = TempDir::new().unwrap();
-            let mut idx = 0;
-
-            // do setup here
-            let mut kvs = vec![];
-            for i in 0..1000 {
-                let k = format!("{:0>8}", i);
-                let v = "value".to_owned();
-                kvs.push((k, v));
-            }
-
-            let (c_tx, s_rx) = unbounded();
-            let (s_tx, c_rx) = unbounded();
-
-            b.iter_batched(
-                || {
-                    // setup store
-                    let db_path = temp_dir.path().join(format!("{}", idx));
-                    idx += 1;
-
-                    fs::create_dir_all(&db_path).unwrap();
-                    let store = KvStore::open(&db_path).unwrap();
-                    let pool = RayonThreadPool::new(num).unwrap();
-
-                    let mut server = KvsServer::new(store, pool)
-                        .rx(c_rx.clone())
-                        .tx(c_tx.clone());
-                    thread::spawn(move || {
-                        server.listen("127.0.0.1:4002".parse().unwrap()).unwrap();
-                    });
-                    let client_pool = RayonThreadPool::new(num).unwrap();
-                    client_pool
-                },
-                |client_pool| {
-                    let done_jobs = Arc::new(AtomicI32::new(0));
-                    for (k, v) in &kvs {
-                        let _k = k.clone();
-                        let _v = v.clone();
-                        let _done_jobs = done_jobs.clone();
-                        client_pool.spawn(move || {
-                            let mut client =
-                                KvsClient::new("127.0.0.1:4002".parse().unwrap()).unwrap();
-                            client.handshake().unwrap();
-                            client.set(_k, _v).unwrap();
-                            client.quit().unwrap();
-                            _done_jobs.fetch_add(1, Ordering::Relaxed);
-                        })
-                    }
-                    // all jobs done
-                    loop {
-                        if done_jobs.load(Ordering::Relaxed) == 1000 {
-                            break;
-                        }
-                    }
-
-                    let mut check_client =
-                        KvsClient::new("127.0.0.1:4002".parse().unwrap()).unwrap();
-                    check_client.handshake().unwrap();
-                    for (k, v) in &kvs {
-                        let _k = k.clone();
-                        let _v = check_client.get(_k).unwrap().unwrap();
-                        assert_eq!(_v, *v);
-                    }
-                    check_client.quit().unwrap();
-
-                    // send shutdown to server
-                    s_tx.send(()).unwrap();
-                    // trigger quit
-                    KvsClient::new("127.0.0.1:4002".parse().unwrap()).unwrap();
-                    // wait for shutdown ack
-                    s_rx.recv().unwrap();
-                },
-                BatchSize::SmallInput,
-            )
+            write_with_pool::<RayonThreadPool>(b, num, "127.0.0.1:4002")
+        })
+        .with_function("WorkStealingThreadPool", move |b, &&num| {
+            write_with_pool::<WorkStealingThreadPool>(b, num, "127.0.0.1:4011")
         }),
     );
 }
@@ -214,29 +144,24 @@ fn write_with_different_kvengine(c: &mut Criterion) {
                         thread::spawn(move || {
                             server.listen("127.0.0.1:4003".parse().unwrap()).unwrap();
                         });
-                        let client_pool = SharedQueueThreadPool::new(num).unwrap();
-                        client_pool
+                        SharedQueueThreadPool::new(num).unwrap()
                     },
                     |client_pool| {
-                        let done_jobs = Arc::new(AtomicI32::new(0));
+                        let mut handles = vec![];
                         for (k, v) in &kvs {
                             let _k = k.clone();
                             let _v = v.clone();
-                            let _done_jobs = done_jobs.clone();
-                            client_pool.spawn(move || {
+                            handles.push(client_pool.spawn_with_handle(move || {
                                 let mut client =
                                     KvsClient::new("127.0.0.1:4003".parse().unwrap()).unwrap();
                                 client.handshake().unwrap();
                                 client.set(_k, _v).unwrap();
                                 client.quit().unwrap();
-                                _done_jobs.fetch_add(1, Ordering::Relaxed);
-                            })
+                            }));
                         }
                         // all jobs done
-                        loop {
-                            if done_jobs.load(Ordering::Relaxed) == 1000 {
-                                break;
-                            }
+                        for handle in handles {
+                            handle.join().unwrap();
                         }
 
                         let mut check_client =
@@ -292,29 +217,24 @@ fn write_with_different_kvengine(c: &mut Criterion) {
                     thread::spawn(move || {
                         server.listen("127.0.0.1:4004".parse().unwrap()).unwrap();
                     });
-                    let client_pool = SharedQueueThreadPool::new(num).unwrap();
-                    client_pool
+                    SharedQueueThreadPool::new(num).unwrap()
                 },
                 |client_pool| {
-                    let done_jobs = Arc::new(AtomicI32::new(0));
+                    let mut handles = vec![];
                     for (k, v) in &kvs {
                         let _k = k.clone();
                         let _v = v.clone();
-                        let _done_jobs = done_jobs.clone();
-                        client_pool.spawn(move || {
+                        handles.push(client_pool.spawn_with_handle(move || {
                             let mut client =
                                 KvsClient::new("127.0.0.1:4004".parse().unwrap()).unwrap();
                             client.handshake().unwrap();
                             client.set(_k, _v).unwrap();
                             client.quit().unwrap();
-                            _done_jobs.fetch_add(1, Ordering::Relaxed);
-                        })
+                        }));
                     }
                     // all jobs done
-                    loop {
-                        if done_jobs.load(Ordering::Relaxed) == 1000 {
-                            break;
-                        }
+                    for handle in handles {
+                        handle.join().unwrap();
                     }
 
                     let mut check_client =
@@ -340,6 +260,75 @@ fn write_with_different_kvengine(c: &mut Criterion) {
     );
 }
 
+// Clients on a `P` getting keys from a server running on a `P`
+fn read_with_pool<P: ThreadPool + Send + Sync + 'static>(
+    b: &mut Bencher,
+    num: u32,
+    addr: &'static str,
+) {
+    let temp_dir = TempDir::new().unwrap();
+    let mut idx = 0;
+
+    let (c_tx, s_rx) = unbounded();
+    let (s_tx, c_rx) = unbounded();
+
+    b.iter_batched(
+        || {
+            // setup store
+            let db_path = temp_dir.path().join(format!("{}", idx));
+            idx += 1;
+
+            fs::create_dir_all(&db_path).unwrap();
+            let store = KvStore::open(&db_path).unwrap();
+            let pool = P::new(num).unwrap();
+
+            let mut server = KvsServer::new(store, pool)
+                .rx(c_rx.clone())
+                .tx(c_tx.clone());
+            thread::spawn(move || {
+                server.listen(addr.parse().unwrap()).unwrap();
+            });
+
+            let mut _client = KvsClient::new(addr.parse().unwrap()).unwrap();
+            _client.handshake().unwrap();
+            for i in 0..1000 {
+                let k = format!("{:0>8}", i);
+                let v = "value".to_owned();
+                _client.set(k, v).unwrap();
+            }
+            _client.quit().unwrap();
+
+            P::new(num).unwrap()
+        },
+        |client_pool| {
+            let mut handles = vec![];
+            for i in 0..1000 {
+                let k = format!("{:0>8}", i);
+                let v = "value".to_owned();
+                handles.push(client_pool.spawn_with_handle(move || {
+                    let mut client = KvsClient::new(addr.parse().unwrap()).unwrap();
+                    client.handshake().unwrap();
+                    let _v = client.get(k).unwrap().unwrap();
+                    assert_eq!(_v, v);
+                    client.quit().unwrap();
+                }));
+            }
+
+            // all jobs done
+            for handle in handles {
+                handle.join().unwrap();
+            }
+            // send shutdown to server
+            s_tx.send(()).unwrap();
+            // trigger quit
+            KvsClient::new(addr.parse().unwrap()).unwrap();
+            // wait for shutdown ack
+            s_rx.recv().unwrap();
+        },
+        BatchSize::SmallInput,
+    )
+}
+
 fn read_with_different_threadpool(c: &mut Criterion) {
     let inputs = &[1, 2, 4, 8];
 
@@ -347,6 +336,25 @@ fn read_with_different_threadpool(c: &mut Criterion) {
         "read_with_different_threadpool",
         ParameterizedBenchmark::new(
             "SharedQueueThreadPool",
+            move |b, &&num| read_with_pool::<SharedQueueThreadPool>(b, num, "127.0.0.1:4005"),
+            inputs,
+        )
+        .with_function("RayonThreadPool", move |b, &&num| {
+            read_with_pool::<RayonThreadPool>(b, num, "127.0.0.1:4006")
+        })
+        .with_function("WorkStealingThreadPool", move |b, &&num| {
+            read_with_pool::<WorkStealingThreadPool>(b, num, "127.0.0.1:4012")
+        }),
+    );
+}
+
+fn read_with_different_kvengine(c: &mut Criterion) {
+    let inputs = &[1, 2, 4, 8];
+
+    c.bench(
+        "read_with_different_kvengine",
+        ParameterizedBenchmark::new(
+            "KvStore",
             move |b, &&num| {
                 let temp_dir = TempDir::new().unwrap();
                 let mut idx = 0;
@@ -368,11 +376,11 @@ fn read_with_different_threadpool(c: &mut Criterion) {
                             .rx(c_rx.clone())
                             .tx(c_tx.clone());
                         thread::spawn(move || {
-                            server.listen("127.0.0.1:4005".parse().unwrap()).unwrap();
+                            server.listen("127.0.0.1:4007".parse().unwrap()).unwrap();
                         });
 
                         let mut _client =
-                            KvsClient::new("127.0.0.1:4005".parse().unwrap()).unwrap();
+                            KvsClient::new("127.0.0.1:4007".parse().unwrap()).unwrap();
                         _client.handshake().unwrap();
                         for i in 0..1000 {
                             let k = format!("{:0>8}", i);
@@ -381,36 +389,31 @@ fn read_with_different_threadpool(c: &mut Criterion) {
                         }
                         _client.quit().unwrap();
 
-                        let client_pool = SharedQueueThreadPool::new(num).unwrap();
-                        client_pool
+                        SharedQueueThreadPool::new(num).unwrap()
                     },
                     |client_pool| {
-                        let done_jobs = Arc::new(AtomicI32::new(0));
+                        let mut handles = vec![];
                         for i in 0..1000 {
                             let k = format!("{:0>8}", i);
                             let v = "value".to_owned();
-                            let _done_jobs = done_jobs.clone();
-                            client_pool.spawn(move || {
+                            handles.push(client_pool.spawn_with_handle(move || {
                                 let mut client =
-                                    KvsClient::new("127.0.0.1:4005".parse().unwrap()).unwrap();
+                                    KvsClient::new("127.0.0.1:4007".parse().unwrap()).unwrap();
                                 client.handshake().unwrap();
                                 let _v = client.get(k).unwrap().unwrap();
                                 assert_eq!(_v, v);
                                 client.quit().unwrap();
-                                _done_jobs.fetch_add(1, Ordering::Relaxed);
-                            })
+                            }));
                         }
 
                         // all jobs done
-                        loop {
-                            if done_jobs.load(Ordering::Relaxed) == 1000 {
-                                break;
-                            }
+                        for handle in handles {
+                            handle.join().unwrap();
                         }
                         // send shutdown to server
                         s_tx.send(()).unwrap();
                         // trigger quit
-                        KvsClient::new("127.0.0.1:4005".parse().unwrap()).unwrap();
+                        KvsClient::new("127.0.0.1:4007".parse().unwrap()).unwrap();
                         // wait for shutdown ack
                         s_rx.recv().unwrap();
                     },
@@ -419,7 +422,7 @@ fn read_with_different_threadpool(c: &mut Criterion) {
             },
             inputs,
         )
-        .with_function("RayonThreadPool", move |b, &&num| {
+        .with_function("SledKvsEngine", move |b, &&num| {
             let temp_dir = TempDir::new().unwrap();
             let mut idx = 0;
 
@@ -433,17 +436,17 @@ fn read_with_different_threadpool(c: &mut Criterion) {
                     idx += 1;
 
                     fs::create_dir_all(&db_path).unwrap();
-                    let store = KvStore::open(&db_path).unwrap();
-                    let pool = RayonThreadPool::new(num).unwrap();
+                    let store = SledKvsEngine::open(&db_path).unwrap();
+                    let pool = SharedQueueThreadPool::new(num).unwrap();
 
                     let mut server = KvsServer::new(store, pool)
                         .rx(c_rx.clone())
                         .tx(c_tx.clone());
                     thread::spawn(move || {
-                        server.listen("127.0.0.1:4006".parse().unwrap()).unwrap();
+                        server.listen("127.0.0.1:4008".parse().unwrap()).unwrap();
                     });
 
-                    let mut _client = KvsClient::new("127.0.0.1:4006".parse().unwrap()).unwrap();
+                    let mut _client = KvsClient::new("127.0.0.1:4008".parse().unwrap()).unwrap();
                     _client.handshake().unwrap();
                     for i in 0..1000 {
                         let k = format!("{:0>8}", i);
@@ -452,36 +455,31 @@ fn read_with_different_threadpool(c: &mut Criterion) {
                     }
                     _client.quit().unwrap();
 
-                    let client_pool = RayonThreadPool::new(num).unwrap();
-                    client_pool
+                    SharedQueueThreadPool::new(num).unwrap()
                 },
                 |client_pool| {
-                    let done_jobs = Arc::new(AtomicI32::new(0));
+                    let mut handles = vec![];
                     for i in 0..1000 {
                         let k = format!("{:0>8}", i);
                         let v = "value".to_owned();
-                        let _done_jobs = done_jobs.clone();
-                        client_pool.spawn(move || {
+                        handles.push(client_pool.spawn_with_handle(move || {
                             let mut client =
-                                KvsClient::new("127.0.0.1:4006".parse().unwrap()).unwrap();
+                                KvsClient::new("127.0.0.1:4008".parse().unwrap()).unwrap();
                             client.handshake().unwrap();
                             let _v = client.get(k).unwrap().unwrap();
                             assert_eq!(_v, v);
                             client.quit().unwrap();
-                            _done_jobs.fetch_add(1, Ordering::Relaxed);
-                        })
+                        }));
                     }
 
                     // all jobs done
-                    loop {
-                        if done_jobs.load(Ordering::Relaxed) == 1000 {
-                            break;
-                        }
+                    for handle in handles {
+                        handle.join().unwrap();
                     }
                     // send shutdown to server
                     s_tx.send(()).unwrap();
                     // trigger quit
-                    KvsClient::new("127.0.0.1:4006".parse().unwrap()).unwrap();
+                    KvsClient::new("127.0.0.1:4008".parse().unwrap()).unwrap();
                     // wait for shutdown ack
                     s_rx.recv().unwrap();
                 },
@@ -491,17 +489,31 @@ fn read_with_different_threadpool(c: &mut Criterion) {
     );
 }
 
-fn read_with_different_kvengine(c: &mut Criterion) {
+fn write_with_different_server_mode(c: &mut Criterion) {
+    // long-lived clients, more of them than server threads
+    const CLIENTS: usize = 8;
     let inputs = &[1, 2, 4, 8];
 
     c.bench(
-        "read_with_different_kvengine",
+        "write_with_different_server_mode",
         ParameterizedBenchmark::new(
-            "KvStore",
+            "KvsServer",
             move |b, &&num| {
                 let temp_dir = TempDir::new().unwrap();
                 let mut idx = 0;
 
+                // do setup here, every client keeps its connection for many ops
+                let mut kvs = vec![];
+                for c in 0..CLIENTS {
+                    let mut client_kvs = vec![];
+                    for i in 0..1000 / CLIENTS {
+                        let k = format!("{:0>8}", c * 1000 / CLIENTS + i);
+                        let v = "value".to_owned();
+                        client_kvs.push((k, v));
+                    }
+                    kvs.push(client_kvs);
+                }
+
                 let (c_tx, s_rx) = unbounded();
                 let (s_tx, c_rx) = unbounded();
 
@@ -519,49 +531,33 @@ fn read_with_different_kvengine(c: &mut Criterion) {
                             .rx(c_rx.clone())
                             .tx(c_tx.clone());
                         thread::spawn(move || {
-                            server.listen("127.0.0.1:4007".parse().unwrap()).unwrap();
+                            server.listen("127.0.0.1:4009".parse().unwrap()).unwrap();
                         });
-
-                        let mut _client =
-                            KvsClient::new("127.0.0.1:4007".parse().unwrap()).unwrap();
-                        _client.handshake().unwrap();
-                        for i in 0..1000 {
-                            let k = format!("{:0>8}", i);
-                            let v = "value".to_owned();
-                            _client.set(k, v).unwrap();
-                        }
-                        _client.quit().unwrap();
-
-                        let client_pool = SharedQueueThreadPool::new(num).unwrap();
-                        client_pool
+                        SharedQueueThreadPool::new(CLIENTS as u32).unwrap()
                     },
                     |client_pool| {
-                        let done_jobs = Arc::new(AtomicI32::new(0));
-                        for i in 0..1000 {
-                            let k = format!("{:0>8}", i);
-                            let v = "value".to_owned();
-                            let _done_jobs = done_jobs.clone();
-                            client_pool.spawn(move || {
+                        let mut handles = vec![];
+                        for client_kvs in &kvs {
+                            let _kvs = client_kvs.clone();
+                            handles.push(client_pool.spawn_with_handle(move || {
                                 let mut client =
-                                    KvsClient::new("127.0.0.1:4007".parse().unwrap()).unwrap();
+                                    KvsClient::new("127.0.0.1:4009".parse().unwrap()).unwrap();
                                 client.handshake().unwrap();
-                                let _v = client.get(k).unwrap().unwrap();
-                                assert_eq!(_v, v);
+                                for (k, v) in _kvs {
+                                    client.set(k, v).unwrap();
+                                }
                                 client.quit().unwrap();
-                                _done_jobs.fetch_add(1, Ordering::Relaxed);
-                            })
+                            }));
                         }
-
                         // all jobs done
-                        loop {
-                            if done_jobs.load(Ordering::Relaxed) == 1000 {
-                                break;
-                            }
+                        for handle in handles {
+                            handle.join().unwrap();
                         }
+
                         // send shutdown to server
                         s_tx.send(()).unwrap();
                         // trigger quit
-                        KvsClient::new("127.0.0.1:4007".parse().unwrap()).unwrap();
+                        KvsClient::new("127.0.0.1:4009".parse().unwrap()).unwrap();
                         // wait for shutdown ack
                         s_rx.recv().unwrap();
                     },
@@ -570,10 +566,22 @@ fn read_with_different_kvengine(c: &mut Criterion) {
             },
             inputs,
         )
-        .with_function("SledKvsEngine", move |b, &&num| {
+        .with_function("KvsReactorServer", move |b, &&num| {
             let temp_dir = TempDir::new().unwrap();
             let mut idx = 0;
 
+            // do setup here, every client keeps its connection for many ops
+            let mut kvs = vec![];
+            for c in 0..CLIENTS {
+                let mut client_kvs = vec![];
+                for i in 0..1000 / CLIENTS {
+                    let k = format!("{:0>8}", c * 1000 / CLIENTS + i);
+                    let v = "value".to_owned();
+                    client_kvs.push((k, v));
+                }
+                kvs.push(client_kvs);
+            }
+
             let (c_tx, s_rx) = unbounded();
             let (s_tx, c_rx) = unbounded();
 
@@ -584,55 +592,41 @@ fn read_with_different_kvengine(c: &mut Criterion) {
                     idx += 1;
 
                     fs::create_dir_all(&db_path).unwrap();
-                    let store = SledKvsEngine::open(&db_path).unwrap();
+                    let store = KvStore::open(&db_path).unwrap();
                     let pool = SharedQueueThreadPool::new(num).unwrap();
 
-                    let mut server = KvsServer::new(store, pool)
+                    let mut server = KvsReactorServer::new(store, pool)
+                        .io_threads(2)
                         .rx(c_rx.clone())
                         .tx(c_tx.clone());
                     thread::spawn(move || {
-                        server.listen("127.0.0.1:4008".parse().unwrap()).unwrap();
+                        server.listen("127.0.0.1:4010".parse().unwrap()).unwrap();
                     });
-
-                    let mut _client = KvsClient::new("127.0.0.1:4008".parse().unwrap()).unwrap();
-                    _client.handshake().unwrap();
-                    for i in 0..1000 {
-                        let k = format!("{:0>8}", i);
-                        let v = "value".to_owned();
-                        _client.set(k, v).unwrap();
-                    }
-                    _client.quit().unwrap();
-
-                    let client_pool = SharedQueueThreadPool::new(num).unwrap();
-                    client_pool
+                    SharedQueueThreadPool::new(CLIENTS as u32).unwrap()
                 },
                 |client_pool| {
-                    let done_jobs = Arc::new(AtomicI32::new(0));
-                    for i in 0..1000 {
-                        let k = format!("{:0>8}", i);
-                        let v = "value".to_owned();
-                        let _done_jobs = done_jobs.clone();
-                        client_pool.spawn(move || {
+                    let mut handles = vec![];
+                    for client_kvs in &kvs {
+                        let _kvs = client_kvs.clone();
+                        handles.push(client_pool.spawn_with_handle(move || {
                             let mut client =
-                                KvsClient::new("127.0.0.1:4008".parse().unwrap()).unwrap();
+                                KvsClient::new("127.0.0.1:4010".parse().unwrap()).unwrap();
                             client.handshake().unwrap();
-                            let _v = client.get(k).unwrap().unwrap();
-                            assert_eq!(_v, v);
+                            for (k, v) in _kvs {
+                                client.set(k, v).unwrap();
+                            }
                             client.quit().unwrap();
-                            _done_jobs.fetch_add(1, Ordering::Relaxed);
-                        })
+                        }));
                     }
-
                     // all jobs done
-                    loop {
-                        if done_jobs.load(Ordering::Relaxed) == 1000 {
-                            break;
-                        }
+                    for handle in handles {
+                        handle.join().unwrap();
                     }
+
                     // send shutdown to server
                     s_tx.send(()).unwrap();
                     // trigger quit
-                    KvsClient::new("127.0.0.1:4008".parse().unwrap()).unwrap();
+                    KvsClient::new("127.0.0.1:4010".parse().unwrap()).unwrap();
                     // wait for shutdown ack
                     s_rx.recv().unwrap();
                 },
@@ -645,6 +639,6 @@ fn read_with_different_kvengine(c: &mut Criterion) {
 criterion_group! {
     name = benches;
     config = Criterion::default().sample_size(2);
-    targets = write_with_different_threadpool,write_with_different_kvengine,read_with_different_threadpool,read_with_different_kvengine
+    targets = write_with_different_threadpool,write_with_different_kvengine,read_with_different_threadpool,read_with_different_kvengine,write_with_different_server_mode
 }
 criterion_main!(benches);
@@ -2,10 +2,13 @@ extern crate structopt;
 use structopt::StructOpt;
 
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process;
 
 extern crate kvs;
 
-use kvs::network::KvsClient;
+use kvs::network::{tls, KvsClient, WatchEvent, WatchFilter};
+use kvs::raft::{self, NodeId};
 use kvs::Result;
 
 #[derive(StructOpt, Debug)]
@@ -16,6 +19,19 @@ enum Opts {
     Get(GetArgs),
     #[structopt(name = "rm", about = "Remove key")]
     Remove(RemoveArgs),
+    #[structopt(name = "watch", about = "Print changes to a key as they happen")]
+    Watch(WatchArgs),
+    #[structopt(
+        name = "info",
+        about = "Print server, engine and thread pool statistics"
+    )]
+    Info(InfoArgs),
+    #[structopt(name = "promote", about = "Turn a follower into a leader")]
+    Promote(PromoteArgs),
+    #[structopt(name = "add-node", about = "Add a node to a Raft cluster")]
+    AddNode(AddNodeArgs),
+    #[structopt(name = "remove-node", about = "Remove a node from a Raft cluster")]
+    RemoveNode(RemoveNodeArgs),
 }
 
 #[derive(StructOpt, Debug)]
@@ -32,6 +48,8 @@ struct SetArgs {
         parse(try_from_str)
     )]
     addr: SocketAddr,
+    #[structopt(flatten)]
+    conn: ConnArgs,
 }
 
 #[derive(StructOpt, Debug)]
@@ -46,6 +64,8 @@ struct GetArgs {
         parse(try_from_str)
     )]
     addr: SocketAddr,
+    #[structopt(flatten)]
+    conn: ConnArgs,
 }
 
 #[derive(StructOpt, Debug)]
@@ -60,20 +80,171 @@ struct RemoveArgs {
         parse(try_from_str)
     )]
     addr: SocketAddr,
+    #[structopt(flatten)]
+    conn: ConnArgs,
+}
+
+#[derive(StructOpt, Debug)]
+struct WatchArgs {
+    #[structopt(name = "KEY")]
+    key: String,
+    #[structopt(long, help = "Watch every key starting with KEY")]
+    prefix: bool,
+    #[structopt(
+        long,
+        help = "Set server address",
+        value_name = "IP:PORT",
+        default_value = "127.0.0.1:4000",
+        parse(try_from_str)
+    )]
+    addr: SocketAddr,
+    #[structopt(flatten)]
+    conn: ConnArgs,
+}
+
+#[derive(StructOpt, Debug)]
+struct InfoArgs {
+    #[structopt(
+        long,
+        help = "Set server address",
+        value_name = "IP:PORT",
+        default_value = "127.0.0.1:4000",
+        parse(try_from_str)
+    )]
+    addr: SocketAddr,
+    #[structopt(flatten)]
+    conn: ConnArgs,
+}
+
+#[derive(StructOpt, Debug)]
+struct PromoteArgs {
+    #[structopt(
+        long,
+        help = "Set server address",
+        value_name = "IP:PORT",
+        default_value = "127.0.0.1:4000",
+        parse(try_from_str)
+    )]
+    addr: SocketAddr,
+    #[structopt(flatten)]
+    conn: ConnArgs,
+}
+
+#[derive(StructOpt, Debug)]
+struct AddNodeArgs {
+    #[structopt(name = "ID")]
+    id: NodeId,
+    #[structopt(
+        name = "RAFT-ADDR",
+        help = "Raft address of the new node",
+        parse(try_from_str)
+    )]
+    addr: SocketAddr,
+    #[structopt(
+        long,
+        help = "Raft address of the leader",
+        value_name = "IP:PORT",
+        parse(try_from_str)
+    )]
+    leader: SocketAddr,
+}
+
+#[derive(StructOpt, Debug)]
+struct RemoveNodeArgs {
+    #[structopt(name = "ID")]
+    id: NodeId,
+    #[structopt(
+        long,
+        help = "Raft address of the leader",
+        value_name = "IP:PORT",
+        parse(try_from_str)
+    )]
+    leader: SocketAddr,
 }
 
-fn main() -> Result<()> {
-    let opt = Opts::from_args();
+#[derive(StructOpt, Debug)]
+struct ConnArgs {
+    #[structopt(
+        long = "tls-ca",
+        help = "Connect with TLS, verifying the server against this CA",
+        value_name = "PEM",
+        parse(from_os_str)
+    )]
+    ca: Option<PathBuf>,
+    #[structopt(
+        long = "tls-cert",
+        help = "Client certificate for mutual TLS",
+        value_name = "PEM",
+        requires = "key",
+        parse(from_os_str)
+    )]
+    cert: Option<PathBuf>,
+    #[structopt(
+        long = "tls-key",
+        help = "Client private key for mutual TLS",
+        value_name = "PEM",
+        requires = "cert",
+        parse(from_os_str)
+    )]
+    key: Option<PathBuf>,
+    #[structopt(
+        long = "tls-domain",
+        help = "Name the server certificate is issued for",
+        value_name = "DOMAIN",
+        default_value = "localhost"
+    )]
+    domain: String,
+    #[structopt(
+        long,
+        help = "Log in as this user",
+        value_name = "USER",
+        requires = "password"
+    )]
+    user: Option<String>,
+    #[structopt(
+        long,
+        help = "Password of the user",
+        value_name = "PASSWORD",
+        requires = "user"
+    )]
+    password: Option<String>,
+}
+
+fn connect(addr: SocketAddr, conn: &ConnArgs) -> Result<KvsClient> {
+    let mut client = match &conn.ca {
+        Some(ca) => {
+            let cert_key = match (&conn.cert, &conn.key) {
+                (Some(cert), Some(key)) => Some((cert.as_path(), key.as_path())),
+                _ => None,
+            };
+            let config = tls::client_config(ca, cert_key)?;
+            KvsClient::new_tls(addr, &config, &conn.domain)?
+        }
+        None => KvsClient::new(addr)?,
+    };
+    if let (Some(user), Some(password)) = (&conn.user, &conn.password) {
+        client = client.with_credentials(user, password);
+    }
+    client.handshake()?;
+    Ok(client)
+}
+
+fn main() {
+    if let Err(e) = run(Opts::from_args()) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn run(opt: Opts) -> Result<()> {
     match opt {
         Opts::Set(set_args) => {
-            let mut client = KvsClient::new(set_args.addr)?;
-            client.handshake()?;
+            let mut client = connect(set_args.addr, &set_args.conn)?;
             client.set(set_args.key, set_args.value)?;
             client.quit()?;
         }
         Opts::Get(get_args) => {
-            let mut client = KvsClient::new(get_args.addr)?;
-            client.handshake()?;
+            let mut client = connect(get_args.addr, &get_args.conn)?;
             let resp = client.get(get_args.key)?;
             match resp {
                 Some(v) => println!("{}", v),
@@ -82,11 +253,37 @@ fn main() -> Result<()> {
             client.quit()?;
         }
         Opts::Remove(remove_args) => {
-            let mut client = KvsClient::new(remove_args.addr)?;
-            client.handshake()?;
+            let mut client = connect(remove_args.addr, &remove_args.conn)?;
             client.remove(remove_args.key)?;
             client.quit()?;
         }
+        Opts::Watch(watch_args) => {
+            let client = connect(watch_args.addr, &watch_args.conn)?;
+            let filter = if watch_args.prefix {
+                WatchFilter::Prefix(watch_args.key)
+            } else {
+                WatchFilter::Key(watch_args.key)
+            };
+            for event in client.watch(filter)? {
+                match event? {
+                    WatchEvent::Set(k, v) => println!("set {} {}", k, v),
+                    WatchEvent::Remove(k) => println!("rm {}", k),
+                    WatchEvent::Heartbeat => {}
+                }
+            }
+        }
+        Opts::Info(info_args) => {
+            let mut client = connect(info_args.addr, &info_args.conn)?;
+            println!("{}", client.info()?);
+            client.quit()?;
+        }
+        Opts::Promote(promote_args) => {
+            let mut client = connect(promote_args.addr, &promote_args.conn)?;
+            client.promote()?;
+            client.quit()?;
+        }
+        Opts::AddNode(args) => raft::add_node(args.leader, args.id, args.addr)?,
+        Opts::RemoveNode(args) => raft::remove_node(args.leader, args.id)?,
     };
     Ok(())
 }
@@ -0,0 +1,215 @@
+#[macro_use]
+extern crate clap;
+#[macro_use]
+extern crate log;
+extern crate env_logger;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use structopt::StructOpt;
+
+extern crate kvs;
+use kvs::network::{Authenticator, KvsClientPool, KvsReactorServer, KvsServer, ProxyKvsEngine};
+use kvs::thread_pool::{Scheduler, SharedQueueThreadPool, ThreadPool};
+use kvs::Result;
+
+#[derive(StructOpt, Debug)]
+struct Opts {
+    #[structopt(
+        long,
+        help = "Set proxy address",
+        value_name = "IP:PORT",
+        default_value = "127.0.0.1:4100",
+        parse(try_from_str)
+    )]
+    addr: SocketAddr,
+    #[structopt(
+        long = "backend",
+        help = "Backend kvs-server, can be repeated",
+        value_name = "IP:PORT",
+        required = true,
+        parse(try_from_str)
+    )]
+    backends: Vec<SocketAddr>,
+    #[structopt(
+        long,
+        help = "Set proxy mode",
+        value_name = "MODE",
+        default_value = "reactor",
+        raw(possible_values = "&Mode::variants()")
+    )]
+    mode: Mode,
+    #[structopt(
+        long = "max-idle",
+        help = "Idle connections kept open to each backend",
+        value_name = "N",
+        default_value = "32"
+    )]
+    max_idle: usize,
+    #[structopt(
+        long,
+        help = "Virtual nodes of each backend on the hash ring",
+        value_name = "N",
+        default_value = "100"
+    )]
+    vnodes: u32,
+    #[structopt(
+        long = "health-interval",
+        help = "Milliseconds between backend health checks",
+        value_name = "MS",
+        default_value = "1000"
+    )]
+    health_interval: u64,
+    #[structopt(
+        long = "status-addr",
+        help = "Serve backend health over HTTP",
+        value_name = "IP:PORT",
+        parse(try_from_str)
+    )]
+    status_addr: Option<SocketAddr>,
+    #[structopt(
+        long = "auth-config",
+        help = "Require a login, users and their ACLs are read from this json file",
+        value_name = "FILE",
+        parse(from_os_str)
+    )]
+    auth_config: Option<PathBuf>,
+    #[structopt(
+        long = "backend-user",
+        help = "Log in to the backends as this user, needs --auth-config",
+        value_name = "USER",
+        requires = "backend_password",
+        // or anybody reaching the proxy would act as this user
+        requires = "auth_config"
+    )]
+    backend_user: Option<String>,
+    #[structopt(
+        long = "backend-password",
+        help = "Password of the backend user",
+        value_name = "PASSWORD",
+        requires = "backend_user"
+    )]
+    backend_password: Option<String>,
+}
+
+arg_enum! {
+    #[allow(non_camel_case_types)]
+    #[derive(Copy, Clone, PartialEq, Debug)]
+    pub enum Mode {
+        thread,
+        reactor
+    }
+}
+
+// a backend slower than this is as good as down
+const BACKEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn main() -> Result<()> {
+    env_logger::init();
+    let opt = Opts::from_args();
+    error!(
+        "{} version: {}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION")
+    );
+    error!(
+        "Configuration: --addr {} --mode {} --backend {:?}",
+        opt.addr, opt.mode, opt.backends
+    );
+
+    let pools = opt
+        .backends
+        .iter()
+        .map(|addr| {
+            let mut pool = KvsClientPool::new(*addr, opt.max_idle).timeout(Some(BACKEND_TIMEOUT));
+            if let (Some(user), Some(password)) = (&opt.backend_user, &opt.backend_password) {
+                pool = pool.credentials(user, password);
+            }
+            pool
+        })
+        .collect();
+    let proxy = ProxyKvsEngine::new(pools, opt.vnodes);
+    check_health(&proxy);
+    let checker = proxy.clone();
+    let interval = Duration::from_millis(opt.health_interval);
+    // a check at a time, the scheduler skips those due while one is going
+    let checks = Arc::new(SharedQueueThreadPool::new(1)?);
+    let scheduler = Scheduler::new(&checks)?;
+    let _health = scheduler.schedule_every(interval, move || check_health(&checker))?;
+    if let Some(addr) = opt.status_addr {
+        serve_status(addr, proxy.clone())?;
+    }
+
+    let auth = match &opt.auth_config {
+        Some(path) => Some(Authenticator::load(path)?),
+        None => None,
+    };
+    let cpus = num_cpus::get() as u32;
+    let pool = SharedQueueThreadPool::new(cpus)?;
+    match opt.mode {
+        Mode::thread => {
+            let mut server = KvsServer::new(proxy, pool);
+            if let Some(auth) = auth {
+                server = server.auth(auth);
+            }
+            server.listen(opt.addr)
+        }
+        Mode::reactor => {
+            let mut server =
+                KvsReactorServer::new(proxy, pool).io_threads((cpus as usize / 2).max(1));
+            if let Some(auth) = auth {
+                server = server.auth(auth);
+            }
+            server.listen(opt.addr)
+        }
+    }
+}
+
+// Log the backends going down or coming back
+fn check_health(proxy: &ProxyKvsEngine) {
+    let before = proxy.health();
+    for (old, new) in before.iter().zip(proxy.check_health()) {
+        if old.healthy != new.healthy || old.latency.is_none() {
+            if new.healthy {
+                info!("Backend {}", new);
+            } else {
+                warn!("Backend {}", new);
+            }
+        }
+    }
+}
+
+// One line per backend, 503 when any of them is down
+fn serve_status(addr: SocketAddr, proxy: ProxyKvsEngine) -> Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            // the request doesn't matter, every path gets the status
+            let mut buf = [0u8; 1024];
+            let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+            let _ = stream.read(&mut buf);
+            let health = proxy.health();
+            let status = if health.iter().all(|h| h.healthy) {
+                "200 OK"
+            } else {
+                "503 Service Unavailable"
+            };
+            let body: String = health.iter().map(|h| format!("{}\n", h)).collect();
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+        }
+    });
+    Ok(())
+}
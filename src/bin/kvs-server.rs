@@ -3,16 +3,28 @@ extern crate clap;
 #[macro_use]
 extern crate log;
 extern crate env_logger;
-use num_cpus;
+use rustls::TLSError;
 use std::env;
 use std::fs;
-use std::net::SocketAddr;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 use structopt::StructOpt;
 
 extern crate kvs;
-use kvs::network::KvsServer;
-use kvs::thread_pool::{NaiveThreadPool, SharedQueueThreadPool, ThreadPool};
-use kvs::{KvStore, KvStoreError, Result, SledKvsEngine};
+use kvs::network::{
+    metrics, tls, Authenticator, Follower, KvsReactorServer, KvsServer, Replication, ServerContext,
+    WatchHub,
+};
+use kvs::raft::{NodeId, RaftConfig, RaftKvsEngine};
+use kvs::thread_pool::{
+    panic_message, ElasticThreadPool, PriorityThreadPool, QueuePolicy, SharedQueueThreadPool,
+    ThreadPool, ThreadPoolBuilder, DEFAULT_KEEP_ALIVE,
+};
+use kvs::{KvStore, KvStoreError, KvsEngine, Result, SledKvsEngine};
 
 #[derive(StructOpt, Debug)]
 struct Opts {
@@ -31,6 +43,137 @@ struct Opts {
         raw(possible_values = "&Engine::variants()")
     )]
     engine: Option<Engine>,
+    #[structopt(
+        long,
+        help = "Set server mode",
+        value_name = "MODE",
+        default_value = "thread",
+        raw(possible_values = "&Mode::variants()")
+    )]
+    mode: Mode,
+    #[structopt(
+        long = "tls-cert",
+        help = "Serve TLS with this certificate chain",
+        value_name = "PEM",
+        requires = "tls_key",
+        parse(from_os_str)
+    )]
+    tls_cert: Option<PathBuf>,
+    #[structopt(
+        long = "tls-key",
+        help = "Private key of the TLS certificate",
+        value_name = "PEM",
+        requires = "tls_cert",
+        parse(from_os_str)
+    )]
+    tls_key: Option<PathBuf>,
+    #[structopt(
+        long = "tls-ca",
+        help = "Require client certificates signed by this CA",
+        value_name = "PEM",
+        requires = "tls_cert",
+        parse(from_os_str)
+    )]
+    tls_ca: Option<PathBuf>,
+    #[structopt(
+        long = "auth-config",
+        help = "Require a login, users and their ACLs are read from this json file",
+        value_name = "FILE",
+        parse(from_os_str)
+    )]
+    auth_config: Option<PathBuf>,
+    #[structopt(
+        long = "replica-of",
+        help = "Run as a read-only follower of this leader",
+        value_name = "IP:PORT",
+        parse(try_from_str)
+    )]
+    replica_of: Option<SocketAddr>,
+    #[structopt(
+        long = "leader-user",
+        help = "Log in to the leader as this user",
+        value_name = "USER",
+        requires = "leader_password"
+    )]
+    leader_user: Option<String>,
+    #[structopt(
+        long = "leader-password",
+        help = "Password of the leader user",
+        value_name = "PASSWORD",
+        requires = "leader_user",
+        requires = "replica_of"
+    )]
+    leader_password: Option<String>,
+    #[structopt(
+        long = "raft-id",
+        help = "Run as a node of a Raft cluster",
+        value_name = "ID",
+        requires = "raft_addr",
+        conflicts_with = "replica_of"
+    )]
+    raft_id: Option<NodeId>,
+    #[structopt(
+        long = "raft-addr",
+        help = "Address for the other Raft nodes",
+        value_name = "IP:PORT",
+        requires = "raft_id",
+        parse(try_from_str)
+    )]
+    raft_addr: Option<SocketAddr>,
+    #[structopt(
+        long = "raft-peer",
+        help = "Other node of a new cluster, can be repeated",
+        value_name = "ID=IP:PORT",
+        requires = "raft_id",
+        parse(try_from_str = "parse_peer")
+    )]
+    raft_peers: Vec<(NodeId, SocketAddr)>,
+    #[structopt(
+        long = "raft-join",
+        help = "Wait to be added to an existing cluster",
+        requires = "raft_id",
+        conflicts_with = "raft_peers"
+    )]
+    raft_join: bool,
+    #[structopt(
+        long = "metrics-addr",
+        help = "Serve Prometheus metrics over HTTP on this address",
+        value_name = "IP:PORT",
+        parse(try_from_str)
+    )]
+    metrics_addr: Option<SocketAddr>,
+    #[structopt(
+        long = "max-queue",
+        help = "Answer busy once this many requests wait for a thread",
+        value_name = "N"
+    )]
+    max_queue: Option<usize>,
+    #[structopt(
+        long = "min-threads",
+        help = "Grow and shrink the thread pool, keeping at least this many threads",
+        value_name = "N",
+        conflicts_with = "max_queue"
+    )]
+    min_threads: Option<u32>,
+    #[structopt(
+        long = "max-threads",
+        help = "Grow and shrink the thread pool, up to this many threads",
+        value_name = "N",
+        conflicts_with = "max_queue"
+    )]
+    max_threads: Option<u32>,
+    #[structopt(
+        long = "priority-pool",
+        help = "Run background engine work behind the requests on a priority thread pool",
+        raw(conflicts_with_all = r#"&["max_queue", "min_threads", "max_threads"]"#)
+    )]
+    priority_pool: bool,
+    #[structopt(
+        long = "retain-logs",
+        help = "Keep this many compacted logs for the change feed, kvs engine only",
+        value_name = "N"
+    )]
+    retain_logs: Option<usize>,
 }
 
 arg_enum! {
@@ -42,6 +185,15 @@ arg_enum! {
     }
 }
 
+arg_enum! {
+    #[allow(non_camel_case_types)]
+    #[derive(Copy, Clone, PartialEq, Debug)]
+    pub enum Mode {
+        thread,
+        reactor
+    }
+}
+
 fn main() -> Result<()> {
     env_logger::init();
     let opt = Opts::from_args();
@@ -51,27 +203,181 @@ fn main() -> Result<()> {
         env!("CARGO_PKG_NAME"),
         env!("CARGO_PKG_VERSION")
     );
-    error!("Configuration: --addr {} --engine {}", opt.addr, engine);
+    error!(
+        "Configuration: --addr {} --engine {} --mode {}",
+        opt.addr, engine, opt.mode
+    );
 
-    let cpus = num_cpus::get() as u32;
     if engine == Engine::kvs {
-        let store = KvStore::open(&env::current_dir()?)?;
-        let pool = SharedQueueThreadPool::new(cpus)?;
-        let mut server = KvsServer::new(store, pool);
-        server.listen(opt.addr)?;
+        let mut store = KvStore::open(&env::current_dir()?)?;
+        if let Some(logs) = opt.retain_logs {
+            store = store.retain_logs(logs)?;
+        }
+        start(store, &opt)?;
     } else if engine == Engine::sled {
+        if opt.retain_logs.is_some() {
+            warn!("--retain-logs is ignored by the sled engine");
+        }
         let store = SledKvsEngine::open(&env::current_dir()?)?;
-        let pool = SharedQueueThreadPool::new(cpus)?;
-        let mut server = KvsServer::new(store, pool);
-        server.listen(opt.addr)?;
+        start(store, &opt)?;
+    }
+    Ok(())
+}
+
+fn start<E: KvsEngine>(store: E, opt: &Opts) -> Result<()> {
+    let (id, addr) = match (opt.raft_id, opt.raft_addr) {
+        (Some(id), Some(addr)) => (id, addr),
+        _ => return run(store, opt),
+    };
+    let mut config = RaftConfig::new(id, addr, env::current_dir()?.join("raft"));
+    if !opt.raft_join {
+        config = config.peers(opt.raft_peers.iter().cloned().collect());
+    }
+    run(RaftKvsEngine::start(config, store)?, opt)
+}
+
+fn parse_peer(s: &str) -> std::result::Result<(NodeId, SocketAddr), String> {
+    let mut parts = s.splitn(2, '=');
+    let id = parts
+        .next()
+        .unwrap_or("")
+        .parse()
+        .map_err(|_| "invalid node id")?;
+    let addr = parts
+        .next()
+        .ok_or("expected ID=IP:PORT")?
+        .parse()
+        .map_err(|_| "invalid address")?;
+    Ok((id, addr))
+}
+
+fn run<E: KvsEngine>(store: E, opt: &Opts) -> Result<()> {
+    let cpus = num_cpus::get() as u32;
+    if opt.min_threads.is_some() || opt.max_threads.is_some() {
+        let min = opt.min_threads.unwrap_or(1);
+        let max = opt.max_threads.unwrap_or_else(|| cpus.max(min));
+        let pool =
+            ElasticThreadPool::with_limits_from_builder(workers(min), max, DEFAULT_KEEP_ALIVE)?;
+        return serve(store, pool, opt);
+    }
+    if opt.priority_pool {
+        return serve(store, workers(cpus).build::<PriorityThreadPool>()?, opt);
     }
+    match opt.max_queue {
+        Some(capacity) => serve(
+            store,
+            SharedQueueThreadPool::bounded_from_builder(
+                workers(cpus),
+                capacity,
+                QueuePolicy::Reject,
+            )?,
+            opt,
+        ),
+        None => serve(store, workers(cpus).build::<SharedQueueThreadPool>()?, opt),
+    }
+}
+
+fn workers(threads: u32) -> ThreadPoolBuilder {
+    ThreadPoolBuilder::new(threads)
+        .name_prefix("kvs-worker-")
+        // sessions end in a panic on errors, the pool counts them for the metrics
+        .panic_hook(|payload| error!("Session failed: {}", panic_message(payload)))
+}
+
+fn serve<E, T>(store: E, pool: T, opt: &Opts) -> Result<()>
+where
+    E: KvsEngine,
+    T: ThreadPool + Send + Sync + 'static,
+{
+    let cpus = num_cpus::get();
+    let tls = match (&opt.tls_cert, &opt.tls_key) {
+        (Some(cert), Some(key)) => Some(tls::server_config(cert, key, opt.tls_ca.as_deref())?),
+        _ => None,
+    };
+    let auth = match &opt.auth_config {
+        Some(path) => Some(Authenticator::load(path)?),
+        None => None,
+    };
+    let watch = Arc::new(WatchHub::new());
+    let replication = match opt.replica_of {
+        Some(leader) => {
+            let replication = Arc::new(Replication::follower());
+            let mut follower =
+                Follower::new(leader, store.clone(), replication.clone()).watch(watch.clone());
+            if let (Some(user), Some(password)) = (&opt.leader_user, &opt.leader_password) {
+                follower = follower.with_credentials(user, password);
+            }
+            follower.spawn();
+            replication
+        }
+        None => Arc::new(Replication::leader()),
+    };
+    match opt.mode {
+        Mode::thread => {
+            let mut server = KvsServer::new(store.clone(), pool)
+                .replication(replication)
+                .watch(watch);
+            if let Some(config) = tls {
+                server = server.tls(config);
+            }
+            if let Some(auth) = auth {
+                server = server.auth(auth);
+            }
+            if let Some(addr) = opt.metrics_addr {
+                serve_metrics(addr, store, server.context())?;
+            }
+            server.listen(opt.addr)
+        }
+        Mode::reactor if tls.is_some() => Err(KvStoreError::Tls(TLSError::General(
+            "TLS is not supported in reactor mode".to_owned(),
+        ))),
+        Mode::reactor => {
+            let mut server = KvsReactorServer::new(store.clone(), pool)
+                .io_threads((cpus / 2).max(1))
+                .replication(replication);
+            if let Some(auth) = auth {
+                server = server.auth(auth);
+            }
+            if let Some(addr) = opt.metrics_addr {
+                serve_metrics(addr, store, server.context())?;
+            }
+            server.listen(opt.addr)
+        }
+    }
+}
+
+fn serve_metrics<E: KvsEngine>(addr: SocketAddr, store: E, ctx: ServerContext) -> Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            // a slow scraper mustn't hold up the next one
+            let (store, ctx) = (store.clone(), ctx.clone());
+            thread::spawn(move || {
+                // the request doesn't matter, every path gets the metrics
+                let mut buf = [0u8; 1024];
+                let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+                let _ = stream.read(&mut buf);
+                let body = metrics::render(&store, &ctx);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+            });
+        }
+    });
     Ok(())
 }
 
 fn check_engine(e: &Option<Engine>) -> Result<Engine> {
     let _engine = match e {
         None => Engine::kvs,
-        Some(ng) => ng.clone(),
+        Some(ng) => *ng,
     };
     let cur_dir = env::current_dir()?;
     let engine_file = cur_dir.join("engine");
@@ -80,9 +386,9 @@ fn check_engine(e: &Option<Engine>) -> Result<Engine> {
             // If no engine file, write the engine into a new engine file
             std::io::ErrorKind::NotFound => {
                 fs::write(&engine_file, format!("{}", _engine))?;
-                return Ok(_engine);
+                Ok(_engine)
             }
-            _ => return Err(e.into()),
+            _ => Err(e.into()),
         },
         Ok(_) => {
             let last_engine = fs::read_to_string(&engine_file)?
@@ -91,7 +397,7 @@ fn check_engine(e: &Option<Engine>) -> Result<Engine> {
             if last_engine != _engine {
                 return Err(KvStoreError::EngineNotMatch);
             }
-            return Ok(last_engine);
+            Ok(last_engine)
         }
     }
 }
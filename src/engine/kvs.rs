@@ -1,16 +1,20 @@
 use crossbeam_skiplist::SkipMap;
 use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
 use std::fs::{self, File, OpenOptions};
 use std::io::prelude::*;
 use std::io::{self, BufReader, BufWriter, SeekFrom};
 use std::path::Path;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
-use crate::{KvStoreError, KvsEngine, Result};
+use crate::{EngineStats, KvStoreError, KvsEngine, Result};
 
 const COMPACTION_POINT: u64 = 1_000_000;
+// Where the writers compact even though `maintain` was asked to
+const MAINTENANCE_LIMIT: u64 = 2 * COMPACTION_POINT;
 
 type KvStoreEntryPoints = SkipMap<String, (u64, u64)>;
 
@@ -20,15 +24,95 @@ enum Commands {
     Remove(RemoveCommand),
 }
 
+impl Commands {
+    fn seq(&self) -> u64 {
+        match self {
+            Commands::Set(cmd) => cmd.seq,
+            Commands::Remove(cmd) => cmd.seq,
+        }
+    }
+}
+
+// `seq` numbers the change feed, records of older logs have none and read as 0
 #[derive(Serialize, Deserialize, Debug)]
 struct SetCommand {
     key: String,
     value: String,
+    #[serde(default)]
+    seq: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 struct RemoveCommand {
     key: String,
+    #[serde(default)]
+    seq: u64,
+}
+
+/// A write read back from the log, `value` is `None` for a removal
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub seq: u64,
+    pub key: String,
+    pub value: Option<String>,
+}
+
+impl From<Commands> for Change {
+    fn from(cmd: Commands) -> Self {
+        match cmd {
+            Commands::Set(cmd) => Change {
+                seq: cmd.seq,
+                key: cmd.key,
+                value: Some(cmd.value),
+            },
+            Commands::Remove(cmd) => Change {
+                seq: cmd.seq,
+                key: cmd.key,
+                value: None,
+            },
+        }
+    }
+}
+
+type LogStream =
+    serde_json::StreamDeserializer<'static, serde_json::de::IoRead<BufReader<File>>, Commands>;
+
+/// Changes after a sequence number, in order, ends at the current end of the log.
+/// Call `KvStore::changes` again with the last seen `seq` to keep tailing.
+pub struct ChangeFeed {
+    // the logs left to read and the first sequence written live to each,
+    // anything below it was copied there by compaction
+    logs: VecDeque<(u64, LogStream)>,
+    since: u64,
+}
+
+impl Iterator for ChangeFeed {
+    type Item = Result<Change>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (first_seq, stream) = self.logs.front_mut()?;
+            match stream.next() {
+                Some(Ok(cmd)) => {
+                    let seq = cmd.seq();
+                    if seq >= *first_seq && seq > self.since {
+                        return Some(Ok(cmd.into()));
+                    }
+                }
+                // a record still being written ends the log like eof
+                Some(Err(ref e)) if e.is_eof() => {
+                    self.logs.pop_front();
+                }
+                None => {
+                    self.logs.pop_front();
+                }
+                Some(Err(e)) => {
+                    self.logs.clear();
+                    return Some(Err(e.into()));
+                }
+            }
+        }
+    }
 }
 
 /// The struct KvStore stores k-v string pairs
@@ -78,10 +162,16 @@ impl KvStoreWriter {
         })
     }
 
-    pub fn write_cmd(&mut self, cmd: &Commands) -> Result<()> {
+    pub fn write_cmd(&mut self, mut cmd: Commands) -> Result<()> {
         // always using the latest version db file
         self.check_writer_version()?;
 
+        let seq = self.meta.last_seq.load(Ordering::SeqCst) + 1;
+        match &mut cmd {
+            Commands::Set(s_cmd) => s_cmd.seq = seq,
+            Commands::Remove(r_cmd) => r_cmd.seq = seq,
+        }
+
         // write to tail
         let pos = self.writer.seek(SeekFrom::End(0))?;
         self.writer
@@ -105,6 +195,7 @@ impl KvStoreWriter {
         self.meta
             .uncompact_size
             .fetch_add(next_pos - pos, Ordering::SeqCst);
+        self.meta.last_seq.store(seq, Ordering::SeqCst);
         Ok(())
     }
     pub fn check_writer_version(&mut self) -> Result<()> {
@@ -176,6 +267,8 @@ impl KvStoreReader {
 struct KvStoreCompactor {
     entrypoints: Arc<KvStoreEntryPoints>,
     reader: KvStoreReader,
+    // taken only to catch up with the writes made while copying
+    writer: Arc<Mutex<KvStoreWriter>>,
     meta_writer: BufWriter<File>,
     meta: Arc<KvStoreMeta>,
 }
@@ -185,70 +278,147 @@ impl KvStoreCompactor {
         meta_file: P,
         entrypoints: Arc<KvStoreEntryPoints>,
         reader: KvStoreReader,
+        writer: Arc<Mutex<KvStoreWriter>>,
         meta: Arc<KvStoreMeta>,
     ) -> Result<Self> {
         let meta_writer = BufWriter::new(OpenOptions::new().write(true).open(&meta_file)?);
         Ok(KvStoreCompactor {
             entrypoints,
             reader,
+            writer,
             meta_writer,
             meta,
         })
     }
-    pub fn compact(&mut self) -> Result<()> {
-        if self.meta.uncompact_size.load(Ordering::Relaxed) < COMPACTION_POINT {
+
+    pub fn write_meta(&mut self) -> Result<()> {
+        self.meta_writer.seek(SeekFrom::Start(0))?;
+        self.meta_writer
+            .write_all(&serde_json::to_string(&self.meta.clone_to_plain_meta())?.into_bytes())?;
+        self.meta_writer.flush()?;
+        // the meta may have shrunk
+        let len = self.meta_writer.stream_position()?;
+        self.meta_writer.get_ref().set_len(len)?;
+        Ok(())
+    }
+    // Copy the live records to a new log while the writers go on, then take
+    // the writer lock to copy what they wrote meanwhile and switch over.
+    // Only done once `threshold` bytes were written since the last compaction
+    pub fn compact(&mut self, threshold: u64) -> Result<()> {
+        if self.meta.uncompact_size.load(Ordering::Relaxed) < threshold {
             return Ok(());
         }
+        let start = Instant::now();
 
         let cur_version = self.meta.version.load(Ordering::SeqCst);
         let new_version = cur_version + 1;
-        let old_log_path = get_db_path(&self.meta.db_dir, cur_version);
+        let cur_log_path = get_db_path(&self.meta.db_dir, cur_version);
         let new_log_path = get_db_path(&self.meta.db_dir, new_version);
         let new_log = OpenOptions::new()
             .create(true)
             .append(true)
             .open(&new_log_path)?;
         let mut new_writer = BufWriter::new(new_log);
+        // the writes past this point are caught up with below
+        let copied_until = {
+            let mut writer = self.writer.lock().unwrap();
+            writer.check_writer_version()?;
+            writer.writer.seek(SeekFrom::End(0))?
+        };
 
+        // the index keeps pointing to the current log until the switch
+        let mut index: BTreeMap<String, (u64, u64)> = BTreeMap::new();
         for entry in self.entrypoints.iter() {
             let (pos, len) = *entry.value();
-            let cmd = self.reader.read_cmd(pos, len)?;
+            if let Commands::Set(set_cmd) = self.reader.read_cmd(pos, len)? {
+                let key = set_cmd.key.clone();
+                let pos_len_pair = append_cmd(&mut new_writer, &Commands::Set(set_cmd))?;
+                index.insert(key, pos_len_pair);
+            }
+        }
+
+        let writer = self.writer.clone();
+        let mut writer = writer.lock().unwrap();
+        let mut reader = BufReader::new(File::open(&cur_log_path)?);
+        reader.seek(SeekFrom::Start(copied_until))?;
+        // removals are copied too, or the records copied above would revive the keys
+        for cmd in serde_json::Deserializer::from_reader(reader).into_iter::<Commands>() {
+            let cmd = cmd?;
+            let pos_len_pair = append_cmd(&mut new_writer, &cmd)?;
             match cmd {
                 Commands::Set(set_cmd) => {
-                    let pos = new_writer.seek(SeekFrom::End(0))?;
-                    let key = set_cmd.key.clone();
-                    new_writer
-                        .write_all(&serde_json::to_string(&Commands::Set(set_cmd))?.into_bytes())?;
-                    new_writer.flush()?;
-                    let next_pos = new_writer.stream_position()?;
-                    let pos_len_pair = (pos, next_pos - pos);
-                    self.entrypoints.insert(key, pos_len_pair);
+                    index.insert(set_cmd.key, pos_len_pair);
+                }
+                Commands::Remove(rm_cmd) => {
+                    index.remove(&rm_cmd.key);
                 }
-                _ => {}
             }
         }
+        new_writer.get_ref().sync_data()?;
+        for (key, pos_len_pair) in index {
+            self.entrypoints.insert(key, pos_len_pair);
+        }
 
         // update meta
+        let expired: Vec<LogGeneration> = {
+            let mut history = self.meta.history.lock().unwrap();
+            history.push(LogGeneration {
+                version: new_version,
+                first_seq: self.meta.last_seq.load(Ordering::SeqCst) + 1,
+            });
+            let retained = self.meta.retained.load(Ordering::SeqCst) as usize;
+            let expired = history.len().saturating_sub(retained + 1);
+            history.drain(..expired).collect()
+        };
         self.meta.version.fetch_add(1, Ordering::SeqCst);
         self.meta.uncompact_size.store(0, Ordering::SeqCst);
+        self.write_meta()?;
+        writer.check_writer_version()?;
+        drop(writer);
 
-        self.meta_writer.seek(SeekFrom::Start(0))?;
-        self.meta_writer
-            .write_all(&serde_json::to_string(&self.meta.clone_to_plain_meta())?.into_bytes())?;
-        self.meta_writer.flush()?;
+        // delete old logs past the retained history
+        for generation in expired {
+            fs::remove_file(get_db_path(&self.meta.db_dir, generation.version))?;
+        }
 
-        // delete old log
-        fs::remove_file(&old_log_path)?;
+        self.meta.compactions.fetch_add(1, Ordering::Relaxed);
+        self.meta
+            .compaction_micros
+            .fetch_add(start.elapsed().as_micros() as u64, Ordering::Relaxed);
 
         Ok(())
     }
 }
 
+// Append `cmd` to a log, returns where it went
+fn append_cmd(writer: &mut BufWriter<File>, cmd: &Commands) -> Result<(u64, u64)> {
+    let pos = writer.seek(SeekFrom::End(0))?;
+    writer.write_all(&serde_json::to_string(cmd)?.into_bytes())?;
+    writer.flush()?;
+    let next_pos = writer.stream_position()?;
+    Ok((pos, next_pos - pos))
+}
+
 #[derive(Debug)]
 struct KvStoreMeta {
     uncompact_size: AtomicU64,
     db_dir: String,
     version: AtomicU64,
+    // oldest first, the last one is the current log
+    history: Mutex<Vec<LogGeneration>>,
+    last_seq: AtomicU64,
+    // compacted logs kept for the change feed
+    retained: AtomicU64,
+    compactions: AtomicU64,
+    compaction_micros: AtomicU64,
+    // a compaction was handed to `maintain`, the writers leave it to it
+    maintenance: AtomicBool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct LogGeneration {
+    version: u64,
+    first_seq: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -256,14 +426,31 @@ struct KvMeta {
     uncompact_size: u64,
     db_dir: String,
     version: u64,
+    #[serde(default)]
+    history: Vec<LogGeneration>,
+    #[serde(default)]
+    retained: u64,
 }
 
 impl From<KvMeta> for KvStoreMeta {
     fn from(meta: KvMeta) -> Self {
+        let mut history = meta.history;
+        if history.is_empty() {
+            history.push(LogGeneration {
+                version: meta.version,
+                first_seq: 1,
+            });
+        }
         KvStoreMeta {
             uncompact_size: AtomicU64::new(meta.uncompact_size),
             db_dir: meta.db_dir,
             version: AtomicU64::new(meta.version),
+            history: Mutex::new(history),
+            last_seq: AtomicU64::new(0),
+            retained: AtomicU64::new(meta.retained),
+            compactions: AtomicU64::new(0),
+            compaction_micros: AtomicU64::new(0),
+            maintenance: AtomicBool::new(false),
         }
     }
 }
@@ -274,12 +461,16 @@ impl KvStoreMeta {
             uncompact_size: self.uncompact_size.load(Ordering::Relaxed),
             db_dir: self.db_dir.clone(),
             version: self.version.load(Ordering::Relaxed),
+            history: self.history.lock().unwrap().clone(),
+            retained: self.retained.load(Ordering::Relaxed),
         }
     }
 }
 
-fn build_entrypoints<P: AsRef<Path>>(path: P) -> Result<KvStoreEntryPoints> {
+// Also returns the highest sequence found in the log
+fn build_entrypoints<P: AsRef<Path>>(path: P) -> Result<(KvStoreEntryPoints, u64)> {
     let entrypoints: KvStoreEntryPoints = SkipMap::new();
+    let mut last_seq = 0;
     let mut cur_pos = 0;
     let mut reader = BufReader::new(File::open(&path)?);
     reader.seek(SeekFrom::Start(0))?;
@@ -287,25 +478,31 @@ fn build_entrypoints<P: AsRef<Path>>(path: P) -> Result<KvStoreEntryPoints> {
     while let Some(cmd) = stream.next() {
         let next_pos = stream.byte_offset() as u64;
         let len = next_pos - cur_pos;
-        let k = match cmd? {
-            Commands::Set(set_cmd) => set_cmd.key,
-            Commands::Remove(rm_cmd) => rm_cmd.key,
-        };
-        entrypoints.insert(k, (cur_pos, len));
+        let cmd = cmd?;
+        last_seq = last_seq.max(cmd.seq());
+        // removed keys leave the index, as they do on a live remove
+        match cmd {
+            Commands::Set(set_cmd) => {
+                entrypoints.insert(set_cmd.key, (cur_pos, len));
+            }
+            Commands::Remove(rm_cmd) => {
+                entrypoints.remove(&rm_cmd.key);
+            }
+        }
         cur_pos = next_pos;
     }
-    Ok(entrypoints)
+    Ok((entrypoints, last_seq))
 }
 
 fn get_db_path<P: AsRef<Path>>(path: P, version: u64) -> String {
-    let path = path.as_ref().clone();
+    let path = path.as_ref();
     path.join(format!("kv.{}.log", version))
         .to_str()
         .expect("invalid db path")
         .to_owned()
 }
 fn get_meta_path<P: AsRef<Path>>(path: P) -> String {
-    let path = path.as_ref().clone();
+    let path = path.as_ref();
     path.join("kv.meta")
         .to_str()
         .expect("invalid db path")
@@ -322,12 +519,15 @@ fn read_meta(path: &Path) -> Result<KvMeta> {
                 let file = OpenOptions::new()
                     .create(true)
                     .write(true)
+                    .truncate(true)
                     .open(&meta_path)?;
                 let mut writer = BufWriter::new(file);
                 let meta = KvMeta {
                     uncompact_size: 0,
                     db_dir: dir.to_str().expect("read meta error").to_owned(),
                     version,
+                    history: vec![],
+                    retained: 0,
                 };
                 writer.write_all(&serde_json::to_string(&meta)?.into_bytes())?;
                 writer.flush()?;
@@ -336,9 +536,6 @@ fn read_meta(path: &Path) -> Result<KvMeta> {
             _ => Err(e.into()),
         },
         Ok(_) => {
-            let mut reader = BufReader::new(File::open(&meta_path)?);
-            let mut s = String::new();
-            let len = reader.read_to_string(&mut s);
             let reader = BufReader::new(File::open(&meta_path)?);
             let meta: KvMeta = serde_json::from_reader(reader)?;
             Ok(meta)
@@ -350,7 +547,7 @@ impl KvStore {
     pub fn open(path: &Path) -> Result<Self> {
         let dir = Path::new(path);
         if dir.is_dir() {
-            let meta = read_meta(&dir)?;
+            let meta = read_meta(dir)?;
             let meta_path = get_meta_path(path);
             let db_path = get_db_path(path, meta.version);
             let meta = KvStoreMeta::from(meta);
@@ -362,21 +559,34 @@ impl KvStore {
                 .open(&db_path)?;
             let kv_store_meta = Arc::new(meta);
             let kv_store_reader = KvStoreReader::new(&db_path, kv_store_meta.clone())?;
-            let kv_store_entrypoints = Arc::new(build_entrypoints(&db_path)?);
-            let kv_store_writer = KvStoreWriter::new(
+            let (kv_store_entrypoints, last_seq) = build_entrypoints(&db_path)?;
+            let kv_store_entrypoints = Arc::new(kv_store_entrypoints);
+            // a compaction may have left the current log without live records
+            let first_seq = kv_store_meta
+                .history
+                .lock()
+                .unwrap()
+                .last()
+                .unwrap()
+                .first_seq;
+            kv_store_meta
+                .last_seq
+                .store(last_seq.max(first_seq - 1), Ordering::SeqCst);
+            let kv_store_writer = Arc::new(Mutex::new(KvStoreWriter::new(
                 &db_path,
                 kv_store_meta.clone(),
                 kv_store_entrypoints.clone(),
-            )?;
+            )?));
             let kv_store_compactor = KvStoreCompactor::new(
                 &meta_path,
                 kv_store_entrypoints.clone(),
                 kv_store_reader.clone(),
+                kv_store_writer.clone(),
                 kv_store_meta.clone(),
             )?;
 
             let store = KvStore {
-                writer: Arc::new(Mutex::new(kv_store_writer)),
+                writer: kv_store_writer,
                 reader: kv_store_reader,
                 compactor: Arc::new(Mutex::new(kv_store_compactor)),
                 entrypoints: kv_store_entrypoints,
@@ -386,21 +596,75 @@ impl KvStore {
         }
         Err(KvStoreError::PathInvalid)
     }
+
+    /// Keep this many compacted logs around, so `changes` can still be served
+    /// to consumers that fall behind a compaction. Saved with the store, so it
+    /// holds across reopens until changed
+    pub fn retain_logs(self, logs: usize) -> Result<Self> {
+        self.meta.retained.store(logs as u64, Ordering::SeqCst);
+        self.compactor.lock().unwrap().write_meta()?;
+        Ok(self)
+    }
+
+    /// Compacted logs kept for the change feed, see `retain_logs`
+    pub fn retained_logs(&self) -> usize {
+        self.meta.retained.load(Ordering::SeqCst) as usize
+    }
+
+    /// Sequence number of the latest write, 0 for none
+    pub fn last_seq(&self) -> u64 {
+        self.meta.last_seq.load(Ordering::SeqCst)
+    }
+
+    /// Changes with a sequence number above `since`, fails if some of them
+    /// were compacted away
+    pub fn changes(&self, since: u64) -> Result<ChangeFeed> {
+        // the lock keeps a compaction from deleting the logs until they are opened
+        let history = self.meta.history.lock().unwrap();
+        let oldest = history[0].first_seq;
+        if since + 1 < oldest {
+            return Err(KvStoreError::ChangesCompacted(oldest));
+        }
+        let mut logs = VecDeque::new();
+        for (i, generation) in history.iter().enumerate() {
+            let end = history.get(i + 1).map(|next| next.first_seq);
+            if end.is_some_and(|end| end <= since + 1) {
+                continue;
+            }
+            let path = get_db_path(&self.meta.db_dir, generation.version);
+            let reader = BufReader::new(File::open(&path)?);
+            let stream = serde_json::Deserializer::from_reader(reader).into_iter();
+            logs.push_back((generation.first_seq, stream));
+        }
+        Ok(ChangeFeed { logs, since })
+    }
+
+    // Compact on the request path, unless a background compaction is on its way
+    fn compact_inline(&self) -> Result<()> {
+        let threshold = if self.meta.maintenance.load(Ordering::SeqCst) {
+            MAINTENANCE_LIMIT
+        } else {
+            COMPACTION_POINT
+        };
+        if self.meta.uncompact_size.load(Ordering::Relaxed) >= threshold {
+            self.compactor.lock().unwrap().compact(threshold)?;
+        }
+        Ok(())
+    }
 }
 
 impl KvsEngine for KvStore {
     /// Set a k-v pair
     fn set(&self, key: String, value: String) -> Result<()> {
-        if self.meta.uncompact_size.load(Ordering::Relaxed) >= COMPACTION_POINT {
-            self.compactor.lock().unwrap().compact()?;
-        }
+        self.compact_inline()?;
 
         let cmd = Commands::Set(SetCommand {
             key: key.clone(),
             value,
+            seq: 0,
         });
 
-        self.writer.lock().unwrap().write_cmd(&cmd)
+        self.writer.lock().unwrap().write_cmd(cmd)
     }
 
     /// Get value of key
@@ -421,15 +685,67 @@ impl KvsEngine for KvStore {
 
     /// Remove a key
     fn remove(&self, key: String) -> Result<()> {
-        if self.meta.uncompact_size.load(Ordering::Relaxed) >= COMPACTION_POINT {
-            self.compactor.lock().unwrap().compact()?;
-        }
+        self.compact_inline()?;
+        // checked under the lock, a concurrent remove of the key goes first
+        let mut writer = self.writer.lock().unwrap();
         match self.entrypoints.get(&key) {
             Some(_) => {
-                let cmd = Commands::Remove(RemoveCommand { key: key.clone() });
-                self.writer.lock().unwrap().write_cmd(&cmd)
+                let cmd = Commands::Remove(RemoveCommand {
+                    key: key.clone(),
+                    seq: 0,
+                });
+                writer.write_cmd(cmd)
             }
             None => Err(KvStoreError::KeyNotFound),
         }
     }
+
+    /// Collect pairs by prefix, the index is ordered so this stops at the first miss
+    fn scan(&self, prefix: String) -> Result<Vec<(String, String)>> {
+        let mut pairs = vec![];
+        for entry in self.entrypoints.range(prefix.clone()..) {
+            if !entry.key().starts_with(&prefix) {
+                break;
+            }
+            let (pos, len) = *entry.value();
+            if let Commands::Set(cmd) = self.reader.read_cmd(pos, len)? {
+                pairs.push((cmd.key, cmd.value));
+            }
+        }
+        Ok(pairs)
+    }
+
+    /// Dead bytes are the part of the current log not referenced by the index
+    fn stats(&self) -> Result<EngineStats> {
+        let live_bytes: u64 = self.entrypoints.iter().map(|e| e.value().1).sum();
+        // the lock keeps a compaction from deleting the logs while they are measured
+        let history = self.meta.history.lock().unwrap();
+        let mut disk_bytes = fs::metadata(get_meta_path(&self.meta.db_dir))?.len();
+        let mut log_bytes = 0;
+        for generation in history.iter() {
+            log_bytes = fs::metadata(get_db_path(&self.meta.db_dir, generation.version))?.len();
+            disk_bytes += log_bytes;
+        }
+        Ok(EngineStats {
+            engine: "kvs".to_owned(),
+            keys: self.entrypoints.len() as u64,
+            disk_bytes,
+            dead_bytes: log_bytes.saturating_sub(live_bytes),
+            compactions: self.meta.compactions.load(Ordering::Relaxed),
+            compaction_secs: self.meta.compaction_micros.load(Ordering::Relaxed) as f64 / 1e6,
+        })
+    }
+
+    /// Due once per compaction, the writers then leave it to `maintain`
+    fn maintenance_due(&self) -> bool {
+        self.meta.uncompact_size.load(Ordering::Relaxed) >= COMPACTION_POINT
+            && !self.meta.maintenance.swap(true, Ordering::SeqCst)
+    }
+
+    /// Compact at the size the writers would, off the request path
+    fn maintain(&self) -> Result<()> {
+        let compacted = self.compactor.lock().unwrap().compact(COMPACTION_POINT);
+        self.meta.maintenance.store(false, Ordering::SeqCst);
+        compacted
+    }
 }
@@ -1,5 +1,22 @@
+use serde::{Deserialize, Serialize};
+
 mod kvs;
 mod sled;
 
-pub use self::kvs::KvStore;
+pub use self::kvs::{Change, ChangeFeed, KvStore};
 pub use self::sled::SledKvsEngine;
+
+/// Engine statistics, numbers an engine can't tell are 0
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct EngineStats {
+    // as given to `kvs-server --engine`
+    pub engine: String,
+    pub keys: u64,
+    // everything the engine keeps on disk
+    pub disk_bytes: u64,
+    // overwritten and removed records a compaction would drop
+    pub dead_bytes: u64,
+    // since the engine was opened
+    pub compactions: u64,
+    pub compaction_secs: f64,
+}
@@ -1,5 +1,5 @@
 use crate::error::{KvStoreError, Result};
-use crate::KvsEngine;
+use crate::{EngineStats, KvsEngine};
 use sled::Db;
 use std::path::Path;
 
@@ -10,7 +10,7 @@ pub struct SledKvsEngine {
 
 impl SledKvsEngine {
     pub fn open(p: &Path) -> Result<Self> {
-        let tree = Db::start_default(p)?;
+        let tree = sled::open(p)?;
         let sledkv = SledKvsEngine { tree };
         Ok(sledkv)
     }
@@ -18,7 +18,7 @@ impl SledKvsEngine {
 
 impl KvsEngine for SledKvsEngine {
     fn set(&self, key: String, value: String) -> Result<()> {
-        self.tree.set(key, value.as_bytes())?;
+        self.tree.insert(key, value.as_bytes())?;
         self.tree.flush()?;
         Ok(())
     }
@@ -33,11 +33,29 @@ impl KvsEngine for SledKvsEngine {
         }
     }
     fn remove(&self, key: String) -> Result<()> {
-        let res = self.tree.del(key)?;
+        let res = self.tree.remove(key)?;
         self.tree.flush()?;
         match res {
             Some(_) => Ok(()),
             None => Err(KvStoreError::KeyNotFound),
         }
     }
+    fn scan(&self, prefix: String) -> Result<Vec<(String, String)>> {
+        let mut pairs = vec![];
+        for pair in self.tree.scan_prefix(prefix) {
+            let (k, v) = pair?;
+            let k = String::from_utf8(Vec::from(k.as_ref())).expect("utf8 error");
+            let v = String::from_utf8(Vec::from(v.as_ref())).expect("utf8 error");
+            pairs.push((k, v));
+        }
+        Ok(pairs)
+    }
+    fn stats(&self) -> Result<EngineStats> {
+        Ok(EngineStats {
+            engine: "sled".to_owned(),
+            keys: self.tree.len() as u64,
+            disk_bytes: self.tree.size_on_disk()?,
+            ..EngineStats::default()
+        })
+    }
 }
@@ -1,3 +1,6 @@
+// failure's derive implements `Fail` from inside a const
+#![allow(non_local_definitions)]
+
 use rayon;
 use sled;
 use std::io;
@@ -18,8 +21,39 @@ pub enum KvStoreError {
     Sled(#[cause] sled::Error),
     #[fail(display = "{}", _0)]
     Rpc(String),
+    #[fail(display = "Server storage error: {}", _0)]
+    RemoteIo(String),
+    #[fail(
+        display = "Incompatible protocol, server speaks version {} to {}",
+        _0, _1
+    )]
+    Incompatible(u32, u32),
+    #[fail(display = "Request timed out")]
+    Timeout,
+    #[fail(display = "Invalid request: {}", _0)]
+    InvalidRequest(String),
+    #[fail(display = "{}", _0)]
+    Unauthorized(String),
+    #[fail(display = "{}", _0)]
+    PermissionDenied(String),
+    #[fail(display = "{}", _0)]
+    ReadOnly(String),
+    #[fail(display = "{}", _0)]
+    NotLeader(String),
+    #[fail(display = "Changes before sequence {} were compacted away", _0)]
+    ChangesCompacted(u64),
+    #[fail(display = "{}", _0)]
+    Busy(String),
+    #[fail(display = "Thread pool is shut down")]
+    PoolShutdown,
+    #[fail(display = "Job panicked: {}", _0)]
+    JobPanicked(String),
+    #[fail(display = "Job dropped before it ran")]
+    JobCancelled,
     #[fail(display = "{}", _0)]
     Rayon(#[cause] rayon::ThreadPoolBuildError),
+    #[fail(display = "{}", _0)]
+    Tls(#[cause] rustls::TLSError),
 }
 
 impl From<io::Error> for KvStoreError {
@@ -46,4 +80,10 @@ impl From<rayon::ThreadPoolBuildError> for KvStoreError {
     }
 }
 
+impl From<rustls::TLSError> for KvStoreError {
+    fn from(error: rustls::TLSError) -> Self {
+        KvStoreError::Tls(error)
+    }
+}
+
 pub type Result<T> = std::result::Result<T, KvStoreError>;
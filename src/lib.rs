@@ -1,6 +1,5 @@
 // #![deny(missing_docs)]
 //! kvs implement an in memory k-v store
-#![feature(seek_convenience)]
 
 #[macro_use]
 extern crate failure;
@@ -8,10 +7,11 @@ extern crate failure;
 pub mod engine;
 pub mod error;
 pub mod network;
+pub mod raft;
 pub mod thread_pool;
 
 pub use crate::error::{KvStoreError, Result};
-pub use engine::{KvStore, SledKvsEngine};
+pub use engine::{Change, ChangeFeed, EngineStats, KvStore, SledKvsEngine};
 
 pub trait KvsEngine: Clone + Send + 'static {
     fn set(&self, key: String, value: String) -> Result<()>;
@@ -19,4 +19,21 @@ pub trait KvsEngine: Clone + Send + 'static {
     fn get(&self, key: String) -> Result<Option<String>>;
 
     fn remove(&self, key: String) -> Result<()>;
+
+    /// Every pair whose key starts with `prefix`, sorted by key
+    fn scan(&self, prefix: String) -> Result<Vec<(String, String)>>;
+
+    /// Size of the data set and what it takes on disk
+    fn stats(&self) -> Result<EngineStats>;
+
+    /// Whether `maintain` has work to do, cheap enough to ask after every write.
+    /// Once it said so, the engine may count on `maintain` being run
+    fn maintenance_due(&self) -> bool {
+        false
+    }
+
+    /// Upkeep such as compaction, which servers run in the background at low priority
+    fn maintain(&self) -> Result<()> {
+        Ok(())
+    }
 }
@@ -0,0 +1,129 @@
+use crate::network::{
+    decode, Credentials, Feature, HandshakeRequest, HandshakeResp, ServerInfo,
+    SessionClientCommand, SessionServerResp,
+};
+use crate::{KvStoreError, Result};
+use std::io;
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Tokio flavor of `KvsClient`, speaking the same wire protocol
+pub struct AsyncKvsClient {
+    buf: Vec<u8>,
+    stream: TcpStream,
+    request: HandshakeRequest,
+    negotiated: Option<HandshakeResp>,
+}
+
+impl AsyncKvsClient {
+    pub async fn new(addr: SocketAddr) -> Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        Ok(AsyncKvsClient {
+            buf: vec![],
+            stream,
+            request: HandshakeRequest::default(),
+            negotiated: None,
+        })
+    }
+
+    // Ask for optional protocol features in the handshake
+    pub fn with_features(mut self, features: Vec<Feature>) -> Self {
+        self.request.features = features;
+        self
+    }
+
+    // Log in during the handshake, required by servers with authentication
+    pub fn with_credentials(mut self, user: &str, password: &str) -> Self {
+        self.request.credentials = Some(Credentials {
+            user: user.to_owned(),
+            password: password.to_owned(),
+        });
+        self
+    }
+
+    // What the server agreed on, None before the handshake
+    pub fn negotiated(&self) -> Option<&HandshakeResp> {
+        self.negotiated.as_ref()
+    }
+
+    pub async fn handshake(&mut self) -> Result<()> {
+        if self.negotiated.is_some() {
+            return Ok(());
+        }
+
+        let handshake = SessionClientCommand::Handshake(self.request.clone());
+        match self.cmd(&handshake).await? {
+            SessionServerResp::Handshake(resp) => {
+                self.negotiated = Some(resp);
+                Ok(())
+            }
+            resp => Err(resp.into_error()),
+        }
+    }
+
+    pub async fn cmd(&mut self, cmd: &SessionClientCommand) -> Result<SessionServerResp> {
+        self.stream
+            .write_all(serde_json::to_string(cmd)?.as_bytes())
+            .await?;
+        let mut chunk = [0u8; 1024];
+        loop {
+            if let Some(resp) = decode(&mut self.buf)? {
+                return Ok(resp);
+            }
+            let len = self.stream.read(&mut chunk).await?;
+            if len == 0 {
+                return Err(KvStoreError::Io(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed by server",
+                )));
+            }
+            self.buf.extend_from_slice(&chunk[..len]);
+        }
+    }
+
+    pub async fn set(&mut self, k: String, v: String) -> Result<()> {
+        let cmd = SessionClientCommand::Set(k, v);
+        match self.cmd(&cmd).await? {
+            SessionServerResp::OK => Ok(()),
+            resp => Err(resp.into_error()),
+        }
+    }
+
+    pub async fn get(&mut self, k: String) -> Result<Option<String>> {
+        let cmd = SessionClientCommand::Get(k);
+        match self.cmd(&cmd).await? {
+            SessionServerResp::Value(v) => Ok(Some(v)),
+            SessionServerResp::NotFound => Ok(None),
+            resp => Err(resp.into_error()),
+        }
+    }
+
+    pub async fn remove(&mut self, k: String) -> Result<()> {
+        let cmd = SessionClientCommand::Remove(k);
+        match self.cmd(&cmd).await? {
+            SessionServerResp::OK => Ok(()),
+            resp => Err(resp.into_error()),
+        }
+    }
+
+    pub async fn scan(&mut self, prefix: String) -> Result<Vec<(String, String)>> {
+        match self.cmd(&SessionClientCommand::Scan(prefix)).await? {
+            SessionServerResp::Pairs(pairs) => Ok(pairs),
+            resp => Err(resp.into_error()),
+        }
+    }
+
+    pub async fn info(&mut self) -> Result<ServerInfo> {
+        match self.cmd(&SessionClientCommand::Info).await? {
+            SessionServerResp::Info(info) => Ok(*info),
+            resp => Err(resp.into_error()),
+        }
+    }
+
+    pub async fn quit(&mut self) -> Result<()> {
+        let cmd = SessionClientCommand::Quit;
+        self.cmd(&cmd).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use crate::network::{
+    decode_cmd, Authenticator, Replication, ServerContext, SessionClientCommand, SessionProcessor,
+};
+use crate::{KvStoreError, KvsEngine, Result};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task;
+
+/// Tokio flavor of `KvsServer`, engine calls run on the blocking
+/// thread pool of the runtime so they never stall the reactor
+pub struct AsyncKvsServer<E: KvsEngine> {
+    store: E,
+    ctx: ServerContext,
+}
+
+impl<E: KvsEngine> AsyncKvsServer<E> {
+    pub fn new(store: E) -> Self {
+        AsyncKvsServer {
+            store,
+            ctx: ServerContext::default(),
+        }
+    }
+
+    pub fn auth(mut self, auth: Authenticator) -> Self {
+        self.ctx.auth = Some(Arc::new(auth));
+        self
+    }
+    // Share the replication role, e.g. with a `Follower`
+    pub fn replication(mut self, replication: Arc<Replication>) -> Self {
+        self.ctx.replication = replication;
+        self
+    }
+
+    pub async fn listen(self, addr: SocketAddr) -> Result<()> {
+        let mut listener = TcpListener::bind(addr).await?;
+        loop {
+            let (stream, _) = listener.accept().await?;
+            self.serve(stream);
+        }
+    }
+
+    /// Serve until `shutdown` resolves
+    pub async fn listen_until<F>(self, addr: SocketAddr, shutdown: F) -> Result<()>
+    where
+        F: Future<Output = ()>,
+    {
+        let mut listener = TcpListener::bind(addr).await?;
+        tokio::pin!(shutdown);
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => break,
+                accepted = listener.accept() => {
+                    let (stream, _) = accepted?;
+                    self.serve(stream);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn serve(&self, stream: TcpStream) {
+        let store = self.store.clone();
+        let ctx = self.ctx.clone();
+        tokio::spawn(async move {
+            // a broken session only affects its own client
+            let _ = handle(stream, store, ctx).await;
+        });
+    }
+}
+
+pub async fn handle<E: KvsEngine>(
+    mut stream: TcpStream,
+    store: E,
+    ctx: ServerContext,
+) -> Result<()> {
+    let mut processor = SessionProcessor::with_context(store, ctx);
+    let mut buf = vec![];
+    let mut chunk = [0u8; 1024];
+    while !processor.should_quit() {
+        let cmd: SessionClientCommand = match decode_cmd(&mut buf)? {
+            Some(cmd) => cmd,
+            None => {
+                let len = stream.read(&mut chunk).await?;
+                if len == 0 {
+                    break;
+                }
+                buf.extend_from_slice(&chunk[..len]);
+                continue;
+            }
+        };
+        let (name, start) = (cmd.name(), Instant::now());
+        let resp = if cmd.is_engine_op() {
+            let (p, resp) = task::spawn_blocking(move || {
+                let resp = processor.process(cmd);
+                (processor, resp)
+            })
+            .await
+            .map_err(|e| KvStoreError::Io(io::Error::other(format!("{}", e))))?;
+            processor = p;
+            resp
+        } else {
+            processor.process(cmd)
+        };
+        processor.record(name, start, resp.is_error());
+        stream
+            .write_all(&serde_json::to_string(&resp)?.into_bytes())
+            .await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,140 @@
+use crate::Result;
+use hmac::Hmac;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use subtle::ConstantTimeEq;
+
+// Sent in the handshake, only safe over TLS
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Credentials {
+    pub user: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    ReadWrite,
+}
+
+// Grants `access` to every key starting with `prefix`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AclRule {
+    pub prefix: String,
+    pub access: Access,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct User {
+    pub name: String,
+    pub salt: String,
+    // `pbkdf2-sha256$<rounds>$<hex encoded key>`, see `hash_password`
+    pub password_hash: String,
+    pub rules: Vec<AclRule>,
+}
+
+/// Users and their ACL rules, loaded from a json file like
+///
+/// ```json
+/// { "users": [ { "name": "app", "salt": "x1", "password_hash": "...",
+///                "rules": [ { "prefix": "app/", "access": "ReadWrite" } ] } ] }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Authenticator {
+    pub users: Vec<User>,
+}
+
+impl Authenticator {
+    pub fn load(path: &Path) -> Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn authenticate(&self, credentials: &Credentials) -> Option<User> {
+        let user = match self.users.iter().find(|u| u.name == credentials.user) {
+            Some(user) => user,
+            None => {
+                // as slow as a wrong password, the timing doesn't tell which users exist
+                let dummy = format!("{}${}$", HASH_SCHEME, HASH_ROUNDS);
+                verify_password(&dummy, "", &credentials.password);
+                return None;
+            }
+        };
+        if verify_password(&user.password_hash, &user.salt, &credentials.password) {
+            Some(user.clone())
+        } else {
+            None
+        }
+    }
+}
+
+impl User {
+    pub fn new(name: &str, salt: &str, password: &str, rules: Vec<AclRule>) -> Self {
+        User {
+            name: name.to_owned(),
+            salt: salt.to_owned(),
+            password_hash: hash_password(salt, password),
+            rules,
+        }
+    }
+
+    // The longest matching prefix decides, no match means no access
+    pub fn allows(&self, key: &str, write: bool) -> bool {
+        let rule = self
+            .rules
+            .iter()
+            .filter(|r| key.starts_with(&r.prefix))
+            .max_by_key(|r| r.prefix.len());
+        match rule {
+            Some(rule) => !write || rule.access == Access::ReadWrite,
+            None => false,
+        }
+    }
+}
+
+const HASH_SCHEME: &str = "pbkdf2-sha256";
+// PBKDF2 rounds of new hashes, stored hashes keep the count they were made with
+const HASH_ROUNDS: u32 = 100_000;
+
+// PBKDF2-HMAC-SHA256 of the password, salted
+pub fn hash_password(salt: &str, password: &str) -> String {
+    let key = derive_key(salt, password, HASH_ROUNDS);
+    format!("{}${}${}", HASH_SCHEME, HASH_ROUNDS, to_hex(&key))
+}
+
+// Compares in constant time, a malformed hash matches nothing
+fn verify_password(hash: &str, salt: &str, password: &str) -> bool {
+    let mut parts = hash.splitn(3, '$');
+    let rounds = match (parts.next(), parts.next(), parts.next()) {
+        (Some(HASH_SCHEME), Some(rounds), Some(_)) => match rounds.parse() {
+            Ok(rounds) if rounds > 0 => rounds,
+            _ => return false,
+        },
+        _ => return false,
+    };
+    let expected = format!(
+        "{}${}${}",
+        HASH_SCHEME,
+        rounds,
+        to_hex(&derive_key(salt, password, rounds))
+    );
+    expected.as_bytes().ct_eq(hash.as_bytes()).into()
+}
+
+fn derive_key(salt: &str, password: &str, rounds: u32) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(
+        password.as_bytes(),
+        salt.as_bytes(),
+        rounds as usize,
+        &mut key,
+    );
+    key
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
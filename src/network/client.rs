@@ -1,50 +1,138 @@
-use crate::network::{SessionClientCommand, SessionServerResp};
+use crate::network::{
+    decode, Credentials, Feature, HandshakeRequest, HandshakeResp, KvsStream, ServerInfo,
+    SessionClientCommand, SessionServerResp, WatchFilter, Watcher,
+};
 use crate::{KvStoreError, Result};
-use std::io::{Read, Write};
+use rustls::ClientConfig;
+use std::io::{self, Read, Write};
 use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
 
 pub struct KvsClient {
-    buf: Vec<u8>,
-    stream: TcpStream,
-    ready: bool,
+    // bytes of a response not complete yet
+    rbuf: Vec<u8>,
+    stream: KvsStream,
+    request: HandshakeRequest,
+    negotiated: Option<HandshakeResp>,
 }
 
 impl KvsClient {
     pub fn new(addr: SocketAddr) -> Result<Self> {
         let stream = TcpStream::connect(addr)?;
-        Ok(KvsClient {
-            buf: vec![0u8; 1024],
+        Ok(KvsClient::from_stream(KvsStream::Plain(stream)))
+    }
+
+    // `domain` is the name the server certificate is checked against
+    pub fn new_tls(addr: SocketAddr, config: &Arc<ClientConfig>, domain: &str) -> Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        Ok(KvsClient::from_stream(KvsStream::client_tls(
+            stream, config, domain,
+        )?))
+    }
+
+    fn from_stream(stream: KvsStream) -> Self {
+        KvsClient {
+            rbuf: vec![],
             stream,
-            ready: false,
-        })
+            request: HandshakeRequest::default(),
+            negotiated: None,
+        }
+    }
+
+    // Ask for optional protocol features in the handshake
+    pub fn with_features(mut self, features: Vec<Feature>) -> Self {
+        self.request.features = features;
+        self
+    }
+
+    // Log in during the handshake, required by servers with authentication
+    pub fn with_credentials(mut self, user: &str, password: &str) -> Self {
+        self.request.credentials = Some(Credentials {
+            user: user.to_owned(),
+            password: password.to_owned(),
+        });
+        self
+    }
+
+    // What the server agreed on, None before the handshake
+    pub fn negotiated(&self) -> Option<&HandshakeResp> {
+        self.negotiated.as_ref()
+    }
+
+    // Check the connection is still open without blocking,
+    // a closed peer reads as EOF
+    pub fn is_alive(&self) -> bool {
+        let tcp = self.stream.tcp();
+        if tcp.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let mut buf = [0u8; 1];
+        let alive = match tcp.peek(&mut buf) {
+            Ok(0) => false,
+            // unexpected data, the stream is out of sync
+            Ok(_) => false,
+            Err(ref e) => e.kind() == io::ErrorKind::WouldBlock,
+        };
+        alive && tcp.set_nonblocking(false).is_ok()
     }
 
     pub fn handshake(&mut self) -> Result<()> {
-        if self.ready {
+        if self.negotiated.is_some() {
             return Ok(());
         }
 
-        let handshake = SessionClientCommand::Handshake;
-        self.cmd(&handshake)?;
-        self.ready = true;
+        let handshake = SessionClientCommand::Handshake(self.request.clone());
+        match self.cmd(&handshake)? {
+            SessionServerResp::Handshake(resp) => {
+                self.negotiated = Some(resp);
+                Ok(())
+            }
+            resp => Err(resp.into_error()),
+        }
+    }
 
+    // None waits forever
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.stream.tcp().set_read_timeout(timeout)?;
+        self.stream.tcp().set_write_timeout(timeout)?;
         Ok(())
     }
 
     pub fn cmd(&mut self, cmd: &SessionClientCommand) -> Result<SessionServerResp> {
+        self.send(cmd)?;
+        self.recv()
+    }
+
+    pub(crate) fn send(&mut self, cmd: &SessionClientCommand) -> Result<()> {
         self.stream
-            .write_all(&serde_json::to_string(cmd)?.as_bytes())?;
-        let len = self.stream.read(&mut self.buf)?;
-        let resp: SessionServerResp = serde_json::from_slice(&self.buf[..len])?;
-        Ok(resp)
+            .write_all(serde_json::to_string(cmd)?.as_bytes())
+            .map_err(timeout_error)
+    }
+
+    // Wait for the next complete response, a partial one survives a timeout
+    pub(crate) fn recv(&mut self) -> Result<SessionServerResp> {
+        let mut chunk = [0u8; 1024];
+        loop {
+            if let Some(resp) = decode(&mut self.rbuf)? {
+                return Ok(resp);
+            }
+            let len = self.stream.read(&mut chunk).map_err(timeout_error)?;
+            if len == 0 {
+                return Err(KvStoreError::Io(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed by server",
+                )));
+            }
+            self.rbuf.extend_from_slice(&chunk[..len]);
+        }
     }
 
     pub fn set(&mut self, k: String, v: String) -> Result<()> {
         let cmd = SessionClientCommand::Set(k, v);
         match self.cmd(&cmd)? {
             SessionServerResp::OK => Ok(()),
-            SessionServerResp::ERR(e) => Err(KvStoreError::Rpc(e)),
-            _ => Err(KvStoreError::Rpc("unnkown error".to_owned())),
+            resp => Err(resp.into_error()),
         }
     }
 
@@ -54,8 +142,7 @@ impl KvsClient {
         match resp {
             SessionServerResp::Value(v) => Ok(Some(v)),
             SessionServerResp::NotFound => Ok(None),
-            SessionServerResp::ERR(e) => Err(KvStoreError::Rpc(e)),
-            _ => Err(KvStoreError::Rpc("unnkown error".to_owned())),
+            resp => Err(resp.into_error()),
         }
     }
     pub fn remove(&mut self, k: String) -> Result<()> {
@@ -63,8 +150,36 @@ impl KvsClient {
         let resp = self.cmd(&cmd)?;
         match resp {
             SessionServerResp::OK => Ok(()),
-            SessionServerResp::ERR(e) => Err(KvStoreError::Rpc(e)),
-            _ => Err(KvStoreError::Rpc("unnkown error".to_owned())),
+            resp => Err(resp.into_error()),
+        }
+    }
+    // Every pair whose key starts with `prefix`, sorted by key
+    pub fn scan(&mut self, prefix: String) -> Result<Vec<(String, String)>> {
+        match self.cmd(&SessionClientCommand::Scan(prefix))? {
+            SessionServerResp::Pairs(pairs) => Ok(pairs),
+            resp => Err(resp.into_error()),
+        }
+    }
+    // Turn the connection into a stream of the changes matching `filter`,
+    // returns once the server is watching
+    pub fn watch(mut self, filter: WatchFilter) -> Result<Watcher> {
+        match self.cmd(&SessionClientCommand::Watch(filter))? {
+            SessionServerResp::OK => Ok(Watcher::new(self)),
+            resp => Err(resp.into_error()),
+        }
+    }
+    // Server, engine and thread pool statistics
+    pub fn info(&mut self) -> Result<ServerInfo> {
+        match self.cmd(&SessionClientCommand::Info)? {
+            SessionServerResp::Info(info) => Ok(*info),
+            resp => Err(resp.into_error()),
+        }
+    }
+    // Turn the follower we are connected to into a leader
+    pub fn promote(&mut self) -> Result<()> {
+        match self.cmd(&SessionClientCommand::Promote)? {
+            SessionServerResp::OK => Ok(()),
+            resp => Err(resp.into_error()),
         }
     }
     pub fn quit(&mut self) -> Result<()> {
@@ -73,3 +188,10 @@ impl KvsClient {
         Ok(())
     }
 }
+
+fn timeout_error(e: io::Error) -> KvStoreError {
+    match e.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => KvStoreError::Timeout,
+        _ => KvStoreError::Io(e),
+    }
+}
@@ -0,0 +1,230 @@
+use crate::network::{KvsClient, ServerInfo};
+use crate::{KvStoreError, Result};
+use std::io;
+use std::net::SocketAddr;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Thread-safe pool of handshaken `KvsClient` connections
+///
+/// Broken connections are dropped and replaced transparently, `get`
+/// (and `set` when `retry_set` is on) are retried with exponential backoff.
+pub struct KvsClientPool {
+    addr: SocketAddr,
+    idle: Mutex<Vec<KvsClient>>,
+    max_idle: usize,
+    max_retries: u32,
+    backoff: Duration,
+    retry_set: bool,
+    credentials: Option<(String, String)>,
+    timeout: Option<Duration>,
+}
+
+/// A connection checked out of the pool, given back on drop
+pub struct PooledClient<'a> {
+    pool: &'a KvsClientPool,
+    client: Option<KvsClient>,
+    broken: bool,
+}
+
+impl KvsClientPool {
+    pub fn new(addr: SocketAddr, max_idle: usize) -> Self {
+        KvsClientPool {
+            addr,
+            idle: Mutex::new(vec![]),
+            max_idle,
+            max_retries: 3,
+            backoff: Duration::from_millis(10),
+            retry_set: false,
+            credentials: None,
+            timeout: None,
+        }
+    }
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+    // only safe when writing the same value twice is fine for the caller
+    pub fn retry_set(mut self, retry_set: bool) -> Self {
+        self.retry_set = retry_set;
+        self
+    }
+    // Log in on every connection the pool opens
+    pub fn credentials(mut self, user: &str, password: &str) -> Self {
+        self.credentials = Some((user.to_owned(), password.to_owned()));
+        self
+    }
+    // Read and write timeout of the pooled connections, None waits forever
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Check out a live connection, opening a new one if none is idle
+    pub fn client(&self) -> Result<PooledClient<'_>> {
+        loop {
+            let idle = self.idle.lock().unwrap().pop();
+            match idle {
+                Some(client) => {
+                    if client.is_alive() {
+                        return Ok(self.wrap(client));
+                    }
+                    // stale, drop it and try the next one
+                }
+                None => return Ok(self.wrap(self.connect()?)),
+            }
+        }
+    }
+
+    fn connect(&self) -> Result<KvsClient> {
+        let mut client = KvsClient::new(self.addr)?;
+        if let Some((user, password)) = &self.credentials {
+            client = client.with_credentials(user, password);
+        }
+        client.set_timeout(self.timeout)?;
+        client.handshake()?;
+        Ok(client)
+    }
+
+    /// Open and handshake a fresh connection outside the pool, returns
+    /// how long it took. Idle connections can't tell a hung server.
+    pub fn ping(&self) -> Result<Duration> {
+        let start = Instant::now();
+        let mut client = self.connect()?;
+        let elapsed = start.elapsed();
+        let _ = client.quit();
+        Ok(elapsed)
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn idle_count(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+
+    pub fn set(&self, k: String, v: String) -> Result<()> {
+        let retries = if self.retry_set { self.max_retries } else { 0 };
+        self.with_retry(retries, |client| client.set(k.clone(), v.clone()))
+    }
+
+    pub fn get(&self, k: String) -> Result<Option<String>> {
+        self.with_retry(self.max_retries, |client| client.get(k.clone()))
+    }
+
+    pub fn scan(&self, prefix: String) -> Result<Vec<(String, String)>> {
+        self.with_retry(self.max_retries, |client| client.scan(prefix.clone()))
+    }
+
+    pub fn info(&self) -> Result<ServerInfo> {
+        self.with_retry(self.max_retries, |client| client.info())
+    }
+
+    // not idempotent, the second try could see the key already removed
+    pub fn remove(&self, k: String) -> Result<()> {
+        self.with_retry(0, |client| client.remove(k.clone()))
+    }
+
+    fn with_retry<T, F>(&self, retries: u32, mut op: F) -> Result<T>
+    where
+        F: FnMut(&mut KvsClient) -> Result<T>,
+    {
+        let mut attempt = 0;
+        loop {
+            let res = self.client().and_then(|mut client| {
+                let res = op(&mut client);
+                if let Err(ref e) = res {
+                    client.broken = is_connection_error(e);
+                }
+                res
+            });
+            match res {
+                Err(ref e) if attempt < retries && is_connection_error(e) => {
+                    thread::sleep(self.backoff * 2u32.pow(attempt));
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    fn wrap(&self, client: KvsClient) -> PooledClient<'_> {
+        PooledClient {
+            pool: self,
+            client: Some(client),
+            broken: false,
+        }
+    }
+
+    fn release(&self, client: KvsClient) {
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < self.max_idle {
+            idle.push(client);
+        }
+    }
+}
+
+// errors worth a reconnect, as opposed to errors reported by the server
+fn is_connection_error(e: &KvStoreError) -> bool {
+    match e {
+        KvStoreError::Io(e) => is_transport_error(e),
+        // the late response would desync the stream
+        KvStoreError::Timeout => true,
+        KvStoreError::Serde(e) => e.is_eof() || e.is_io(),
+        // turned down by a loaded server, worth another try after the backoff
+        KvStoreError::Busy(_) => true,
+        _ => false,
+    }
+}
+
+// the socket broke, as opposed to a local failure such as a bad TLS setup
+fn is_transport_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::AddrNotAvailable
+            | io::ErrorKind::Interrupted
+    )
+}
+
+impl<'a> PooledClient<'a> {
+    /// Mark the connection as unusable, it will not go back to the pool
+    pub fn discard(&mut self) {
+        self.broken = true;
+    }
+}
+
+impl<'a> Deref for PooledClient<'a> {
+    type Target = KvsClient;
+    fn deref(&self) -> &KvsClient {
+        self.client.as_ref().unwrap()
+    }
+}
+
+impl<'a> DerefMut for PooledClient<'a> {
+    fn deref_mut(&mut self) -> &mut KvsClient {
+        self.client.as_mut().unwrap()
+    }
+}
+
+impl<'a> Drop for PooledClient<'a> {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            if !self.broken {
+                self.pool.release(client);
+            }
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use crate::KvStoreError;
+use serde::{Deserialize, Serialize};
+use std::io;
+
+// Machine readable reason of a failed command
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    KeyNotFound,
+    Io,
+    Timeout,
+    InvalidRequest,
+    Unauthorized,
+    PermissionDenied,
+    ReadOnly,
+    NotLeader,
+    // the server has no room for the request, try again later
+    Busy,
+    Internal,
+}
+
+// Error payload of `SessionServerResp::ERR`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SessionError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl SessionError {
+    pub fn new(code: ErrorCode, message: &str) -> Self {
+        SessionError {
+            code,
+            message: message.to_owned(),
+        }
+    }
+}
+
+impl<'a> From<&'a KvStoreError> for SessionError {
+    fn from(error: &'a KvStoreError) -> Self {
+        let code = match error {
+            KvStoreError::KeyNotFound => ErrorCode::KeyNotFound,
+            KvStoreError::Io(e) => match e.kind() {
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => ErrorCode::Timeout,
+                _ => ErrorCode::Io,
+            },
+            KvStoreError::Sled(_) | KvStoreError::RemoteIo(_) => ErrorCode::Io,
+            KvStoreError::Timeout => ErrorCode::Timeout,
+            KvStoreError::InvalidRequest(_) => ErrorCode::InvalidRequest,
+            KvStoreError::Unauthorized(_) => ErrorCode::Unauthorized,
+            KvStoreError::PermissionDenied(_) => ErrorCode::PermissionDenied,
+            KvStoreError::ReadOnly(_) => ErrorCode::ReadOnly,
+            KvStoreError::NotLeader(_) => ErrorCode::NotLeader,
+            KvStoreError::Busy(_) | KvStoreError::PoolShutdown => ErrorCode::Busy,
+            _ => ErrorCode::Internal,
+        };
+        SessionError {
+            code,
+            message: format!("{}", error),
+        }
+    }
+}
+
+impl From<SessionError> for KvStoreError {
+    fn from(error: SessionError) -> Self {
+        match error.code {
+            ErrorCode::KeyNotFound => KvStoreError::KeyNotFound,
+            // the server's disk failed, not the connection to it
+            ErrorCode::Io => KvStoreError::RemoteIo(error.message),
+            ErrorCode::Timeout => KvStoreError::Timeout,
+            ErrorCode::InvalidRequest => KvStoreError::InvalidRequest(error.message),
+            ErrorCode::Unauthorized => KvStoreError::Unauthorized(error.message),
+            ErrorCode::PermissionDenied => KvStoreError::PermissionDenied(error.message),
+            ErrorCode::ReadOnly => KvStoreError::ReadOnly(error.message),
+            ErrorCode::NotLeader => KvStoreError::NotLeader(error.message),
+            ErrorCode::Busy => KvStoreError::Busy(error.message),
+            ErrorCode::Internal => KvStoreError::Rpc(error.message),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use crate::network::Credentials;
+use serde::{Deserialize, Serialize};
+
+// Version 1 was the bare `Handshake` command without any payload,
+// version 2 sent errors as plain strings
+pub const PROTOCOL_VERSION: u32 = 3;
+pub const MIN_PROTOCOL_VERSION: u32 = 3;
+
+// Optional protocol capabilities, both peers have to agree on them
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    Compression,
+    Batching,
+    Auth,
+}
+
+// Features this server is able to speak
+pub const SERVER_FEATURES: &[Feature] = &[];
+
+// Sent by the client as the first command of a session
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HandshakeRequest {
+    // highest version the client speaks, version 1 sent none
+    #[serde(default = "legacy_version")]
+    pub version: u32,
+    // lowest version the client still speaks
+    #[serde(default = "legacy_version")]
+    pub min_version: u32,
+    #[serde(default)]
+    pub features: Vec<Feature>,
+    #[serde(default)]
+    pub client: String,
+    // required by servers with authentication
+    #[serde(default)]
+    pub credentials: Option<Credentials>,
+}
+
+// What the server agreed on
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HandshakeResp {
+    pub version: u32,
+    pub features: Vec<Feature>,
+    pub server: String,
+}
+
+impl HandshakeRequest {
+    pub fn new(features: Vec<Feature>) -> Self {
+        HandshakeRequest {
+            version: PROTOCOL_VERSION,
+            min_version: MIN_PROTOCOL_VERSION,
+            features,
+            client: identity(),
+            credentials: None,
+        }
+    }
+
+    // The bare `Handshake` of a version 1 client
+    pub(crate) fn legacy() -> Self {
+        HandshakeRequest {
+            version: legacy_version(),
+            min_version: legacy_version(),
+            features: vec![],
+            client: String::new(),
+            credentials: None,
+        }
+    }
+}
+
+fn legacy_version() -> u32 {
+    1
+}
+
+impl Default for HandshakeRequest {
+    fn default() -> Self {
+        HandshakeRequest::new(vec![])
+    }
+}
+
+impl HandshakeResp {
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.features.contains(&feature)
+    }
+}
+
+pub fn identity() -> String {
+    format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+}
+
+// Pick the highest version both peers speak, None if the ranges don't overlap
+pub fn negotiate(req: &HandshakeRequest, supported: &[Feature]) -> Option<HandshakeResp> {
+    let version = req.version.min(PROTOCOL_VERSION);
+    if version < req.min_version.max(MIN_PROTOCOL_VERSION) {
+        return None;
+    }
+    let features = req
+        .features
+        .iter()
+        .filter(|f| supported.contains(f))
+        .cloned()
+        .collect();
+    Some(HandshakeResp {
+        version,
+        features,
+        server: identity(),
+    })
+}
@@ -0,0 +1,181 @@
+//! Server metrics in the Prometheus text format
+
+use crate::network::ServerContext;
+use crate::KvsEngine;
+use std::fmt::Write;
+
+// `# HELP` and `# TYPE` lines of a metric
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn single<T: std::fmt::Display>(out: &mut String, name: &str, kind: &str, help: &str, value: T) {
+    header(out, name, kind, help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Render the metrics of a server, `ctx` being the context its sessions share.
+/// The engine metrics are left out when the engine can't tell its stats.
+pub fn render<E: KvsEngine>(store: &E, ctx: &ServerContext) -> String {
+    let mut out = String::new();
+    let stats = &ctx.stats;
+    let commands = stats.command_stats();
+
+    header(
+        &mut out,
+        "kvs_requests_total",
+        "counter",
+        "Requests handled, by command",
+    );
+    for (name, command) in &commands {
+        let _ = writeln!(
+            out,
+            "kvs_requests_total{{command=\"{}\"}} {}",
+            name, command.count
+        );
+    }
+    header(
+        &mut out,
+        "kvs_request_errors_total",
+        "counter",
+        "Requests answered with an error, by command",
+    );
+    for (name, command) in &commands {
+        let _ = writeln!(
+            out,
+            "kvs_request_errors_total{{command=\"{}\"}} {}",
+            name, command.errors
+        );
+    }
+    header(
+        &mut out,
+        "kvs_request_duration_seconds",
+        "histogram",
+        "Time to handle a request, by command",
+    );
+    for (name, command) in &commands {
+        for (bound, count) in command.cumulative_buckets() {
+            let _ = writeln!(
+                out,
+                "kvs_request_duration_seconds_bucket{{command=\"{}\",le=\"{}\"}} {}",
+                name, bound, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "kvs_request_duration_seconds_bucket{{command=\"{}\",le=\"+Inf\"}} {}",
+            name, command.count
+        );
+        let _ = writeln!(
+            out,
+            "kvs_request_duration_seconds_sum{{command=\"{}\"}} {}",
+            name, command.latency_secs
+        );
+        let _ = writeln!(
+            out,
+            "kvs_request_duration_seconds_count{{command=\"{}\"}} {}",
+            name, command.count
+        );
+    }
+
+    single(
+        &mut out,
+        "kvs_active_sessions",
+        "gauge",
+        "Sessions currently open",
+        stats.active_sessions(),
+    );
+    single(
+        &mut out,
+        "kvs_connections_total",
+        "counter",
+        "Sessions opened since the start",
+        stats.connections(),
+    );
+    single(
+        &mut out,
+        "kvs_uptime_seconds",
+        "gauge",
+        "Time since the server started",
+        stats.uptime().as_secs(),
+    );
+
+    if let Ok(engine) = store.stats() {
+        single(
+            &mut out,
+            "kvs_keys",
+            "gauge",
+            "Keys in the engine",
+            engine.keys,
+        );
+        single(
+            &mut out,
+            "kvs_disk_bytes",
+            "gauge",
+            "Bytes the engine keeps on disk",
+            engine.disk_bytes,
+        );
+        single(
+            &mut out,
+            "kvs_dead_bytes",
+            "gauge",
+            "Bytes a compaction would drop",
+            engine.dead_bytes,
+        );
+        single(
+            &mut out,
+            "kvs_compactions_total",
+            "counter",
+            "Compactions since the engine was opened",
+            engine.compactions,
+        );
+        single(
+            &mut out,
+            "kvs_compaction_seconds_total",
+            "counter",
+            "Time spent compacting",
+            engine.compaction_secs,
+        );
+    }
+
+    if let Some(probe) = &ctx.pool {
+        let pool = probe();
+        single(
+            &mut out,
+            "kvs_pool_threads",
+            "gauge",
+            "Threads of the thread pool",
+            pool.threads,
+        );
+        single(
+            &mut out,
+            "kvs_pool_queued_jobs",
+            "gauge",
+            "Jobs waiting for a thread",
+            pool.queued,
+        );
+        single(
+            &mut out,
+            "kvs_pool_active_workers",
+            "gauge",
+            "Threads running a job",
+            pool.active,
+        );
+        single(
+            &mut out,
+            "kvs_pool_completed_jobs_total",
+            "counter",
+            "Jobs the thread pool ran to the end",
+            pool.completed,
+        );
+        single(
+            &mut out,
+            "kvs_pool_panics_total",
+            "counter",
+            "Jobs of the thread pool which panicked",
+            pool.panics,
+        );
+    }
+    out
+}
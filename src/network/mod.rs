@@ -1,19 +1,130 @@
 use crate::error::Result;
-use crate::KvsEngine;
+use crate::thread_pool::{Priority, ThreadPool};
+use crate::{EngineStats, KvStoreError, KvsEngine};
+use crossbeam::channel::RecvTimeoutError;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::io::{Read, Write};
-use std::net::{Shutdown, TcpStream};
+use std::net::Shutdown;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
 
+#[cfg(feature = "async")]
+mod async_client;
+#[cfg(feature = "async")]
+mod async_server;
+pub mod auth;
 mod client;
+mod client_pool;
+mod error_code;
+mod handshake;
+pub mod metrics;
+mod proxy;
+mod reactor;
+mod replication;
 mod server;
+mod sharded;
+mod stats;
+mod stream;
+pub mod tls;
+mod watch;
 
+#[cfg(feature = "async")]
+pub use async_client::AsyncKvsClient;
+#[cfg(feature = "async")]
+pub use async_server::AsyncKvsServer;
+pub use auth::{Authenticator, Credentials};
 pub use client::KvsClient;
+pub use client_pool::{KvsClientPool, PooledClient};
+pub use error_code::{ErrorCode, SessionError};
+pub use handshake::{
+    Feature, HandshakeRequest, HandshakeResp, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+    SERVER_FEATURES,
+};
+pub use proxy::{BackendHealth, ProxyKvsEngine};
+pub use reactor::KvsReactorServer;
+use replication::HEARTBEAT_INTERVAL;
+pub use replication::{Follower, Replication, ReplicationEvent, Subscription};
 pub use server::KvsServer;
+pub use sharded::{HashRing, ShardedKvsClient};
+pub use stats::{CommandStats, PoolProbe, ServerInfo, ServerStats, LATENCY_BUCKETS};
+pub use stream::KvsStream;
+pub use watch::{WatchEvent, WatchFilter, WatchHub, WatchReceiver, Watcher};
 
-pub struct Session<'a, E: KvsEngine> {
-    store: &'a mut E,
-    sock: TcpStream,
+pub struct Session<E: KvsEngine> {
+    processor: SessionProcessor<E>,
+    sock: KvsStream,
+    // bytes of a command not complete yet
+    rbuf: Vec<u8>,
+    // set once a follower subscribed, see `Session::replicate`
+    feed: Option<Subscription>,
+    // set once the client started watching, see `Session::watch`
+    watch: Option<WatchReceiver>,
+}
+
+// Protocol state of one connection, detached from the socket so that
+// both the blocking and the reactor server can drive it
+pub struct SessionProcessor<E: KvsEngine> {
+    store: E,
+    ctx: ServerContext,
     state: SessionState,
+    negotiated: Option<HandshakeResp>,
+    user: Option<auth::User>,
+}
+
+// Server wide settings shared by every session
+#[derive(Clone, Default)]
+pub struct ServerContext {
+    // sessions have to log in when set
+    pub auth: Option<Arc<Authenticator>>,
+    pub replication: Arc<Replication>,
+    pub watch: Arc<WatchHub>,
+    pub stats: Arc<ServerStats>,
+    // set by servers running on a `ThreadPool`
+    pub pool: Option<PoolProbe>,
+    // runs engine upkeep on that pool at low priority
+    pub background: Option<Background>,
+}
+
+pub type Background = Arc<dyn Fn(Box<dyn FnOnce() + Send>) + Send + Sync>;
+
+impl ServerContext {
+    // Context of a server running on `pool`, the sessions don't keep it alive
+    fn on_pool<T: ThreadPool + Send + Sync + 'static>(pool: &Arc<T>) -> Self {
+        let (probe, background) = (Arc::downgrade(pool), Arc::downgrade(pool));
+        // one background job is queued at a time, those coming meanwhile are dropped
+        let pending = Arc::new(AtomicBool::new(false));
+        ServerContext {
+            pool: Some(Arc::new(move || {
+                probe.upgrade().map(|pool| pool.stats()).unwrap_or_default()
+            })),
+            background: Some(Arc::new(move |job| {
+                let pool = match background.upgrade() {
+                    Some(pool) => pool,
+                    None => return,
+                };
+                if pending.swap(true, Ordering::SeqCst) {
+                    return;
+                }
+                let guard = Pending(pending.clone());
+                pool.spawn_with_priority(Priority::Low, move || {
+                    drop(guard);
+                    job();
+                });
+            })),
+            ..ServerContext::default()
+        }
+    }
+}
+
+// Clears the pending flag as the background job starts, or once the pool dropped it
+struct Pending(Arc<AtomicBool>);
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
 }
 
 #[derive(PartialEq)]
@@ -29,11 +140,21 @@ pub enum SessionState {
 // For client
 #[derive(Serialize, Deserialize, Debug)]
 pub enum SessionClientCommand {
-    Handshake,
+    Handshake(HandshakeRequest),
     Quit,
     Get(String),
     Set(String, String),
     Remove(String),
+    // every pair whose key starts with the prefix
+    Scan(String),
+    // turn this session into a stream of the server's writes
+    Replicate,
+    // make a follower accept writes
+    Promote,
+    // turn this session into a stream of the matching writes
+    Watch(WatchFilter),
+    // server, engine and thread pool statistics
+    Info,
     Invalid,
 }
 
@@ -41,95 +162,543 @@ pub enum SessionClientCommand {
 #[derive(Serialize, Deserialize, Debug)]
 pub enum SessionServerResp {
     OK,
-    ERR(String),
+    ERR(SessionError),
     Value(String),
+    // sorted by key
+    Pairs(Vec<(String, String)>),
     NotFound,
     InvalidCmd,
+    Handshake(HandshakeResp),
+    // no common protocol version, the server speaks min..=max
+    Incompatible { min_version: u32, version: u32 },
+    Replicated(ReplicationEvent),
+    Changed(WatchEvent),
+    Info(Box<ServerInfo>),
 }
 
-impl<'a, E: KvsEngine> Session<'a, E> {
-    pub fn new(stream: TcpStream, store: &'a mut E) -> Self {
-        Session {
-            store,
-            sock: stream,
-            state: SessionState::Wait,
+// Take one complete message off the front of the buffer,
+// returns None when more bytes are needed
+pub(crate) fn decode<T: DeserializeOwned>(buf: &mut Vec<u8>) -> Result<Option<T>> {
+    let (msg, offset) = {
+        let mut stream = serde_json::Deserializer::from_slice(buf).into_iter::<T>();
+        match stream.next() {
+            Some(Ok(msg)) => (msg, stream.byte_offset()),
+            Some(Err(ref e)) if e.is_eof() => return Ok(None),
+            Some(Err(e)) => return Err(e.into()),
+            None => return Ok(None),
         }
+    };
+    buf.drain(..offset);
+    Ok(Some(msg))
+}
+
+// Commands of older protocol versions that no longer parse
+#[derive(Deserialize)]
+enum LegacyCommand {
+    Handshake,
+}
+
+// Like `decode`, the bare `Handshake` of version 1 clients reads as a
+// handshake of that version so they get an explicit `Incompatible`
+pub(crate) fn decode_cmd(buf: &mut Vec<u8>) -> Result<Option<SessionClientCommand>> {
+    match decode(buf) {
+        Err(e) => match decode(buf) {
+            Ok(Some(LegacyCommand::Handshake)) => Ok(Some(SessionClientCommand::Handshake(
+                HandshakeRequest::legacy(),
+            ))),
+            _ => Err(e),
+        },
+        res => res,
     }
+}
 
-    pub fn poll(&mut self) -> Result<()> {
-        let mut buf = vec![0u8; 1024];
-        let cmd = match self.sock.read(&mut buf) {
-            Ok(len) => {
-                if len == 0 {
-                    self.state = SessionState::Done;
-                    SessionClientCommand::Invalid
-                } else {
-                    let msg = &buf[..len];
-                    let cmd: SessionClientCommand = serde_json::from_slice(&msg)?;
-                    cmd
-                }
+impl SessionClientCommand {
+    // label of the command in the stats
+    pub fn name(&self) -> &'static str {
+        match self {
+            SessionClientCommand::Handshake(_) => "handshake",
+            SessionClientCommand::Quit => "quit",
+            SessionClientCommand::Get(_) => "get",
+            SessionClientCommand::Set(_, _) => "set",
+            SessionClientCommand::Remove(_) => "rm",
+            SessionClientCommand::Scan(_) => "scan",
+            SessionClientCommand::Replicate => "replicate",
+            SessionClientCommand::Promote => "promote",
+            SessionClientCommand::Watch(_) => "watch",
+            SessionClientCommand::Info => "info",
+            SessionClientCommand::Invalid => "invalid",
+        }
+    }
+
+    // whether the command has to touch the engine
+    pub fn is_engine_op(&self) -> bool {
+        matches!(
+            self,
+            SessionClientCommand::Get(_)
+                | SessionClientCommand::Set(_, _)
+                | SessionClientCommand::Remove(_)
+                | SessionClientCommand::Scan(_)
+                | SessionClientCommand::Info
+        )
+    }
+
+    // how urgent the command is on the pool, stats can wait
+    pub fn priority(&self) -> Priority {
+        match self {
+            SessionClientCommand::Info => Priority::Low,
+            _ => Priority::Normal,
+        }
+    }
+
+    // whether the command is refused before the handshake
+    pub fn requires_handshake(&self) -> bool {
+        match self {
+            SessionClientCommand::Replicate
+            | SessionClientCommand::Promote
+            | SessionClientCommand::Watch(_) => true,
+            cmd => cmd.is_engine_op(),
+        }
+    }
+}
+
+impl SessionServerResp {
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self,
+            SessionServerResp::ERR(_)
+                | SessionServerResp::InvalidCmd
+                | SessionServerResp::Incompatible { .. }
+        )
+    }
+
+    // Turn an unexpected response into the matching client side error
+    pub fn into_error(self) -> KvStoreError {
+        match self {
+            SessionServerResp::ERR(e) => e.into(),
+            SessionServerResp::InvalidCmd => {
+                KvStoreError::InvalidRequest("Invalid command".to_owned())
             }
-            Err(_e) => SessionClientCommand::Invalid,
-        };
-        self.handle(cmd)
+            SessionServerResp::Incompatible {
+                min_version,
+                version,
+            } => KvStoreError::Incompatible(min_version, version),
+            resp => KvStoreError::Rpc(format!("Unexpected response {:?}", resp)),
+        }
     }
+}
 
-    pub fn close(&mut self) -> Result<()> {
-        self.sock.shutdown(Shutdown::Both)?;
-        Ok(())
+impl<E: KvsEngine> SessionProcessor<E> {
+    pub fn new(store: E) -> Self {
+        SessionProcessor::with_context(store, ServerContext::default())
     }
 
-    pub fn handle(&mut self, cmd: SessionClientCommand) -> Result<()> {
+    pub fn with_context(store: E, ctx: ServerContext) -> Self {
+        ctx.stats.session_opened();
+        SessionProcessor {
+            store,
+            ctx,
+            state: SessionState::Wait,
+            negotiated: None,
+            user: None,
+        }
+    }
+
+    // Count a command handled since `start` in the server stats
+    pub(crate) fn record(&self, command: &'static str, start: Instant, failed: bool) {
+        self.ctx.stats.record(command, start.elapsed(), failed);
+    }
+
+    pub fn process(&mut self, cmd: SessionClientCommand) -> SessionServerResp {
+        if cmd.requires_handshake() && self.state != SessionState::Connect {
+            return SessionServerResp::ERR(SessionError::new(
+                ErrorCode::InvalidRequest,
+                "Handshake required",
+            ));
+        }
+        if let Some(resp) = self.authorize(&cmd) {
+            return resp;
+        }
         match cmd {
-            SessionClientCommand::Handshake => {
+            SessionClientCommand::Handshake(req) => {
+                let resp = match handshake::negotiate(&req, &self.supported_features()) {
+                    Some(resp) => resp,
+                    None => {
+                        // nothing to talk about with this peer, whoever it is
+                        self.state = SessionState::Done;
+                        return SessionServerResp::Incompatible {
+                            min_version: MIN_PROTOCOL_VERSION,
+                            version: PROTOCOL_VERSION,
+                        };
+                    }
+                };
+                if let Some(auth) = &self.ctx.auth {
+                    let user = req.credentials.as_ref().and_then(|c| auth.authenticate(c));
+                    if user.is_none() {
+                        self.state = SessionState::Done;
+                        return SessionServerResp::ERR(SessionError::new(
+                            ErrorCode::Unauthorized,
+                            "Authentication failed",
+                        ));
+                    }
+                    self.user = user;
+                }
                 self.state = SessionState::Connect;
-                let resp = SessionServerResp::OK;
-                self.sock
-                    .write_all(serde_json::to_string(&resp)?.as_bytes())?
+                self.negotiated = Some(resp.clone());
+                SessionServerResp::Handshake(resp)
             }
             SessionClientCommand::Quit => {
                 self.state = SessionState::Done;
-                let resp = SessionServerResp::OK;
-                self.sock
-                    .write_all(serde_json::to_string(&resp)?.as_bytes())?
-            }
-            SessionClientCommand::Get(k) => {
-                let resp = match self.store.get(k) {
-                    Ok(some_v) => match some_v {
-                        Some(v) => SessionServerResp::Value(v),
-                        None => SessionServerResp::NotFound,
-                    },
-                    Err(e) => SessionServerResp::ERR(format!("{}", e)),
-                };
-                self.sock
-                    .write_all(&serde_json::to_string(&resp)?.into_bytes())?
+                SessionServerResp::OK
             }
+            SessionClientCommand::Get(k) => match self.store.get(k) {
+                Ok(some_v) => match some_v {
+                    Some(v) => SessionServerResp::Value(v),
+                    None => SessionServerResp::NotFound,
+                },
+                Err(e) => SessionServerResp::ERR(SessionError::from(&e)),
+            },
+            SessionClientCommand::Scan(prefix) => match self.store.scan(prefix) {
+                // keys under the prefix may still be denied by a longer rule
+                Ok(pairs) => SessionServerResp::Pairs(
+                    pairs
+                        .into_iter()
+                        .filter(|(k, _)| self.can_read(k))
+                        .collect(),
+                ),
+                Err(e) => SessionServerResp::ERR(SessionError::from(&e)),
+            },
             SessionClientCommand::Set(k, v) => {
-                let resp = match self.store.set(k, v) {
-                    Ok(_) => SessionServerResp::OK,
-                    Err(e) => SessionServerResp::ERR(format!("{}", e)),
+                let (store, watch) = (&self.store, &self.ctx.watch);
+                let event = ReplicationEvent::Set(k.clone(), v.clone());
+                let changed = WatchEvent::Set(k.clone(), v.clone());
+                let apply = || {
+                    store.set(k, v)?;
+                    watch.publish(&changed);
+                    Ok(())
                 };
-                self.sock
-                    .write_all(&serde_json::to_string(&resp)?.into_bytes())?
+                match self.ctx.replication.write(event, apply) {
+                    Ok(_) => {
+                        self.schedule_maintenance();
+                        SessionServerResp::OK
+                    }
+                    Err(e) => SessionServerResp::ERR(SessionError::from(&e)),
+                }
             }
             SessionClientCommand::Remove(k) => {
-                let resp = match self.store.remove(k) {
-                    Ok(_) => SessionServerResp::OK,
-                    Err(e) => SessionServerResp::ERR(format!("{}", e)),
+                let (store, watch) = (&self.store, &self.ctx.watch);
+                let event = ReplicationEvent::Remove(k.clone());
+                let changed = WatchEvent::Remove(k.clone());
+                let apply = || {
+                    store.remove(k)?;
+                    watch.publish(&changed);
+                    Ok(())
                 };
-                self.sock
-                    .write_all(&serde_json::to_string(&resp)?.into_bytes())?
+                match self.ctx.replication.write(event, apply) {
+                    Ok(_) => {
+                        self.schedule_maintenance();
+                        SessionServerResp::OK
+                    }
+                    Err(e) => SessionServerResp::ERR(SessionError::from(&e)),
+                }
             }
-            SessionClientCommand::Invalid => {
-                let resp = SessionServerResp::InvalidCmd;
-                self.sock
-                    .write_all(&serde_json::to_string(&resp)?.into_bytes())?
+            SessionClientCommand::Promote => {
+                self.ctx.replication.promote();
+                SessionServerResp::OK
             }
+            SessionClientCommand::Info => match self.store.stats() {
+                Ok(engine) => SessionServerResp::Info(Box::new(self.info(engine))),
+                Err(e) => SessionServerResp::ERR(SessionError::from(&e)),
+            },
+            // the stream needs to own the socket, see `Session::replicate`
+            SessionClientCommand::Replicate => SessionServerResp::ERR(SessionError::new(
+                ErrorCode::InvalidRequest,
+                "Replication is not supported by this server",
+            )),
+            // same as replication, see `Session::watch`
+            SessionClientCommand::Watch(_) => SessionServerResp::ERR(SessionError::new(
+                ErrorCode::InvalidRequest,
+                "Watching is not supported by this server",
+            )),
+            SessionClientCommand::Invalid => SessionServerResp::InvalidCmd,
+        }
+    }
+
+    // Hand the engine's upkeep to the server's pool once it's due
+    fn schedule_maintenance(&self) {
+        let background = match &self.ctx.background {
+            Some(background) => background,
+            None => return,
         };
-        Ok(())
+        if self.store.maintenance_due() {
+            let store = self.store.clone();
+            background(Box::new(move || {
+                if let Err(e) = store.maintain() {
+                    log::warn!("Engine maintenance failed: {}", e);
+                }
+            }));
+        }
+    }
+
+    // Check the logged in user may run `cmd`, the error response if not
+    fn authorize(&self, cmd: &SessionClientCommand) -> Option<SessionServerResp> {
+        let write = matches!(
+            cmd,
+            SessionClientCommand::Set(_, _) | SessionClientCommand::Remove(_)
+        );
+        if write && self.ctx.replication.is_read_only() {
+            return Some(SessionServerResp::ERR(SessionError::new(
+                ErrorCode::ReadOnly,
+                "Read only follower, write to the leader",
+            )));
+        }
+        // without auth anyone may run anything
+        self.ctx.auth.as_ref()?;
+        // replication covers every key
+        let (key, write) = match cmd {
+            SessionClientCommand::Get(k) => (k.as_str(), false),
+            SessionClientCommand::Set(k, _) => (k.as_str(), true),
+            SessionClientCommand::Remove(k) => (k.as_str(), true),
+            SessionClientCommand::Scan(prefix) => (prefix.as_str(), false),
+            SessionClientCommand::Watch(filter) => (filter.key(), false),
+            SessionClientCommand::Replicate => ("", false),
+            SessionClientCommand::Promote => ("", true),
+            _ => return None,
+        };
+        match &self.user {
+            Some(user) if user.allows(key, write) => None,
+            _ => Some(SessionServerResp::ERR(SessionError::new(
+                ErrorCode::PermissionDenied,
+                &format!("Permission denied on key {}", key),
+            ))),
+        }
+    }
+
+    // Keys under an allowed prefix may still be denied by a longer rule
+    fn can_read(&self, key: &str) -> bool {
+        match (&self.ctx.auth, &self.user) {
+            (None, _) => true,
+            (Some(_), Some(user)) => user.allows(key, false),
+            (Some(_), None) => false,
+        }
+    }
+
+    fn info(&self, engine: EngineStats) -> ServerInfo {
+        let stats = &self.ctx.stats;
+        ServerInfo {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            read_only: self.ctx.replication.is_read_only(),
+            uptime_secs: stats.uptime().as_secs(),
+            connections: stats.connections(),
+            active_sessions: stats.active_sessions(),
+            commands: stats.commands(),
+            engine,
+            pool: self.ctx.pool.as_ref().map(|probe| probe()),
+        }
+    }
+
+    fn supported_features(&self) -> Vec<Feature> {
+        let mut features = SERVER_FEATURES.to_vec();
+        if self.ctx.auth.is_some() {
+            features.push(Feature::Auth);
+        }
+        features
+    }
+
+    // Start streaming the store to a follower
+    pub fn subscribe(&mut self) -> std::result::Result<Subscription, SessionServerResp> {
+        self.check(&SessionClientCommand::Replicate).and_then(|_| {
+            self.ctx
+                .replication
+                .subscribe(&self.store)
+                .map_err(|e| SessionServerResp::ERR(SessionError::from(&e)))
+        })
+    }
+
+    // Start pushing the writes matching `filter`
+    pub fn watch(
+        &mut self,
+        filter: WatchFilter,
+    ) -> std::result::Result<WatchReceiver, SessionServerResp> {
+        self.check(&SessionClientCommand::Watch(filter.clone()))
+            .map(|_| self.ctx.watch.subscribe(filter))
+    }
+
+    // Handshake and authorization of the commands taking over the session
+    fn check(&self, cmd: &SessionClientCommand) -> std::result::Result<(), SessionServerResp> {
+        if self.state != SessionState::Connect {
+            return Err(SessionServerResp::ERR(SessionError::new(
+                ErrorCode::InvalidRequest,
+                "Handshake required",
+            )));
+        }
+        match self.authorize(cmd) {
+            Some(resp) => Err(resp),
+            None => Ok(()),
+        }
     }
 
     pub fn should_quit(&self) -> bool {
         self.state == SessionState::Done
     }
+
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_ref().map(|u| u.name.as_str())
+    }
+
+    pub fn negotiated(&self) -> Option<&HandshakeResp> {
+        self.negotiated.as_ref()
+    }
+
+    pub fn quit(&mut self) {
+        self.state = SessionState::Done;
+    }
+}
+
+impl<E: KvsEngine> Drop for SessionProcessor<E> {
+    fn drop(&mut self) {
+        self.ctx.stats.session_closed();
+    }
+}
+
+impl<E: KvsEngine> Session<E> {
+    pub fn new<S: Into<KvsStream>>(stream: S, store: E) -> Self {
+        Session::with_context(stream, store, ServerContext::default())
+    }
+
+    pub fn with_context<S: Into<KvsStream>>(stream: S, store: E, ctx: ServerContext) -> Self {
+        Session {
+            processor: SessionProcessor::with_context(store, ctx),
+            sock: stream.into(),
+            rbuf: vec![],
+            feed: None,
+            watch: None,
+        }
+    }
+
+    pub fn poll(&mut self) -> Result<()> {
+        let mut chunk = [0u8; 1024];
+        let cmd = loop {
+            match decode_cmd(&mut self.rbuf) {
+                Ok(Some(cmd)) => break cmd,
+                Ok(None) => {}
+                // unknown commands, e.g. from older clients, are answered as invalid
+                Err(_) => {
+                    self.rbuf.clear();
+                    break SessionClientCommand::Invalid;
+                }
+            }
+            match self.sock.read(&mut chunk) {
+                Ok(0) => {
+                    self.processor.quit();
+                    break SessionClientCommand::Invalid;
+                }
+                Ok(len) => self.rbuf.extend_from_slice(&chunk[..len]),
+                Err(_e) => {
+                    // broken connection, or a failed TLS handshake
+                    self.processor.quit();
+                    return Ok(());
+                }
+            }
+        };
+        self.handle(cmd)
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        self.sock.shutdown(Shutdown::Both)?;
+        Ok(())
+    }
+
+    pub fn handle(&mut self, cmd: SessionClientCommand) -> Result<()> {
+        let (name, start) = (cmd.name(), Instant::now());
+        let resp = match cmd {
+            SessionClientCommand::Replicate => match self.processor.subscribe() {
+                Ok(feed) => {
+                    self.feed = Some(feed);
+                    // the stream starts right away, nothing to answer
+                    self.processor.record(name, start, false);
+                    return Ok(());
+                }
+                Err(resp) => resp,
+            },
+            SessionClientCommand::Watch(filter) => match self.processor.watch(filter) {
+                Ok(changes) => {
+                    self.watch = Some(changes);
+                    // from now on the client won't miss a change
+                    SessionServerResp::OK
+                }
+                Err(resp) => resp,
+            },
+            cmd => self.processor.process(cmd),
+        };
+        self.processor.record(name, start, resp.is_error());
+        self.send(&resp)
+    }
+
+    // Whether the session turned into a replication stream, which
+    // lives as long as the follower and should get its own thread
+    pub fn is_replicating(&self) -> bool {
+        self.feed.is_some()
+    }
+
+    // Ship the snapshot then every write, until the follower goes away
+    pub fn replicate(mut self) -> Result<()> {
+        let (snapshot, events) = match self.feed.take() {
+            Some(feed) => feed,
+            None => return Ok(()),
+        };
+        let snapshot = snapshot
+            .into_iter()
+            .map(|(k, v)| ReplicationEvent::Set(k, v))
+            .chain(Some(ReplicationEvent::SnapshotDone));
+        for event in snapshot {
+            self.send(&SessionServerResp::Replicated(event))?;
+        }
+        loop {
+            let event = match events.recv_timeout(HEARTBEAT_INTERVAL) {
+                Ok(event) => event,
+                // idle, also finds out about a dead follower
+                Err(RecvTimeoutError::Timeout) => ReplicationEvent::Heartbeat,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            self.send(&SessionServerResp::Replicated(event))?;
+        }
+        Ok(())
+    }
+
+    // Whether the session turned into a watch, see `is_replicating`
+    pub fn is_watching(&self) -> bool {
+        self.watch.is_some()
+    }
+
+    // Push the matching writes until the client goes away
+    pub fn watch(mut self) -> Result<()> {
+        let changes = match self.watch.take() {
+            Some(changes) => changes,
+            None => return Ok(()),
+        };
+        loop {
+            let event = match changes.recv_timeout(HEARTBEAT_INTERVAL) {
+                Ok(WatchEvent::Set(ref k, _)) | Ok(WatchEvent::Remove(ref k))
+                    if !self.processor.can_read(k) =>
+                {
+                    continue
+                }
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => WatchEvent::Heartbeat,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            self.send(&SessionServerResp::Changed(event))?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, resp: &SessionServerResp) -> Result<()> {
+        self.sock
+            .write_all(&serde_json::to_string(resp)?.into_bytes())?;
+        Ok(())
+    }
+
+    pub fn should_quit(&self) -> bool {
+        self.processor.should_quit()
+    }
 }
@@ -0,0 +1,173 @@
+use crate::network::{HashRing, KvsClientPool};
+use crate::{EngineStats, KvStoreError, KvsEngine, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Last known state of a backend
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackendHealth {
+    pub addr: SocketAddr,
+    pub healthy: bool,
+    // time to connect and handshake
+    pub latency: Option<Duration>,
+    pub error: Option<String>,
+}
+
+impl fmt::Display for BackendHealth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.error, self.latency) {
+            (Some(e), _) => write!(f, "{} down {}", self.addr, e),
+            (None, Some(latency)) => write!(
+                f,
+                "{} up {:.3}ms",
+                self.addr,
+                latency.as_micros() as f64 / 1000.0
+            ),
+            (None, None) => write!(f, "{} unchecked", self.addr),
+        }
+    }
+}
+
+struct Backend {
+    pool: KvsClientPool,
+    health: Mutex<BackendHealth>,
+}
+
+/// `KvsEngine` forwarding every command to a set of `kvs-server`s
+///
+/// Keys are routed with the same `HashRing` as `ShardedKvsClient`, so
+/// both can be used on the same servers. Each backend has its own pool of
+/// connections, shared by every session of the proxy. Backends found down
+/// by `check_health` fail fast until they are found up again, scans leave
+/// them out.
+#[derive(Clone)]
+pub struct ProxyKvsEngine {
+    ring: Arc<HashRing>,
+    backends: Arc<HashMap<SocketAddr, Backend>>,
+}
+
+impl ProxyKvsEngine {
+    pub fn new(pools: Vec<KvsClientPool>, vnodes: u32) -> Self {
+        let mut ring = HashRing::new(vnodes);
+        let mut backends = HashMap::new();
+        for pool in pools {
+            let addr = pool.addr();
+            ring.add(addr);
+            let health = BackendHealth {
+                addr,
+                healthy: true,
+                latency: None,
+                error: None,
+            };
+            backends.insert(
+                addr,
+                Backend {
+                    pool,
+                    health: Mutex::new(health),
+                },
+            );
+        }
+        ProxyKvsEngine {
+            ring: Arc::new(ring),
+            backends: Arc::new(backends),
+        }
+    }
+
+    /// Ping every backend and record the result, returns the new state
+    /// sorted by address
+    pub fn check_health(&self) -> Vec<BackendHealth> {
+        for backend in self.backends.values() {
+            let res = backend.pool.ping();
+            let mut health = backend.health.lock().unwrap();
+            match res {
+                Ok(latency) => {
+                    health.healthy = true;
+                    health.latency = Some(latency);
+                    health.error = None;
+                }
+                Err(e) => {
+                    health.healthy = false;
+                    health.latency = None;
+                    health.error = Some(format!("{}", e));
+                }
+            }
+        }
+        self.health()
+    }
+
+    // Last known state, sorted by address
+    pub fn health(&self) -> Vec<BackendHealth> {
+        self.ring
+            .nodes()
+            .iter()
+            .map(|addr| self.backends[addr].health.lock().unwrap().clone())
+            .collect()
+    }
+
+    fn backend(&self, addr: &SocketAddr) -> Result<&KvsClientPool> {
+        let backend = &self.backends[addr];
+        if backend.health.lock().unwrap().healthy {
+            Ok(&backend.pool)
+        } else {
+            Err(KvStoreError::Rpc(format!("Backend {} is down", addr)))
+        }
+    }
+
+    fn route(&self, key: &str) -> Result<&KvsClientPool> {
+        match self.ring.node(key) {
+            Some(addr) => self.backend(&addr),
+            None => Err(KvStoreError::Rpc("No backends".to_owned())),
+        }
+    }
+}
+
+impl KvsEngine for ProxyKvsEngine {
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.route(&key)?.set(key, value)
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        self.route(&key)?.get(key)
+    }
+
+    fn remove(&self, key: String) -> Result<()> {
+        self.route(&key)?.remove(key)
+    }
+
+    // the keys of the backends up, fails only if none is
+    fn scan(&self, prefix: String) -> Result<Vec<(String, String)>> {
+        let mut pairs = vec![];
+        let mut up = false;
+        for addr in self.ring.nodes() {
+            if let Ok(backend) = self.backend(&addr) {
+                pairs.extend(backend.scan(prefix.clone())?);
+                up = true;
+            }
+        }
+        if !up {
+            return Err(KvStoreError::Rpc("No backends up".to_owned()));
+        }
+        pairs.sort();
+        Ok(pairs)
+    }
+
+    // Sum of the backends
+    fn stats(&self) -> Result<EngineStats> {
+        let mut stats = EngineStats {
+            engine: "proxy".to_owned(),
+            ..EngineStats::default()
+        };
+        for addr in self.ring.nodes() {
+            let backend = self.backend(&addr)?.info()?.engine;
+            stats.keys += backend.keys;
+            stats.disk_bytes += backend.disk_bytes;
+            stats.dead_bytes += backend.dead_bytes;
+            stats.compactions += backend.compactions;
+            stats.compaction_secs += backend.compaction_secs;
+        }
+        Ok(stats)
+    }
+}
@@ -0,0 +1,407 @@
+use crate::network::{
+    decode_cmd, Authenticator, Replication, ServerContext, SessionClientCommand, SessionProcessor,
+    SessionServerResp,
+};
+use crate::thread_pool::ThreadPool;
+use crate::{KvStoreError, KvsEngine, Result};
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use mio::net::TcpStream;
+use mio::{Events, Poll, PollOpt, Ready, Registration, SetReadiness, Token};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{self, SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+// usize::MAX is reserved by mio
+const WAKER: Token = Token(usize::MAX - 1);
+
+/// Event driven server, sessions are multiplexed on a few I/O threads
+/// and only the engine operations are handed to the thread pool
+pub struct KvsReactorServer<E: KvsEngine, T: ThreadPool> {
+    store: E,
+    pool: Arc<T>,
+    io_threads: usize,
+    rx: Option<Receiver<()>>,
+    tx: Option<Sender<()>>,
+    ctx: ServerContext,
+}
+
+// mostly `Done`, boxing it would allocate for every command
+#[allow(clippy::large_enum_variant)]
+enum ReactorMessage<E: KvsEngine> {
+    // new connection from the acceptor
+    Connect(net::TcpStream),
+    // engine op finished on the pool
+    Done(Token, SessionProcessor<E>, SessionServerResp),
+    Shutdown,
+}
+
+// Handle used to talk to an I/O thread
+struct ReactorHandle<E: KvsEngine> {
+    sender: Sender<ReactorMessage<E>>,
+    waker: SetReadiness,
+}
+
+impl<E: KvsEngine> Clone for ReactorHandle<E> {
+    fn clone(&self) -> Self {
+        ReactorHandle {
+            sender: self.sender.clone(),
+            waker: self.waker.clone(),
+        }
+    }
+}
+
+impl<E: KvsEngine> ReactorHandle<E> {
+    fn send(&self, msg: ReactorMessage<E>) -> Result<()> {
+        self.sender
+            .send(msg)
+            .map_err(|e| KvStoreError::Io(io::Error::other(format!("{}", e))))?;
+        self.waker.set_readiness(Ready::readable())?;
+        Ok(())
+    }
+}
+
+struct Connection<E: KvsEngine> {
+    stream: TcpStream,
+    // None while a command of this connection is running on the pool
+    processor: Option<SessionProcessor<E>>,
+    rbuf: Vec<u8>,
+    wbuf: Vec<u8>,
+    // peer is done sending, the buffered commands are still answered
+    eof: bool,
+    // broken socket or protocol error, dropped right away
+    closing: bool,
+}
+
+struct Reactor<E: KvsEngine, T: ThreadPool> {
+    poll: Poll,
+    _registration: Registration,
+    handle: ReactorHandle<E>,
+    receiver: Receiver<ReactorMessage<E>>,
+    pool: Arc<T>,
+    store: E,
+    ctx: ServerContext,
+    conns: HashMap<Token, Connection<E>>,
+    next_token: usize,
+}
+
+impl<E: KvsEngine, T: ThreadPool + Send + Sync + 'static> KvsReactorServer<E, T> {
+    pub fn new(store: E, pool: T) -> Self {
+        let pool = Arc::new(pool);
+        let ctx = ServerContext::on_pool(&pool);
+        KvsReactorServer {
+            store,
+            pool,
+            io_threads: 1,
+            rx: None,
+            tx: None,
+            ctx,
+        }
+    }
+    pub fn io_threads(mut self, io_threads: usize) -> Self {
+        self.io_threads = io_threads.max(1);
+        self
+    }
+    pub fn rx(mut self, rx: Receiver<()>) -> Self {
+        self.rx = Some(rx);
+        self
+    }
+    pub fn tx(mut self, tx: Sender<()>) -> Self {
+        self.tx = Some(tx);
+        self
+    }
+    pub fn auth(mut self, auth: Authenticator) -> Self {
+        self.ctx.auth = Some(Arc::new(auth));
+        self
+    }
+    // Share the replication role, e.g. with a `Follower`
+    pub fn replication(mut self, replication: Arc<Replication>) -> Self {
+        self.ctx.replication = replication;
+        self
+    }
+    // What the sessions share, e.g. for `metrics::render`
+    pub fn context(&self) -> ServerContext {
+        self.ctx.clone()
+    }
+    pub fn listen(&mut self, addr: SocketAddr) -> Result<()> {
+        let listener = TcpListener::bind(addr)?;
+
+        let mut handles = vec![];
+        let mut workers: Vec<JoinHandle<()>> = vec![];
+        for _ in 0..self.io_threads {
+            let mut reactor =
+                Reactor::new(self.store.clone(), self.ctx.clone(), self.pool.clone())?;
+            handles.push(reactor.handle.clone());
+            workers.push(thread::spawn(move || {
+                reactor.run().expect("error reactor");
+            }));
+        }
+
+        let mut next = 0;
+        for stream in listener.incoming() {
+            // no receiver costs nothing
+            if let Some(r) = &self.rx {
+                // non block recv to reduce overhead
+                if r.try_recv().is_ok() {
+                    break;
+                }
+            }
+            match stream {
+                Ok(s) => {
+                    // round robin between I/O threads
+                    handles[next].send(ReactorMessage::Connect(s))?;
+                    next = (next + 1) % handles.len();
+                }
+                Err(e) => {
+                    return Err(KvStoreError::Io(io::Error::other(format!("{}", e))));
+                }
+            }
+        }
+
+        for handle in &handles {
+            handle.send(ReactorMessage::Shutdown)?;
+        }
+        for worker in workers {
+            worker.join().expect("failed to join reactor");
+        }
+
+        if let Some(t) = &self.tx {
+            // shutdown ack, bench case can now safely go to next iter
+            t.send(()).expect("failed to send shutdown back");
+        }
+        Ok(())
+    }
+}
+
+impl<E: KvsEngine, T: ThreadPool + Send + Sync + 'static> Reactor<E, T> {
+    fn new(store: E, ctx: ServerContext, pool: Arc<T>) -> Result<Self> {
+        let poll = Poll::new()?;
+        let (registration, waker) = Registration::new2();
+        poll.register(&registration, WAKER, Ready::readable(), PollOpt::edge())?;
+        let (sender, receiver) = unbounded();
+        Ok(Reactor {
+            poll,
+            _registration: registration,
+            handle: ReactorHandle { sender, waker },
+            receiver,
+            pool,
+            store,
+            ctx,
+            conns: HashMap::new(),
+            next_token: 0,
+        })
+    }
+
+    fn run(&mut self) -> Result<()> {
+        let mut events = Events::with_capacity(1024);
+        loop {
+            self.poll.poll(&mut events, None)?;
+            for event in events.iter() {
+                let token = event.token();
+                if token == WAKER {
+                    // reset before draining, so no wake up gets lost
+                    self.handle.waker.set_readiness(Ready::empty())?;
+                    while let Ok(msg) = self.receiver.try_recv() {
+                        match msg {
+                            ReactorMessage::Connect(s) => self.accept(s),
+                            ReactorMessage::Done(token, processor, resp) => {
+                                self.complete(token, processor, resp)
+                            }
+                            ReactorMessage::Shutdown => return Ok(()),
+                        }
+                    }
+                    continue;
+                }
+                if event.readiness().is_readable() {
+                    self.read(token);
+                }
+                if event.readiness().is_writable() {
+                    self.flush(token);
+                }
+                self.advance(token);
+            }
+        }
+    }
+
+    // A connection which can't be set up, e.g. out of fds, is dropped alone
+    fn accept(&mut self, s: net::TcpStream) {
+        if let Err(e) = self.register(s) {
+            log::warn!("Failed to accept a connection: {}", e);
+        }
+    }
+
+    fn register(&mut self, s: net::TcpStream) -> Result<()> {
+        let stream = TcpStream::from_stream(s)?;
+        let token = Token(self.next_token);
+        self.next_token = (self.next_token + 1) % WAKER.0;
+        self.poll.register(
+            &stream,
+            token,
+            Ready::readable() | Ready::writable(),
+            PollOpt::edge(),
+        )?;
+        self.conns.insert(
+            token,
+            Connection {
+                stream,
+                processor: Some(SessionProcessor::with_context(
+                    self.store.clone(),
+                    self.ctx.clone(),
+                )),
+                rbuf: vec![],
+                wbuf: vec![],
+                eof: false,
+                closing: false,
+            },
+        );
+        Ok(())
+    }
+
+    fn complete(&mut self, token: Token, processor: SessionProcessor<E>, resp: SessionServerResp) {
+        if let Some(conn) = self.conns.get_mut(&token) {
+            conn.processor = Some(processor);
+            conn.respond(&resp);
+        }
+        self.flush(token);
+        self.advance(token);
+    }
+
+    fn read(&mut self, token: Token) {
+        if let Some(conn) = self.conns.get_mut(&token) {
+            let mut buf = [0u8; 1024];
+            loop {
+                match conn.stream.read(&mut buf) {
+                    Ok(0) => {
+                        conn.eof = true;
+                        break;
+                    }
+                    Ok(len) => conn.rbuf.extend_from_slice(&buf[..len]),
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => {
+                        conn.closing = true;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    fn flush(&mut self, token: Token) {
+        if let Some(conn) = self.conns.get_mut(&token) {
+            while !conn.wbuf.is_empty() {
+                match conn.stream.write(&conn.wbuf) {
+                    // the peer is gone
+                    Ok(0) => {
+                        conn.closing = true;
+                        conn.wbuf.clear();
+                        break;
+                    }
+                    Ok(len) => {
+                        conn.wbuf.drain(..len);
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => {
+                        conn.closing = true;
+                        conn.wbuf.clear();
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    // dispatch buffered commands, or drop the connection once it is done.
+    // A peer which stopped sending still gets the answers to what it sent
+    fn advance(&mut self, token: Token) {
+        loop {
+            let conn = match self.conns.get_mut(&token) {
+                Some(conn) => conn,
+                None => return,
+            };
+            if conn.closing {
+                self.close(token);
+                return;
+            }
+            let mut processor = match conn.processor.take() {
+                Some(processor) => processor,
+                // a command is still in flight
+                None => return,
+            };
+            let quit = processor.should_quit();
+            let cmd = if quit { None } else { conn.next_cmd() };
+            let cmd = match cmd {
+                Some(cmd) => cmd,
+                None => {
+                    conn.processor = Some(processor);
+                    // nothing more to answer, done once the answers are out
+                    if conn.closing || (conn.wbuf.is_empty() && (quit || conn.eof)) {
+                        self.close(token);
+                    }
+                    return;
+                }
+            };
+            let (name, start) = (cmd.name(), Instant::now());
+            if cmd.is_engine_op() {
+                let (handle, priority) = (self.handle.clone(), cmd.priority());
+                // the processor comes back if the pool turns the command down
+                let slot = Arc::new(Mutex::new(Some((processor, cmd))));
+                let job = slot.clone();
+                let spawned = self.pool.try_spawn_with_priority(priority, move || {
+                    if let Some((mut processor, cmd)) = job.lock().unwrap().take() {
+                        let resp = processor.process(cmd);
+                        processor.record(name, start, resp.is_error());
+                        // the reactor may be gone during shutdown
+                        let _ = handle.send(ReactorMessage::Done(token, processor, resp));
+                    }
+                });
+                if let Err(e) = spawned {
+                    let rejected = slot.lock().unwrap().take();
+                    if let Some((processor, _)) = rejected {
+                        conn.processor = Some(processor);
+                        conn.respond(&SessionServerResp::ERR((&e).into()));
+                        self.flush(token);
+                        continue;
+                    }
+                }
+                return;
+            }
+            let resp = processor.process(cmd);
+            processor.record(name, start, resp.is_error());
+            conn.processor = Some(processor);
+            conn.respond(&resp);
+            self.flush(token);
+        }
+    }
+
+    fn close(&mut self, token: Token) {
+        if let Some(conn) = self.conns.remove(&token) {
+            // the socket is closed on drop anyway
+            let _ = self.poll.deregister(&conn.stream);
+        }
+    }
+}
+
+impl<E: KvsEngine> Connection<E> {
+    // parse one complete command from the read buffer
+    fn next_cmd(&mut self) -> Option<SessionClientCommand> {
+        match decode_cmd(&mut self.rbuf) {
+            Ok(cmd) => cmd,
+            Err(_) => {
+                self.rbuf.clear();
+                self.closing = true;
+                None
+            }
+        }
+    }
+
+    fn respond(&mut self, resp: &SessionServerResp) {
+        match serde_json::to_vec(resp) {
+            Ok(bytes) => self.wbuf.extend_from_slice(&bytes),
+            Err(_) => self.closing = true,
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use crate::network::{KvsClient, SessionClientCommand, SessionServerResp, WatchEvent, WatchHub};
+use crate::{KvStoreError, KvsEngine, Result};
+use crossbeam::channel::{bounded, Receiver, Sender};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// how often a follower blocked on the leader checks for promotion
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const RECONNECT_DELAY: Duration = Duration::from_millis(200);
+// sent by an idle leader, a follower missing three gives up on it
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+// writes queued for a follower, one further behind is dropped and resyncs
+const FOLLOWER_BACKLOG: usize = 10_000;
+
+// A write shipped from the leader to its followers
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ReplicationEvent {
+    Set(String, String),
+    Remove(String),
+    // the snapshot is complete, live writes follow
+    SnapshotDone,
+    Heartbeat,
+}
+
+// Snapshot of the store and the writes that follow it
+pub type Subscription = (Vec<(String, String)>, Receiver<ReplicationEvent>);
+
+/// Replication role of a server, shared by all its sessions
+///
+/// Every server can be followed, followers are read-only until promoted.
+pub struct Replication {
+    read_only: AtomicBool,
+    // shared by the writes while nobody follows, held across a write
+    // and its shipping once somebody does
+    order: RwLock<()>,
+    followers: Mutex<Vec<Sender<ReplicationEvent>>>,
+}
+
+impl Default for Replication {
+    fn default() -> Self {
+        Replication::leader()
+    }
+}
+
+impl Replication {
+    pub fn leader() -> Self {
+        Replication {
+            read_only: AtomicBool::new(false),
+            order: RwLock::new(()),
+            followers: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn follower() -> Self {
+        Replication {
+            read_only: AtomicBool::new(true),
+            order: RwLock::new(()),
+            followers: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::SeqCst)
+    }
+
+    // Accept writes from clients, the follower thread stops on its next poll
+    pub fn promote(&self) {
+        self.read_only.store(false, Ordering::SeqCst);
+    }
+
+    pub fn follower_count(&self) -> usize {
+        self.followers.lock().unwrap().len()
+    }
+
+    // Apply a write and ship it. With followers one write goes at a time,
+    // so every follower sees the order the engine saw
+    pub fn write<F>(&self, event: ReplicationEvent, apply: F) -> Result<()>
+    where
+        F: FnOnce() -> Result<()>,
+    {
+        let unordered = self.order.read().unwrap();
+        if self.followers.lock().unwrap().is_empty() {
+            return apply();
+        }
+        drop(unordered);
+        let _order = self.order.write().unwrap();
+        apply()?;
+        self.followers
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.try_send(event.clone()).is_ok());
+        Ok(())
+    }
+
+    // Snapshot of the store plus every write after it. Writes go on on the
+    // side, the ones racing the scan may show up in both, which replays fine
+    pub fn subscribe<E: KvsEngine>(&self, store: &E) -> Result<Subscription> {
+        let (tx, rx) = bounded(FOLLOWER_BACKLOG);
+        {
+            // the writes not shipped are done before the snapshot starts
+            let _order = self.order.write().unwrap();
+            self.followers.lock().unwrap().push(tx);
+        }
+        let snapshot = store.scan(String::new())?;
+        Ok((snapshot, rx))
+    }
+}
+
+/// Keeps a local store in sync with a leader until promoted,
+/// reconnecting whenever the leader goes away
+pub struct Follower<E: KvsEngine> {
+    leader: SocketAddr,
+    store: E,
+    replication: Arc<Replication>,
+    watch: Option<Arc<WatchHub>>,
+    credentials: Option<(String, String)>,
+}
+
+impl<E: KvsEngine> Follower<E> {
+    pub fn new(leader: SocketAddr, store: E, replication: Arc<Replication>) -> Self {
+        Follower {
+            leader,
+            store,
+            replication,
+            watch: None,
+            credentials: None,
+        }
+    }
+
+    // Tell the server's watchers about the replicated writes
+    pub fn watch(mut self, watch: Arc<WatchHub>) -> Self {
+        self.watch = Some(watch);
+        self
+    }
+
+    // Log in to a leader with authentication
+    pub fn with_credentials(mut self, user: &str, password: &str) -> Self {
+        self.credentials = Some((user.to_owned(), password.to_owned()));
+        self
+    }
+
+    pub fn spawn(self) -> JoinHandle<()> {
+        thread::spawn(move || self.run())
+    }
+
+    pub fn run(&self) {
+        while self.replication.is_read_only() {
+            if self.sync().is_err() {
+                thread::sleep(RECONNECT_DELAY);
+            }
+        }
+    }
+
+    fn sync(&self) -> Result<()> {
+        let mut client = KvsClient::new(self.leader)?;
+        if let Some((user, password)) = &self.credentials {
+            client = client.with_credentials(user, password);
+        }
+        client.handshake()?;
+        client.set_timeout(Some(POLL_INTERVAL))?;
+        client.send(&SessionClientCommand::Replicate)?;
+
+        // keys of the snapshot, local keys missing from it are stale
+        let mut snapshot = Some(HashSet::new());
+        let mut last_seen = Instant::now();
+        while self.replication.is_read_only() {
+            let event = match client.recv() {
+                Ok(SessionServerResp::Replicated(event)) => event,
+                Ok(resp) => return Err(resp.into_error()),
+                Err(KvStoreError::Timeout) if last_seen.elapsed() < HEARTBEAT_INTERVAL * 3 => {
+                    continue
+                }
+                Err(e) => return Err(e),
+            };
+            last_seen = Instant::now();
+            match event {
+                ReplicationEvent::SnapshotDone => {
+                    if let Some(keys) = snapshot.take() {
+                        for (k, _) in self.store.scan(String::new())? {
+                            if !keys.contains(&k) {
+                                self.apply(ReplicationEvent::Remove(k))?;
+                            }
+                        }
+                    }
+                }
+                event => {
+                    if let (Some(keys), ReplicationEvent::Set(k, _)) = (&mut snapshot, &event) {
+                        keys.insert(k.clone());
+                    }
+                    self.apply(event)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Go through `Replication::write` so our own followers get it too
+    fn apply(&self, event: ReplicationEvent) -> Result<()> {
+        let (res, changed) = match event.clone() {
+            ReplicationEvent::Set(k, v) => (
+                self.replication
+                    .write(event, || self.store.set(k.clone(), v.clone())),
+                WatchEvent::Set(k, v),
+            ),
+            ReplicationEvent::Remove(k) => (
+                self.replication
+                    .write(event, || self.store.remove(k.clone())),
+                WatchEvent::Remove(k),
+            ),
+            ReplicationEvent::SnapshotDone | ReplicationEvent::Heartbeat => return Ok(()),
+        };
+        if let (Ok(()), Some(watch)) = (&res, &self.watch) {
+            watch.publish(&changed);
+        }
+        match res {
+            // already gone, e.g. replayed after a reconnect
+            Err(KvStoreError::KeyNotFound) => Ok(()),
+            res => res,
+        }
+    }
+}
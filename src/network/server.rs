@@ -1,24 +1,41 @@
-use crate::network::Session;
+use crate::network::{
+    Authenticator, KvsStream, Replication, ServerContext, Session, SessionServerResp, WatchHub,
+};
 use crate::thread_pool::ThreadPool;
 use crate::{KvStoreError, KvsEngine, Result};
-use crossbeam::channel::{Receiver, Sender};
-use std::io;
-use std::net::{SocketAddr, TcpListener, TcpStream};
+use crossbeam::channel::{bounded, Receiver, Sender};
+use rustls::ServerConfig;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// How long a turned down client gets to send its first request
+const REJECT_READ_TIMEOUT: Duration = Duration::from_millis(100);
+// Turned down clients waiting for their answer, more are hung up on right away
+const REJECT_BACKLOG: usize = 64;
 
 pub struct KvsServer<E: KvsEngine, T: ThreadPool> {
     store: E,
-    pool: T,
+    pool: Arc<T>,
     rx: Option<Receiver<()>>,
     tx: Option<Sender<()>>,
+    tls: Option<Arc<ServerConfig>>,
+    ctx: ServerContext,
 }
 
-impl<E: KvsEngine, T: ThreadPool> KvsServer<E, T> {
+impl<E: KvsEngine, T: ThreadPool + Send + Sync + 'static> KvsServer<E, T> {
     pub fn new(store: E, pool: T) -> Self {
+        let pool = Arc::new(pool);
+        let ctx = ServerContext::on_pool(&pool);
         KvsServer {
             store,
             pool,
             rx: None,
             tx: None,
+            tls: None,
+            ctx,
         }
     }
     pub fn rx(mut self, rx: Receiver<()>) -> Self {
@@ -29,29 +46,73 @@ impl<E: KvsEngine, T: ThreadPool> KvsServer<E, T> {
         self.tx = Some(tx);
         self
     }
+    // Serve TLS only
+    pub fn tls(mut self, config: Arc<ServerConfig>) -> Self {
+        self.tls = Some(config);
+        self
+    }
+    // Require a login in the handshake, keys are then checked against the user's ACL
+    pub fn auth(mut self, auth: Authenticator) -> Self {
+        self.ctx.auth = Some(Arc::new(auth));
+        self
+    }
+    // Share the replication role, e.g. with a `Follower`
+    pub fn replication(mut self, replication: Arc<Replication>) -> Self {
+        self.ctx.replication = replication;
+        self
+    }
+    // Share the watchers, e.g. with a `Follower`
+    pub fn watch(mut self, watch: Arc<WatchHub>) -> Self {
+        self.ctx.watch = watch;
+        self
+    }
+    // What the sessions share, e.g. for `metrics::render`
+    pub fn context(&self) -> ServerContext {
+        self.ctx.clone()
+    }
     pub fn listen(&mut self, addr: SocketAddr) -> Result<()> {
         let listener = TcpListener::bind(addr)?;
+        // one thread answers the rejections so waiting for the request mustn't hold up accepting
+        let (rejects, queued) = bounded::<(KvsStream, SessionServerResp)>(REJECT_BACKLOG);
+        thread::spawn(move || {
+            for (s, error) in queued {
+                let _ = reject(s, &error);
+            }
+        });
 
         for stream in listener.incoming() {
             // no receiver costs nothing
             if let Some(r) = &self.rx {
                 // non block recv to reduce overhead
-                if let Ok(_) = r.try_recv() {
+                if r.try_recv().is_ok() {
                     break;
                 }
             }
             match stream {
                 Ok(s) => {
+                    let s = match &self.tls {
+                        Some(config) => KvsStream::server_tls(s, config),
+                        None => KvsStream::Plain(s),
+                    };
                     let store = self.store.clone();
-                    self.pool.spawn(|| {
-                        handle(s, store).expect("error session");
-                    })
+                    let ctx = self.ctx.clone();
+                    // the stream comes back if the pool turns the session down
+                    let slot = Arc::new(Mutex::new(Some(s)));
+                    let session = slot.clone();
+                    let spawned = self.pool.try_spawn(move || {
+                        if let Some(s) = session.lock().unwrap().take() {
+                            handle(s, store, ctx).expect("error session");
+                        }
+                    });
+                    if let Err(e) = spawned {
+                        if let Some(s) = slot.lock().unwrap().take() {
+                            // a full backlog drops the connection
+                            let _ = rejects.try_send((s, SessionServerResp::ERR((&e).into())));
+                        }
+                    }
                 }
                 Err(e) => {
-                    return Err(KvStoreError::Io(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("{}", e),
-                    )));
+                    return Err(KvStoreError::Io(io::Error::other(format!("{}", e))));
                 }
             }
         }
@@ -64,11 +125,33 @@ impl<E: KvsEngine, T: ThreadPool> KvsServer<E, T> {
     }
 }
 
-pub fn handle<E: KvsEngine>(stream: TcpStream, store: E) -> Result<()> {
-    let mut store = store;
-    let mut session = Session::new(stream, &mut store);
+// Answer the first request of a session with `resp` and hang up
+fn reject(mut stream: KvsStream, resp: &SessionServerResp) -> Result<()> {
+    // closing on unread data would reset the connection before the client reads the answer
+    stream.tcp().set_read_timeout(Some(REJECT_READ_TIMEOUT))?;
+    let _ = stream.read(&mut [0; 4096]);
+    stream.write_all(serde_json::to_string(resp)?.as_bytes())?;
+    stream.flush()?;
+    Ok(())
+}
+
+pub fn handle<E: KvsEngine, S: Into<KvsStream>>(
+    stream: S,
+    store: E,
+    ctx: ServerContext,
+) -> Result<()> {
+    let mut session = Session::with_context(stream, store, ctx);
     while !session.should_quit() {
         session.poll()?;
+        if session.is_replicating() {
+            // keep the pool for short lived sessions
+            thread::spawn(move || session.replicate());
+            break;
+        }
+        if session.is_watching() {
+            thread::spawn(move || session.watch());
+            break;
+        }
     }
     Ok(())
 }
@@ -0,0 +1,260 @@
+use crate::network::{KvsClient, SessionClientCommand, SessionServerResp};
+use crate::{KvStoreError, Result};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::net::SocketAddr;
+
+const DEFAULT_VNODES: u32 = 100;
+
+// 64 bit FNV-1a, stable across processes and platforms unlike `DefaultHasher`.
+// Similar short keys land close together with plain FNV, the murmur3
+// finalizer spreads them over the ring.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in bytes {
+        hash ^= u64::from(*b);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+/// Consistent hash ring, every server owns `vnodes` points on it
+///
+/// A key belongs to the first point at or after its hash, so adding or
+/// removing a server only moves the keys next to its points.
+#[derive(Clone)]
+pub struct HashRing {
+    vnodes: u32,
+    points: BTreeMap<u64, SocketAddr>,
+    nodes: BTreeSet<SocketAddr>,
+}
+
+impl HashRing {
+    pub fn new(vnodes: u32) -> Self {
+        HashRing {
+            vnodes: vnodes.max(1),
+            points: BTreeMap::new(),
+            nodes: BTreeSet::new(),
+        }
+    }
+
+    pub fn add(&mut self, node: SocketAddr) {
+        if !self.nodes.insert(node) {
+            return;
+        }
+        for i in 0..self.vnodes {
+            self.points
+                .insert(hash(format!("{}#{}", node, i).as_bytes()), node);
+        }
+    }
+
+    pub fn remove(&mut self, node: SocketAddr) {
+        if self.nodes.remove(&node) {
+            self.points.retain(|_, n| *n != node);
+        }
+    }
+
+    // None when the ring is empty
+    pub fn node(&self, key: &str) -> Option<SocketAddr> {
+        let point = hash(key.as_bytes());
+        self.points
+            .range(point..)
+            .next()
+            .or_else(|| self.points.iter().next())
+            .map(|(_, node)| *node)
+    }
+
+    pub fn nodes(&self) -> Vec<SocketAddr> {
+        self.nodes.iter().cloned().collect()
+    }
+}
+
+/// Client spreading the keyspace over several `kvs-server`s
+///
+/// Keys are routed with a `HashRing`, scans and `get_many` fan out to every
+/// server involved. Connections are opened on first use.
+pub struct ShardedKvsClient {
+    ring: HashRing,
+    clients: HashMap<SocketAddr, KvsClient>,
+    credentials: Option<(String, String)>,
+}
+
+impl ShardedKvsClient {
+    pub fn new(addrs: &[SocketAddr]) -> Self {
+        ShardedKvsClient::with_vnodes(addrs, DEFAULT_VNODES)
+    }
+
+    pub fn with_vnodes(addrs: &[SocketAddr], vnodes: u32) -> Self {
+        let mut ring = HashRing::new(vnodes);
+        for addr in addrs {
+            ring.add(*addr);
+        }
+        ShardedKvsClient {
+            ring,
+            clients: HashMap::new(),
+            credentials: None,
+        }
+    }
+
+    // Log in to every server with the same user
+    pub fn with_credentials(mut self, user: &str, password: &str) -> Self {
+        self.credentials = Some((user.to_owned(), password.to_owned()));
+        self
+    }
+
+    pub fn ring(&self) -> &HashRing {
+        &self.ring
+    }
+
+    // Server owning `key`
+    pub fn node(&self, key: &str) -> Result<SocketAddr> {
+        self.ring
+            .node(key)
+            .ok_or_else(|| KvStoreError::InvalidRequest("No servers to shard on".to_owned()))
+    }
+
+    fn client(&mut self, addr: SocketAddr) -> Result<&mut KvsClient> {
+        if !self.clients.contains_key(&addr) {
+            let mut client = KvsClient::new(addr)?;
+            if let Some((user, password)) = &self.credentials {
+                client = client.with_credentials(user, password);
+            }
+            client.handshake()?;
+            self.clients.insert(addr, client);
+        }
+        Ok(self.clients.get_mut(&addr).unwrap())
+    }
+
+    pub fn set(&mut self, k: String, v: String) -> Result<()> {
+        let addr = self.node(&k)?;
+        self.client(addr)?.set(k, v)
+    }
+
+    pub fn get(&mut self, k: String) -> Result<Option<String>> {
+        let addr = self.node(&k)?;
+        self.client(addr)?.get(k)
+    }
+
+    pub fn remove(&mut self, k: String) -> Result<()> {
+        let addr = self.node(&k)?;
+        self.client(addr)?.remove(k)
+    }
+
+    // Values in the order of `keys`, the reads to one server are pipelined
+    pub fn get_many(&mut self, keys: &[String]) -> Result<Vec<Option<String>>> {
+        let mut by_node: BTreeMap<SocketAddr, Vec<usize>> = BTreeMap::new();
+        for (i, k) in keys.iter().enumerate() {
+            by_node.entry(self.node(k)?).or_default().push(i);
+        }
+        let mut values = vec![None; keys.len()];
+        for (addr, indices) in by_node {
+            let res = get_pipelined(self.client(addr)?, keys, &indices, &mut values);
+            match res {
+                Ok(Ok(())) => {}
+                // every response was read, the connection is fine
+                Ok(Err(e)) => return Err(e),
+                // responses may still be on their way, don't reuse it
+                Err(e) => {
+                    self.clients.remove(&addr);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    // Pairs under `prefix` from every server, sorted by key
+    pub fn scan(&mut self, prefix: String) -> Result<Vec<(String, String)>> {
+        let mut pairs = vec![];
+        for addr in self.ring.nodes() {
+            pairs.extend(self.client(addr)?.scan(prefix.clone())?);
+        }
+        pairs.sort();
+        Ok(pairs)
+    }
+
+    /// Put `addr` on the ring and move the keys it now owns over to it,
+    /// returns the number of keys moved
+    pub fn add_node(&mut self, addr: SocketAddr) -> Result<usize> {
+        let others = self.ring.nodes();
+        self.ring.add(addr);
+        let mut moved = 0;
+        for node in others {
+            moved += self.migrate(node)?;
+        }
+        Ok(moved)
+    }
+
+    /// Take `addr` off the ring and move all its keys to the remaining
+    /// servers, returns the number of keys moved
+    pub fn remove_node(&mut self, addr: SocketAddr) -> Result<usize> {
+        if self.ring.nodes() == vec![addr] {
+            return Err(KvStoreError::InvalidRequest(
+                "Can't remove the last server".to_owned(),
+            ));
+        }
+        self.ring.remove(addr);
+        let moved = self.migrate(addr)?;
+        if let Some(mut client) = self.clients.remove(&addr) {
+            let _ = client.quit();
+        }
+        Ok(moved)
+    }
+
+    /// Move every misplaced key to its owner, e.g. after an interrupted
+    /// migration or when the servers were filled without sharding
+    pub fn rebalance(&mut self) -> Result<usize> {
+        let mut moved = 0;
+        for node in self.ring.nodes() {
+            moved += self.migrate(node)?;
+        }
+        Ok(moved)
+    }
+
+    // Copy the keys `from` doesn't own anymore to their owner, then remove
+    // them from `from`. A key is on both servers in between, never on none.
+    // Writes to the moving keys from other clients may be lost.
+    fn migrate(&mut self, from: SocketAddr) -> Result<usize> {
+        let pairs = self.client(from)?.scan(String::new())?;
+        let mut moved = 0;
+        for (k, v) in pairs {
+            let owner = self.node(&k)?;
+            if owner == from {
+                continue;
+            }
+            self.client(owner)?.set(k.clone(), v)?;
+            self.client(from)?.remove(k)?;
+            moved += 1;
+        }
+        Ok(moved)
+    }
+}
+
+// Send the gets for `indices` at once, then read every response. The outer
+// error is a broken connection, the inner one the first failed get
+fn get_pipelined(
+    client: &mut KvsClient,
+    keys: &[String],
+    indices: &[usize],
+    values: &mut [Option<String>],
+) -> Result<Result<()>> {
+    for &i in indices {
+        client.send(&SessionClientCommand::Get(keys[i].clone()))?;
+    }
+    let mut res = Ok(());
+    for &i in indices {
+        match client.recv()? {
+            SessionServerResp::Value(v) => values[i] = Some(v),
+            SessionServerResp::NotFound => values[i] = None,
+            resp => {
+                if res.is_ok() {
+                    res = Err(resp.into_error());
+                }
+            }
+        }
+    }
+    Ok(res)
+}
@@ -0,0 +1,212 @@
+use crate::thread_pool::PoolStats;
+use crate::EngineStats;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Reads the stats of the pool a server runs its sessions on
+pub type PoolProbe = Arc<dyn Fn() -> PoolStats + Send + Sync>;
+
+// Upper bounds of the latency histogram buckets, in seconds
+pub const LATENCY_BUCKETS: &[f64] = &[
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+];
+
+/// Requests of one command
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandStats {
+    pub count: u64,
+    // answered with an error
+    pub errors: u64,
+    pub latency_secs: f64,
+    // per `LATENCY_BUCKETS` bound, slower requests are only in `count`
+    buckets: Vec<u64>,
+}
+
+impl CommandStats {
+    // Requests at most as slow as each `LATENCY_BUCKETS` bound
+    pub fn cumulative_buckets(&self) -> Vec<(f64, u64)> {
+        let mut total = 0;
+        LATENCY_BUCKETS
+            .iter()
+            .enumerate()
+            .map(|(i, bound)| {
+                total += self.buckets.get(i).cloned().unwrap_or(0);
+                (*bound, total)
+            })
+            .collect()
+    }
+}
+
+// Names of the commands counted apart, see `SessionClientCommand::name`
+const COMMANDS: &[&str] = &[
+    "get",
+    "handshake",
+    "info",
+    "invalid",
+    "promote",
+    "quit",
+    "replicate",
+    "rm",
+    "scan",
+    "set",
+    "watch",
+];
+
+// Lock free counters behind a `CommandStats`
+struct CommandCounters {
+    count: AtomicU64,
+    errors: AtomicU64,
+    latency_nanos: AtomicU64,
+    buckets: Vec<AtomicU64>,
+}
+
+impl CommandCounters {
+    fn new() -> Self {
+        CommandCounters {
+            count: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            latency_nanos: AtomicU64::new(0),
+            buckets: LATENCY_BUCKETS.iter().map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    fn record(&self, elapsed: Duration, failed: bool) {
+        let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        let nanos = elapsed.as_secs() * 1_000_000_000 + u64::from(elapsed.subsec_nanos());
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.latency_nanos.fetch_add(nanos, Ordering::Relaxed);
+        if failed {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|bound| secs <= *bound) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn snapshot(&self) -> CommandStats {
+        CommandStats {
+            count: self.count.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            latency_secs: self.latency_nanos.load(Ordering::Relaxed) as f64 / 1e9,
+            buckets: self
+                .buckets
+                .iter()
+                .map(|b| b.load(Ordering::Relaxed))
+                .collect(),
+        }
+    }
+}
+
+/// Counters shared by the sessions of a server
+pub struct ServerStats {
+    started: Instant,
+    connections: AtomicU64,
+    active_sessions: AtomicU64,
+    commands: AtomicU64,
+    // one per `COMMANDS` entry
+    per_command: Vec<CommandCounters>,
+}
+
+impl Default for ServerStats {
+    fn default() -> Self {
+        ServerStats {
+            started: Instant::now(),
+            connections: AtomicU64::new(0),
+            active_sessions: AtomicU64::new(0),
+            commands: AtomicU64::new(0),
+            per_command: COMMANDS.iter().map(|_| CommandCounters::new()).collect(),
+        }
+    }
+}
+
+impl ServerStats {
+    pub fn new() -> Self {
+        ServerStats::default()
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    // Sessions ever opened
+    pub fn connections(&self) -> u64 {
+        self.connections.load(Ordering::Relaxed)
+    }
+
+    pub fn active_sessions(&self) -> u64 {
+        self.active_sessions.load(Ordering::Relaxed)
+    }
+
+    pub fn commands(&self) -> u64 {
+        self.commands.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn session_opened(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        self.active_sessions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn session_closed(&self) {
+        self.active_sessions.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    // The commands seen so far, sorted by name
+    pub fn command_stats(&self) -> Vec<(&'static str, CommandStats)> {
+        COMMANDS
+            .iter()
+            .zip(&self.per_command)
+            .map(|(name, counters)| (*name, counters.snapshot()))
+            .filter(|(_, stats)| stats.count > 0)
+            .collect()
+    }
+
+    pub(crate) fn record(&self, command: &'static str, elapsed: Duration, failed: bool) {
+        self.commands.fetch_add(1, Ordering::Relaxed);
+        if let Some(i) = COMMANDS.iter().position(|name| *name == command) {
+            self.per_command[i].record(elapsed, failed);
+        }
+    }
+}
+
+/// Answer to the `Info` command
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ServerInfo {
+    pub version: String,
+    // followers refuse writes
+    pub read_only: bool,
+    pub uptime_secs: u64,
+    pub connections: u64,
+    pub active_sessions: u64,
+    pub commands: u64,
+    pub engine: EngineStats,
+    // None when the server doesn't run on a `ThreadPool`
+    pub pool: Option<PoolStats>,
+}
+
+// One `name: value` per line
+impl fmt::Display for ServerInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "version: {}", self.version)?;
+        writeln!(f, "read_only: {}", self.read_only)?;
+        writeln!(f, "uptime_secs: {}", self.uptime_secs)?;
+        writeln!(f, "connections: {}", self.connections)?;
+        writeln!(f, "active_sessions: {}", self.active_sessions)?;
+        writeln!(f, "commands: {}", self.commands)?;
+        writeln!(f, "engine: {}", self.engine.engine)?;
+        writeln!(f, "keys: {}", self.engine.keys)?;
+        writeln!(f, "disk_bytes: {}", self.engine.disk_bytes)?;
+        write!(f, "dead_bytes: {}", self.engine.dead_bytes)?;
+        if let Some(pool) = &self.pool {
+            writeln!(f)?;
+            writeln!(f, "pool_threads: {}", pool.threads)?;
+            writeln!(f, "pool_queued: {}", pool.queued)?;
+            writeln!(f, "pool_active: {}", pool.active)?;
+            writeln!(f, "pool_completed: {}", pool.completed)?;
+            write!(f, "pool_panics: {}", pool.panics)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+use crate::{KvStoreError, Result};
+use rustls::{ClientConfig, ClientSession, ServerConfig, ServerSession, StreamOwned};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::Arc;
+use webpki::DNSNameRef;
+
+// A connection between client and server, plaintext or TLS
+pub enum KvsStream {
+    Plain(TcpStream),
+    TlsServer(Box<StreamOwned<ServerSession, TcpStream>>),
+    TlsClient(Box<StreamOwned<ClientSession, TcpStream>>),
+}
+
+impl KvsStream {
+    pub fn server_tls(sock: TcpStream, config: &Arc<ServerConfig>) -> Self {
+        let session = ServerSession::new(config);
+        KvsStream::TlsServer(Box::new(StreamOwned::new(session, sock)))
+    }
+
+    pub fn client_tls(sock: TcpStream, config: &Arc<ClientConfig>, domain: &str) -> Result<Self> {
+        let domain = DNSNameRef::try_from_ascii_str(domain).map_err(|_| {
+            KvStoreError::Tls(rustls::TLSError::General(format!(
+                "invalid domain name {}",
+                domain
+            )))
+        })?;
+        let session = ClientSession::new(config, domain);
+        Ok(KvsStream::TlsClient(Box::new(StreamOwned::new(
+            session, sock,
+        ))))
+    }
+
+    // The underlying socket, for timeouts and shutdown
+    pub fn tcp(&self) -> &TcpStream {
+        match self {
+            KvsStream::Plain(s) => s,
+            KvsStream::TlsServer(s) => &s.sock,
+            KvsStream::TlsClient(s) => &s.sock,
+        }
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.tcp().shutdown(how)
+    }
+}
+
+impl From<TcpStream> for KvsStream {
+    fn from(sock: TcpStream) -> Self {
+        KvsStream::Plain(sock)
+    }
+}
+
+impl Read for KvsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            KvsStream::Plain(s) => s.read(buf),
+            KvsStream::TlsServer(s) => s.read(buf),
+            KvsStream::TlsClient(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for KvsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            KvsStream::Plain(s) => s.write(buf),
+            KvsStream::TlsServer(s) => s.write(buf),
+            KvsStream::TlsClient(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            KvsStream::Plain(s) => s.flush(),
+            KvsStream::TlsServer(s) => s.flush(),
+            KvsStream::TlsClient(s) => s.flush(),
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use crate::{KvStoreError, Result};
+use rustls::internal::pemfile;
+use rustls::{
+    AllowAnyAuthenticatedClient, Certificate, ClientConfig, NoClientAuth, PrivateKey,
+    RootCertStore, ServerConfig, TLSError,
+};
+use std::fs::File;
+use std::io::{BufReader, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Arc;
+
+// Server side TLS, client certificates signed by `ca` are required when given
+pub fn server_config(cert: &Path, key: &Path, ca: Option<&Path>) -> Result<Arc<ServerConfig>> {
+    let verifier = match ca {
+        Some(ca) => AllowAnyAuthenticatedClient::new(load_roots(ca)?),
+        None => NoClientAuth::new(),
+    };
+    let mut config = ServerConfig::new(verifier);
+    config.set_single_cert(load_certs(cert)?, load_key(key)?)?;
+    Ok(Arc::new(config))
+}
+
+// Client side TLS, the server is verified against `ca`,
+// `cert_key` is the client certificate for mutual TLS
+pub fn client_config(ca: &Path, cert_key: Option<(&Path, &Path)>) -> Result<Arc<ClientConfig>> {
+    let mut config = ClientConfig::new();
+    config.root_store = load_roots(ca)?;
+    if let Some((cert, key)) = cert_key {
+        config.set_single_client_cert(load_certs(cert)?, load_key(key)?);
+    }
+    Ok(Arc::new(config))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    match pemfile::certs(&mut reader) {
+        Ok(ref certs) if certs.is_empty() => Err(pem_error("no certificate", path)),
+        Ok(certs) => Ok(certs),
+        Err(_) => Err(pem_error("invalid certificate", path)),
+    }
+}
+
+// PKCS8 first, then RSA keys
+fn load_key(path: &Path) -> Result<PrivateKey> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut keys = pemfile::pkcs8_private_keys(&mut reader)
+        .map_err(|_| pem_error("invalid private key", path))?;
+    if keys.is_empty() {
+        reader.seek(SeekFrom::Start(0))?;
+        keys = pemfile::rsa_private_keys(&mut reader)
+            .map_err(|_| pem_error("invalid private key", path))?;
+    }
+    keys.pop().ok_or_else(|| pem_error("no private key", path))
+}
+
+fn load_roots(path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots
+            .add(&cert)
+            .map_err(|e| pem_error(&format!("invalid ca certificate ({:?})", e), path))?;
+    }
+    Ok(roots)
+}
+
+fn pem_error(msg: &str, path: &Path) -> KvStoreError {
+    KvStoreError::Tls(TLSError::General(format!("{}: {}", msg, path.display())))
+}
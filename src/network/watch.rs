@@ -0,0 +1,149 @@
+use crate::network::{KvsClient, SessionServerResp};
+use crate::Result;
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use serde::{Deserialize, Serialize};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+// What a session watches
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum WatchFilter {
+    Key(String),
+    Prefix(String),
+}
+
+impl WatchFilter {
+    pub fn matches(&self, key: &str) -> bool {
+        match self {
+            WatchFilter::Key(k) => k == key,
+            WatchFilter::Prefix(prefix) => key.starts_with(prefix.as_str()),
+        }
+    }
+
+    // Key the ACL is checked against
+    pub fn key(&self) -> &str {
+        match self {
+            WatchFilter::Key(k) | WatchFilter::Prefix(k) => k,
+        }
+    }
+}
+
+// A change pushed to a watching client
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum WatchEvent {
+    Set(String, String),
+    Remove(String),
+    // keeps an idle watch from looking dead, never handed to the user
+    Heartbeat,
+}
+
+impl WatchEvent {
+    fn key(&self) -> Option<&str> {
+        match self {
+            WatchEvent::Set(k, _) | WatchEvent::Remove(k) => Some(k),
+            WatchEvent::Heartbeat => None,
+        }
+    }
+}
+
+/// Fans the writes of a server out to the sessions watching them
+#[derive(Default)]
+pub struct WatchHub {
+    watchers: Mutex<Vec<Watch>>,
+}
+
+struct Watch {
+    filter: WatchFilter,
+    tx: Sender<WatchEvent>,
+    // set once the receiving session is gone
+    closed: Arc<AtomicBool>,
+}
+
+/// The writes a session watches, leaves the hub when dropped
+pub struct WatchReceiver {
+    rx: Receiver<WatchEvent>,
+    closed: Arc<AtomicBool>,
+}
+
+impl WatchHub {
+    pub fn new() -> Self {
+        WatchHub::default()
+    }
+
+    pub fn subscribe(&self, filter: WatchFilter) -> WatchReceiver {
+        let (tx, rx) = unbounded();
+        let closed = Arc::new(AtomicBool::new(false));
+        self.watchers.lock().unwrap().push(Watch {
+            filter,
+            tx,
+            closed: closed.clone(),
+        });
+        WatchReceiver { rx, closed }
+    }
+
+    // Called right after the engine applied the write, watchers that
+    // went away are dropped here whether they match or not
+    pub fn publish(&self, event: &WatchEvent) {
+        let key = event.key();
+        self.watchers.lock().unwrap().retain(|w| {
+            if w.closed.load(Ordering::SeqCst) {
+                return false;
+            }
+            match key {
+                Some(key) if w.filter.matches(key) => w.tx.send(event.clone()).is_ok(),
+                _ => true,
+            }
+        });
+    }
+
+    pub fn watcher_count(&self) -> usize {
+        self.watchers.lock().unwrap().len()
+    }
+}
+
+impl Deref for WatchReceiver {
+    type Target = Receiver<WatchEvent>;
+    fn deref(&self) -> &Receiver<WatchEvent> {
+        &self.rx
+    }
+}
+
+impl Drop for WatchReceiver {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Changes seen by a `KvsClient` turned into a watch, ends on the first error
+pub struct Watcher {
+    client: KvsClient,
+    done: bool,
+}
+
+impl Watcher {
+    pub(crate) fn new(client: KvsClient) -> Self {
+        Watcher {
+            client,
+            done: false,
+        }
+    }
+}
+
+impl Iterator for Watcher {
+    type Item = Result<WatchEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let res = match self.client.recv() {
+                Ok(SessionServerResp::Changed(WatchEvent::Heartbeat)) => continue,
+                Ok(SessionServerResp::Changed(event)) => Ok(event),
+                Ok(resp) => Err(resp.into_error()),
+                Err(e) => Err(e),
+            };
+            self.done = res.is_err();
+            return Some(res);
+        }
+        None
+    }
+}
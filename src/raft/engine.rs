@@ -0,0 +1,74 @@
+use crate::raft::{Payload, RaftConfig, RaftNode};
+use crate::{EngineStats, KvsEngine, Result};
+
+/// `KvsEngine` whose writes go through the Raft log
+///
+/// Writes return once committed and applied. Reads are served by the leader
+/// from its engine once a heartbeat round confirmed it still leads, so a
+/// deposed leader can't answer with stale data. Followers refuse everything with
+/// `KvStoreError::NotLeader`.
+#[derive(Clone)]
+pub struct RaftKvsEngine<E: KvsEngine> {
+    node: RaftNode,
+    engine: E,
+}
+
+impl<E: KvsEngine> RaftKvsEngine<E> {
+    pub fn start(config: RaftConfig, engine: E) -> Result<Self> {
+        let node = RaftNode::start(config, engine.clone())?;
+        Ok(RaftKvsEngine { node, engine })
+    }
+
+    pub fn node(&self) -> &RaftNode {
+        &self.node
+    }
+
+    fn check_leader(&self) -> Result<()> {
+        if self.node.is_leader() {
+            Ok(())
+        } else {
+            Err(self.node.not_leader())
+        }
+    }
+
+    // Still the leader for a majority, and every write committed
+    // before the read is applied by then
+    fn read_barrier(&self) -> Result<()> {
+        self.check_leader()?;
+        self.node.read_index()
+    }
+}
+
+impl<E: KvsEngine> KvsEngine for RaftKvsEngine<E> {
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.node.propose(Payload::Set(key, value))
+    }
+
+    fn get(&self, key: String) -> Result<Option<String>> {
+        self.read_barrier()?;
+        self.engine.get(key)
+    }
+
+    // a missing key fails when the entry is applied
+    fn remove(&self, key: String) -> Result<()> {
+        self.node.propose(Payload::Remove(key))
+    }
+
+    fn scan(&self, prefix: String) -> Result<Vec<(String, String)>> {
+        self.read_barrier()?;
+        self.engine.scan(prefix)
+    }
+
+    // local numbers, useful on followers too
+    fn stats(&self) -> Result<EngineStats> {
+        self.engine.stats()
+    }
+
+    fn maintenance_due(&self) -> bool {
+        self.engine.maintenance_due()
+    }
+
+    fn maintain(&self) -> Result<()> {
+        self.engine.maintain()
+    }
+}
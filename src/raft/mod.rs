@@ -0,0 +1,143 @@
+//! Raft consensus on top of a `KvsEngine`
+//!
+//! Writes are appended to a replicated log and only reach the engine once
+//! a majority of the cluster stored them. The log is compacted into engine
+//! snapshots, and nodes are added or removed one at a time.
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+mod engine;
+mod node;
+mod storage;
+mod transport;
+
+pub use engine::RaftKvsEngine;
+pub use node::{RaftNode, RaftStatus, Role};
+pub use transport::{add_node, remove_node};
+
+pub type NodeId = u64;
+
+// Raft address of every voting member
+pub type Members = BTreeMap<NodeId, SocketAddr>;
+
+// What a log entry does once committed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Payload {
+    // appended by a new leader to commit the entries of earlier terms
+    Noop,
+    Set(String, String),
+    Remove(String),
+    // membership changes take effect as soon as they are appended
+    AddNode(NodeId, SocketAddr),
+    RemoveNode(NodeId),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    pub index: u64,
+    pub term: u64,
+    pub payload: Payload,
+}
+
+// The engine content up to `last_index`, replaces that part of the log
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Snapshot {
+    pub last_index: u64,
+    pub last_term: u64,
+    pub members: Members,
+    pub data: Vec<(String, String)>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum RaftRequest {
+    Vote {
+        term: u64,
+        candidate: NodeId,
+        last_index: u64,
+        last_term: u64,
+    },
+    Append {
+        term: u64,
+        leader: NodeId,
+        prev_index: u64,
+        prev_term: u64,
+        entries: Vec<Entry>,
+        commit: u64,
+    },
+    InstallSnapshot {
+        term: u64,
+        leader: NodeId,
+        snapshot: Snapshot,
+    },
+    // sent by operators to the leader
+    ChangeMembers(Payload),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum RaftResponse {
+    Vote {
+        term: u64,
+        granted: bool,
+    },
+    // `last_index` is the match on success, a hint where to retry otherwise
+    Append {
+        term: u64,
+        success: bool,
+        last_index: u64,
+    },
+    InstallSnapshot {
+        term: u64,
+        last_index: u64,
+    },
+    Changed(std::result::Result<(), String>),
+}
+
+/// Settings of one node
+pub struct RaftConfig {
+    pub id: NodeId,
+    pub addr: SocketAddr,
+    // log, vote and snapshots live here
+    pub dir: PathBuf,
+    // members to start a new cluster with, ignored once the log exists
+    pub members: Members,
+    pub heartbeat_interval: Duration,
+    // a random timeout in this range avoids split votes
+    pub election_timeout: (Duration, Duration),
+    // applied entries kept in the log before taking a snapshot
+    pub snapshot_threshold: u64,
+}
+
+impl RaftConfig {
+    pub fn new(id: NodeId, addr: SocketAddr, dir: PathBuf) -> Self {
+        RaftConfig {
+            id,
+            addr,
+            dir,
+            members: Members::new(),
+            heartbeat_interval: Duration::from_millis(50),
+            election_timeout: (Duration::from_millis(300), Duration::from_millis(600)),
+            snapshot_threshold: 10_000,
+        }
+    }
+    // Bootstrap a cluster of this node and `peers`
+    pub fn peers(mut self, peers: Members) -> Self {
+        self.members = peers;
+        self.members.insert(self.id, self.addr);
+        self
+    }
+    pub fn heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = interval;
+        self
+    }
+    pub fn election_timeout(mut self, min: Duration, max: Duration) -> Self {
+        self.election_timeout = (min, max);
+        self
+    }
+    pub fn snapshot_threshold(mut self, threshold: u64) -> Self {
+        self.snapshot_threshold = threshold.max(1);
+        self
+    }
+}
@@ -0,0 +1,831 @@
+use crate::raft::storage::RaftStorage;
+use crate::raft::transport;
+use crate::raft::{
+    Entry, Members, NodeId, Payload, RaftConfig, RaftRequest, RaftResponse, Snapshot,
+};
+use crate::{KvStoreError, KvsEngine, Result};
+use crossbeam::channel::{bounded, unbounded, Receiver, RecvTimeoutError, Sender};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// entries per append request
+const MAX_BATCH: usize = 256;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RaftStatus {
+    pub id: NodeId,
+    pub role: Role,
+    pub term: u64,
+    pub leader: Option<NodeId>,
+    pub commit: u64,
+    pub applied: u64,
+    pub members: Members,
+}
+
+pub(crate) enum Message {
+    Propose(Payload, Sender<Result<()>>),
+    ReadIndex(Sender<Result<()>>),
+    Request(RaftRequest, Sender<RaftResponse>),
+    Response(NodeId, RaftResponse),
+    // the last request to this peer failed
+    Unreachable(NodeId),
+    Shutdown,
+}
+
+/// Handle to a running Raft node, cheap to clone
+#[derive(Clone)]
+pub struct RaftNode {
+    addr: SocketAddr,
+    inbox: Sender<Message>,
+    status: Arc<Mutex<RaftStatus>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl RaftNode {
+    /// Recover the node from `config.dir` and join the cluster
+    pub fn start<E: KvsEngine>(config: RaftConfig, engine: E) -> Result<Self> {
+        let storage = RaftStorage::open(&config.dir, &config.members)?;
+        let listener = TcpListener::bind(config.addr)?;
+        let (inbox, receiver) = unbounded();
+        let core = RaftCore::new(config, storage, engine, inbox.clone(), receiver);
+        let node = RaftNode {
+            addr: listener.local_addr()?,
+            inbox,
+            status: core.status.clone(),
+            stopped: Arc::new(AtomicBool::new(false)),
+        };
+        transport::serve(listener, node.clone());
+        thread::spawn(move || core.run());
+        Ok(node)
+    }
+
+    /// Append `payload` to the log, returns once it is applied
+    pub fn propose(&self, payload: Payload) -> Result<()> {
+        let (tx, rx) = bounded(1);
+        self.send(Message::Propose(payload, tx))?;
+        rx.recv().map_err(|_| stopped())?
+    }
+
+    /// Returns once a majority confirmed this node still leads and every
+    /// write committed before the call is applied, without touching the log
+    pub fn read_index(&self) -> Result<()> {
+        let (tx, rx) = bounded(1);
+        self.send(Message::ReadIndex(tx))?;
+        rx.recv().map_err(|_| stopped())?
+    }
+
+    pub(crate) fn request(&self, req: RaftRequest) -> Result<RaftResponse> {
+        let (tx, rx) = bounded(1);
+        self.send(Message::Request(req, tx))?;
+        rx.recv().map_err(|_| stopped())
+    }
+
+    fn send(&self, msg: Message) -> Result<()> {
+        self.inbox.send(msg).map_err(|_| stopped())
+    }
+
+    pub fn status(&self) -> RaftStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.status.lock().unwrap().role == Role::Leader
+    }
+
+    // Error telling clients where to go instead
+    pub fn not_leader(&self) -> KvStoreError {
+        let status = self.status();
+        not_leader(status.leader, &status.members)
+    }
+
+    pub fn shutdown(&self) {
+        if !self.stopped.swap(true, Ordering::SeqCst) {
+            let _ = self.inbox.send(Message::Shutdown);
+            // wake up the listener
+            let _ = TcpStream::connect(self.addr);
+        }
+    }
+
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+}
+
+fn stopped() -> KvStoreError {
+    KvStoreError::Rpc("Raft node stopped".to_owned())
+}
+
+fn not_leader(leader: Option<NodeId>, members: &Members) -> KvStoreError {
+    let msg = match leader.and_then(|id| members.get(&id).map(|addr| (id, addr))) {
+        Some((id, addr)) => format!("Not the leader, node {} at {} is", id, addr),
+        None => "Not the leader, no leader elected yet".to_owned(),
+    };
+    KvStoreError::NotLeader(msg)
+}
+
+// A read waiting for its heartbeat round, see `RaftNode::read_index`
+struct PendingRead {
+    // the commit index when the read came in
+    index: u64,
+    round: u64,
+    reply: Sender<Result<()>>,
+}
+
+// State machine of one node, owned by a single thread and fed through `Message`s
+struct RaftCore<E: KvsEngine> {
+    id: NodeId,
+    config: RaftConfig,
+    storage: RaftStorage,
+    engine: E,
+    role: Role,
+    leader: Option<NodeId>,
+    members: Members,
+    commit: u64,
+    applied: u64,
+    votes: HashSet<NodeId>,
+    // leader only, per follower
+    next_index: HashMap<NodeId, u64>,
+    match_index: HashMap<NodeId, u64>,
+    // one request in flight per peer at most
+    inflight: HashSet<NodeId>,
+    peers: HashMap<NodeId, Sender<RaftRequest>>,
+    // proposals waiting for their entry to be applied, with the term they were made in
+    waiters: HashMap<u64, (u64, Sender<Result<()>>)>,
+    // leader only, heartbeat rounds confirming the leadership to reads
+    round: u64,
+    sent_round: HashMap<NodeId, u64>,
+    acked_round: HashMap<NodeId, u64>,
+    reads: Vec<PendingRead>,
+    election_deadline: Instant,
+    heartbeat_due: Instant,
+    last_heard: Instant,
+    rng: u64,
+    inbox: Sender<Message>,
+    receiver: Receiver<Message>,
+    status: Arc<Mutex<RaftStatus>>,
+}
+
+impl<E: KvsEngine> RaftCore<E> {
+    fn new(
+        config: RaftConfig,
+        storage: RaftStorage,
+        engine: E,
+        inbox: Sender<Message>,
+        receiver: Receiver<Message>,
+    ) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos() as u64)
+            .unwrap_or(0);
+        let snapshot_index = storage.snapshot().last_index;
+        let now = Instant::now();
+        let mut core = RaftCore {
+            id: config.id,
+            storage,
+            engine,
+            role: Role::Follower,
+            leader: None,
+            members: Members::new(),
+            // entries after the snapshot are applied again once known committed,
+            // replaying sets and removes over a newer engine state ends up the same
+            commit: snapshot_index,
+            applied: snapshot_index,
+            votes: HashSet::new(),
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            inflight: HashSet::new(),
+            peers: HashMap::new(),
+            waiters: HashMap::new(),
+            round: 0,
+            sent_round: HashMap::new(),
+            acked_round: HashMap::new(),
+            reads: vec![],
+            election_deadline: now,
+            heartbeat_due: now,
+            last_heard: now,
+            rng: seed ^ config.id.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1,
+            inbox,
+            receiver,
+            status: Arc::new(Mutex::new(RaftStatus {
+                id: config.id,
+                role: Role::Follower,
+                term: 0,
+                leader: None,
+                commit: 0,
+                applied: 0,
+                members: Members::new(),
+            })),
+            config,
+        };
+        core.refresh_members();
+        core.reset_election();
+        core.publish();
+        core
+    }
+
+    fn run(mut self) {
+        loop {
+            let deadline = match self.role {
+                Role::Leader => self.heartbeat_due,
+                _ => self.election_deadline,
+            };
+            let now = Instant::now();
+            let timeout = if deadline > now {
+                deadline - now
+            } else {
+                Duration::from_millis(0)
+            };
+            let res = match self.receiver.recv_timeout(timeout) {
+                Ok(Message::Shutdown) | Err(RecvTimeoutError::Disconnected) => break,
+                Ok(msg) => self.handle(msg),
+                Err(RecvTimeoutError::Timeout) => Ok(()),
+            };
+            // the log can't be trusted after a failed write
+            if res.and_then(|_| self.tick()).is_err() {
+                break;
+            }
+            self.serve_reads();
+            self.publish();
+        }
+        for (_, (_, reply)) in self.waiters.drain() {
+            let _ = reply.send(Err(stopped()));
+        }
+        for read in self.reads.drain(..) {
+            let _ = read.reply.send(Err(stopped()));
+        }
+        self.role = Role::Follower;
+        self.leader = None;
+        self.publish();
+    }
+
+    fn handle(&mut self, msg: Message) -> Result<()> {
+        match msg {
+            Message::Propose(payload, reply) => self.propose(payload, reply),
+            Message::ReadIndex(reply) => {
+                self.read_index(reply);
+                Ok(())
+            }
+            Message::Request(req, reply) => {
+                let resp = match req {
+                    RaftRequest::Vote {
+                        term,
+                        candidate,
+                        last_index,
+                        last_term,
+                    } => self.on_vote(term, candidate, last_index, last_term)?,
+                    RaftRequest::Append {
+                        term,
+                        leader,
+                        prev_index,
+                        prev_term,
+                        entries,
+                        commit,
+                    } => self.on_append(term, leader, prev_index, prev_term, entries, commit)?,
+                    RaftRequest::InstallSnapshot {
+                        term,
+                        leader,
+                        snapshot,
+                    } => self.on_install_snapshot(term, leader, snapshot)?,
+                    // handled by the transport through `propose`
+                    RaftRequest::ChangeMembers(_) => {
+                        RaftResponse::Changed(Err("Unexpected request".to_owned()))
+                    }
+                };
+                let _ = reply.send(resp);
+                Ok(())
+            }
+            Message::Response(peer, resp) => {
+                self.inflight.remove(&peer);
+                self.on_response(peer, resp)
+            }
+            Message::Unreachable(peer) => {
+                self.inflight.remove(&peer);
+                Ok(())
+            }
+            Message::Shutdown => Ok(()),
+        }
+    }
+
+    fn tick(&mut self) -> Result<()> {
+        let now = Instant::now();
+        match self.role {
+            Role::Leader if now >= self.heartbeat_due => self.broadcast(),
+            Role::Leader => {}
+            _ if now >= self.election_deadline => self.start_election()?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn propose(&mut self, payload: Payload, reply: Sender<Result<()>>) -> Result<()> {
+        if self.role != Role::Leader {
+            let _ = reply.send(Err(not_leader(self.leader, &self.members)));
+            return Ok(());
+        }
+        let rejected = match payload {
+            Payload::AddNode(_, _) | Payload::RemoveNode(_)
+                if self.storage.last_change() > self.commit =>
+            {
+                Some("A membership change is in progress")
+            }
+            Payload::RemoveNode(id) if !self.members.contains_key(&id) => Some("Unknown node"),
+            _ => None,
+        };
+        if let Some(msg) = rejected {
+            let _ = reply.send(Err(KvStoreError::InvalidRequest(msg.to_owned())));
+            return Ok(());
+        }
+        let index = self.append_local(payload)?;
+        self.waiters.insert(index, (self.storage.term(), reply));
+        self.broadcast();
+        self.advance_commit()
+    }
+
+    // Queue a read for the next heartbeat round, see `serve_reads`
+    fn read_index(&mut self, reply: Sender<Result<()>>) {
+        if self.role != Role::Leader {
+            let _ = reply.send(Err(not_leader(self.leader, &self.members)));
+            return;
+        }
+        self.round += 1;
+        self.reads.push(PendingRead {
+            index: self.commit,
+            round: self.round,
+            reply,
+        });
+        self.broadcast();
+    }
+
+    // Answer the reads a majority acked a round for, once this leader
+    // committed an entry of its term and applied what they wait for
+    fn serve_reads(&mut self) {
+        if self.reads.is_empty() {
+            return;
+        }
+        let term = self.storage.term();
+        if self.role != Role::Leader || self.storage.term_at(self.commit) != Some(term) {
+            return;
+        }
+        let mut reads = std::mem::take(&mut self.reads);
+        reads.retain(|read| {
+            let confirmed = self.has_quorum(|id| {
+                id == self.id || self.acked_round.get(&id).is_some_and(|r| *r >= read.round)
+            });
+            if !confirmed || self.applied < read.index {
+                return true;
+            }
+            let _ = read.reply.send(Ok(()));
+            false
+        });
+        self.reads = reads;
+    }
+
+    fn append_local(&mut self, payload: Payload) -> Result<u64> {
+        let entry = Entry {
+            index: self.storage.last_index() + 1,
+            term: self.storage.term(),
+            payload,
+        };
+        let index = entry.index;
+        self.storage.append(&[entry])?;
+        self.refresh_members();
+        Ok(index)
+    }
+
+    fn on_vote(
+        &mut self,
+        term: u64,
+        candidate: NodeId,
+        last_index: u64,
+        last_term: u64,
+    ) -> Result<RaftResponse> {
+        // a leader is alive, e.g. the candidate missed its own removal
+        if self.leader.is_some() && self.last_heard.elapsed() < self.config.election_timeout.0 {
+            return Ok(RaftResponse::Vote {
+                term: self.storage.term(),
+                granted: false,
+            });
+        }
+        if term > self.storage.term() {
+            self.become_follower(term, None)?;
+        }
+        let up_to_date = last_term > self.storage.last_term()
+            || (last_term == self.storage.last_term() && last_index >= self.storage.last_index());
+        let free = match self.storage.voted_for() {
+            None => true,
+            Some(id) => id == candidate,
+        };
+        let granted = term == self.storage.term() && up_to_date && free;
+        if granted {
+            self.storage.set_hard_state(term, Some(candidate))?;
+            self.reset_election();
+        }
+        Ok(RaftResponse::Vote {
+            term: self.storage.term(),
+            granted,
+        })
+    }
+
+    fn on_append(
+        &mut self,
+        term: u64,
+        leader: NodeId,
+        mut prev_index: u64,
+        mut prev_term: u64,
+        mut entries: Vec<Entry>,
+        commit: u64,
+    ) -> Result<RaftResponse> {
+        if term < self.storage.term() {
+            return Ok(RaftResponse::Append {
+                term: self.storage.term(),
+                success: false,
+                last_index: self.storage.last_index(),
+            });
+        }
+        self.follow(term, leader)?;
+
+        // the snapshot covers committed entries only, they agree by definition
+        let snapshot_index = self.storage.snapshot().last_index;
+        if prev_index < snapshot_index {
+            entries.retain(|e| e.index > snapshot_index);
+            prev_index = snapshot_index;
+            prev_term = self.storage.snapshot().last_term;
+        }
+        if self.storage.term_at(prev_index) != Some(prev_term) {
+            let hint = self.storage.last_index().min(prev_index.saturating_sub(1));
+            return Ok(RaftResponse::Append {
+                term,
+                success: false,
+                last_index: hint,
+            });
+        }
+
+        let last_new = prev_index + entries.len() as u64;
+        let mut new = vec![];
+        for (i, entry) in entries.iter().enumerate() {
+            match self.storage.term_at(entry.index) {
+                Some(t) if t == entry.term => continue,
+                Some(_) => self.storage.truncate(entry.index)?,
+                None => {}
+            }
+            new = entries.split_off(i);
+            break;
+        }
+        if !new.is_empty() {
+            self.storage.append(&new)?;
+            self.refresh_members();
+        }
+        // a stale or short append never takes back what is committed
+        let commit = self.commit.max(commit.min(last_new));
+        if commit > self.commit {
+            self.commit = commit;
+            self.apply()?;
+        }
+        Ok(RaftResponse::Append {
+            term,
+            success: true,
+            last_index: last_new,
+        })
+    }
+
+    fn on_install_snapshot(
+        &mut self,
+        term: u64,
+        leader: NodeId,
+        snapshot: Snapshot,
+    ) -> Result<RaftResponse> {
+        if term < self.storage.term() {
+            return Ok(RaftResponse::InstallSnapshot {
+                term: self.storage.term(),
+                last_index: 0,
+            });
+        }
+        self.follow(term, leader)?;
+        let last_index = snapshot.last_index;
+        if last_index > self.commit {
+            self.restore(&snapshot.data)?;
+            self.storage.save_snapshot(snapshot)?;
+            self.commit = last_index;
+            self.applied = last_index;
+            self.refresh_members();
+        }
+        Ok(RaftResponse::InstallSnapshot { term, last_index })
+    }
+
+    fn on_response(&mut self, peer: NodeId, resp: RaftResponse) -> Result<()> {
+        let (term, matched) = match resp {
+            RaftResponse::Vote { term, granted } => {
+                if term > self.storage.term() {
+                    return self.become_follower(term, None);
+                }
+                if self.role == Role::Candidate && term == self.storage.term() && granted {
+                    self.votes.insert(peer);
+                    if self.has_quorum(|id| self.votes.contains(&id)) {
+                        self.become_leader()?;
+                    }
+                }
+                return Ok(());
+            }
+            RaftResponse::Append {
+                term,
+                success,
+                last_index,
+            } => (
+                term,
+                if success {
+                    Ok(last_index)
+                } else {
+                    Err(last_index)
+                },
+            ),
+            RaftResponse::InstallSnapshot { term, last_index } => (term, Ok(last_index)),
+            RaftResponse::Changed(_) => return Ok(()),
+        };
+        if term > self.storage.term() {
+            return self.become_follower(term, None);
+        }
+        if self.role != Role::Leader || term != self.storage.term() {
+            return Ok(());
+        }
+        // the peer takes us for its leader, even if the logs don't agree yet
+        if let Some(&round) = self.sent_round.get(&peer) {
+            self.acked_round.insert(peer, round);
+        }
+        let next = self.next_index.get(&peer).cloned().unwrap_or(1);
+        match matched {
+            Ok(last_index) => {
+                let matched = self.match_index.entry(peer).or_insert(0);
+                *matched = (*matched).max(last_index);
+                let matched = *matched;
+                self.next_index.insert(peer, matched + 1);
+                self.advance_commit()?;
+            }
+            // walk back to where the logs agree
+            Err(hint) => {
+                self.next_index
+                    .insert(peer, (next - 1).min(hint + 1).max(1));
+            }
+        }
+        if self.next_index[&peer] <= self.storage.last_index() {
+            self.send_append(peer);
+        }
+        Ok(())
+    }
+
+    fn follow(&mut self, term: u64, leader: NodeId) -> Result<()> {
+        if term > self.storage.term() || self.role != Role::Follower {
+            self.become_follower(term, Some(leader))?;
+        }
+        self.leader = Some(leader);
+        self.last_heard = Instant::now();
+        self.reset_election();
+        Ok(())
+    }
+
+    fn become_follower(&mut self, term: u64, leader: Option<NodeId>) -> Result<()> {
+        if term > self.storage.term() {
+            self.storage.set_hard_state(term, None)?;
+        }
+        self.role = Role::Follower;
+        self.leader = leader;
+        self.votes.clear();
+        // their entries may still commit, but not through us
+        let err = not_leader(self.leader, &self.members);
+        for (_, (_, reply)) in self.waiters.drain() {
+            let _ = reply.send(Err(KvStoreError::NotLeader(format!("{}", err))));
+        }
+        for read in self.reads.drain(..) {
+            let _ = read
+                .reply
+                .send(Err(KvStoreError::NotLeader(format!("{}", err))));
+        }
+        Ok(())
+    }
+
+    fn start_election(&mut self) -> Result<()> {
+        self.reset_election();
+        // learners wait until a leader adds them
+        if !self.members.contains_key(&self.id) {
+            return Ok(());
+        }
+        let term = self.storage.term() + 1;
+        self.storage.set_hard_state(term, Some(self.id))?;
+        self.role = Role::Candidate;
+        self.leader = None;
+        self.votes.clear();
+        self.votes.insert(self.id);
+        if self.has_quorum(|id| id == self.id) {
+            return self.become_leader();
+        }
+        let last_index = self.storage.last_index();
+        let last_term = self.storage.last_term();
+        for peer in self.peers.values() {
+            let _ = peer.send(RaftRequest::Vote {
+                term,
+                candidate: self.id,
+                last_index,
+                last_term,
+            });
+        }
+        Ok(())
+    }
+
+    fn become_leader(&mut self) -> Result<()> {
+        self.role = Role::Leader;
+        self.leader = Some(self.id);
+        self.next_index.clear();
+        self.match_index.clear();
+        self.sent_round.clear();
+        self.acked_round.clear();
+        let next = self.storage.last_index() + 1;
+        for &peer in self.peers.keys() {
+            self.next_index.insert(peer, next);
+            self.match_index.insert(peer, 0);
+        }
+        self.append_local(Payload::Noop)?;
+        self.broadcast();
+        self.advance_commit()
+    }
+
+    fn broadcast(&mut self) {
+        let peers: Vec<NodeId> = self.peers.keys().cloned().collect();
+        for peer in peers {
+            if !self.inflight.contains(&peer) {
+                self.send_append(peer);
+            }
+        }
+        self.heartbeat_due = Instant::now() + self.config.heartbeat_interval;
+        self.last_heard = Instant::now();
+    }
+
+    fn send_append(&mut self, peer: NodeId) {
+        let term = self.storage.term();
+        let next = *self
+            .next_index
+            .entry(peer)
+            .or_insert(self.storage.last_index() + 1);
+        let snapshot = self.storage.snapshot();
+        let req = if next <= snapshot.last_index {
+            RaftRequest::InstallSnapshot {
+                term,
+                leader: self.id,
+                snapshot: snapshot.clone(),
+            }
+        } else {
+            RaftRequest::Append {
+                term,
+                leader: self.id,
+                prev_index: next - 1,
+                prev_term: self.storage.term_at(next - 1).unwrap_or(0),
+                entries: self.storage.entries_from(next, MAX_BATCH),
+                commit: self.commit,
+            }
+        };
+        if let Some(tx) = self.peers.get(&peer) {
+            if tx.send(req).is_ok() {
+                self.inflight.insert(peer);
+                self.sent_round.insert(peer, self.round);
+            }
+        }
+    }
+
+    // Commit the highest entry of this term stored on a majority
+    fn advance_commit(&mut self) -> Result<()> {
+        let term = self.storage.term();
+        let mut index = self.storage.last_index();
+        while index > self.commit && self.storage.term_at(index) == Some(term) {
+            let stored = |id: NodeId| {
+                id == self.id || self.match_index.get(&id).is_some_and(|m| *m >= index)
+            };
+            if self.has_quorum(stored) {
+                self.commit = index;
+                return self.apply();
+            }
+            index -= 1;
+        }
+        Ok(())
+    }
+
+    fn has_quorum<F: Fn(NodeId) -> bool>(&self, agrees: F) -> bool {
+        let count = self.members.keys().filter(|id| agrees(**id)).count();
+        count * 2 > self.members.len()
+    }
+
+    fn apply(&mut self) -> Result<()> {
+        while self.applied < self.commit {
+            let entry = match self.storage.entry(self.applied + 1) {
+                Some(entry) => entry.clone(),
+                None => break,
+            };
+            let res = match entry.payload.clone() {
+                Payload::Set(k, v) => self.engine.set(k, v),
+                Payload::Remove(k) => self.engine.remove(k),
+                _ => Ok(()),
+            };
+            let res = match res {
+                // the outcome of the command, the same on every node
+                Ok(()) => Ok(()),
+                Err(KvStoreError::KeyNotFound) => Err(KvStoreError::KeyNotFound),
+                // the engine fell behind the log, the node stops with it
+                Err(e) => {
+                    log::error!(
+                        "Raft node {} failed to apply {}: {}",
+                        self.id,
+                        entry.index,
+                        e
+                    );
+                    return Err(e);
+                }
+            };
+            self.applied = entry.index;
+            if let Some((term, reply)) = self.waiters.remove(&entry.index) {
+                let res = if term == entry.term {
+                    res
+                } else {
+                    Err(not_leader(self.leader, &self.members))
+                };
+                let _ = reply.send(res);
+            }
+            // a removed leader hands over once everyone knows
+            if entry.payload == Payload::RemoveNode(self.id) && self.role == Role::Leader {
+                self.become_follower(self.storage.term(), None)?;
+            }
+        }
+        self.compact()
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        if self.applied - self.storage.snapshot().last_index < self.config.snapshot_threshold {
+            return Ok(());
+        }
+        let snapshot = Snapshot {
+            last_index: self.applied,
+            last_term: self.storage.term_at(self.applied).unwrap_or(0),
+            members: self.storage.members_at(self.applied),
+            data: self.engine.scan(String::new())?,
+        };
+        self.storage.save_snapshot(snapshot)
+    }
+
+    // Make the engine hold exactly `data`
+    fn restore(&self, data: &[(String, String)]) -> Result<()> {
+        let keys: HashSet<&String> = data.iter().map(|(k, _)| k).collect();
+        for (k, _) in self.engine.scan(String::new())? {
+            if !keys.contains(&k) {
+                self.engine.remove(k)?;
+            }
+        }
+        for (k, v) in data {
+            self.engine.set(k.clone(), v.clone())?;
+        }
+        Ok(())
+    }
+
+    // Members follow the log, so start or stop talking to peers
+    fn refresh_members(&mut self) {
+        self.members = self.storage.members_at(self.storage.last_index());
+        let members = &self.members;
+        self.peers.retain(|id, _| members.contains_key(id));
+        for (&id, &addr) in &self.members {
+            if id != self.id && !self.peers.contains_key(&id) {
+                let tx = transport::peer(id, addr, self.inbox.clone());
+                self.peers.insert(id, tx);
+            }
+        }
+    }
+
+    fn reset_election(&mut self) {
+        let (min, max) = self.config.election_timeout;
+        let spread = (max - min).as_millis() as u64;
+        // xorshift, good enough to spread timeouts
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        let jitter = if spread == 0 { 0 } else { self.rng % spread };
+        self.election_deadline = Instant::now() + min + Duration::from_millis(jitter);
+    }
+
+    fn publish(&self) {
+        *self.status.lock().unwrap() = RaftStatus {
+            id: self.id,
+            role: self.role,
+            term: self.storage.term(),
+            leader: self.leader,
+            commit: self.commit,
+            applied: self.applied,
+            members: self.members.clone(),
+        };
+    }
+}
@@ -0,0 +1,229 @@
+use crate::raft::{Entry, Members, NodeId, Payload, Snapshot};
+use crate::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Deserialize, Default)]
+struct HardState {
+    term: u64,
+    voted_for: Option<NodeId>,
+}
+
+// Durable part of a node: vote, log and the snapshot the log starts after.
+// Everything is flushed to disk before the node answers a request.
+pub struct RaftStorage {
+    dir: PathBuf,
+    hard_state: HardState,
+    snapshot: Snapshot,
+    // entries after `snapshot.last_index`
+    entries: Vec<Entry>,
+    log: BufWriter<File>,
+}
+
+impl RaftStorage {
+    pub fn open(dir: &Path, members: &Members) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        let hard_state = match File::open(dir.join("state.json")) {
+            Ok(f) => serde_json::from_reader(BufReader::new(f))?,
+            Err(_) => HardState::default(),
+        };
+        let snapshot = match File::open(dir.join("snapshot.json")) {
+            Ok(f) => serde_json::from_reader(BufReader::new(f))?,
+            // brand new node
+            Err(_) => Snapshot {
+                members: members.clone(),
+                ..Snapshot::default()
+            },
+        };
+        let mut entries: Vec<Entry> = vec![];
+        if let Ok(f) = File::open(dir.join("log")) {
+            let stream = serde_json::Deserializer::from_reader(BufReader::new(f)).into_iter();
+            for entry in stream {
+                let entry: Entry = match entry {
+                    Ok(entry) => entry,
+                    // torn write of the last entry
+                    Err(ref e) if e.is_eof() => break,
+                    Err(e) => return Err(e.into()),
+                };
+                if entry.index > snapshot.last_index {
+                    entries.push(entry);
+                }
+            }
+        }
+        let log = open_log(dir)?;
+        let mut storage = RaftStorage {
+            dir: dir.to_owned(),
+            hard_state,
+            snapshot,
+            entries,
+            log,
+        };
+        // drop what the torn tail left behind
+        storage.rewrite_log()?;
+        Ok(storage)
+    }
+
+    pub fn term(&self) -> u64 {
+        self.hard_state.term
+    }
+
+    pub fn voted_for(&self) -> Option<NodeId> {
+        self.hard_state.voted_for
+    }
+
+    pub fn set_hard_state(&mut self, term: u64, voted_for: Option<NodeId>) -> Result<()> {
+        self.hard_state = HardState { term, voted_for };
+        write_durably(
+            &self.dir,
+            "state.json",
+            &serde_json::to_vec(&self.hard_state)?,
+        )
+    }
+
+    pub fn snapshot(&self) -> &Snapshot {
+        &self.snapshot
+    }
+
+    pub fn last_index(&self) -> u64 {
+        self.entries
+            .last()
+            .map_or(self.snapshot.last_index, |e| e.index)
+    }
+
+    pub fn last_term(&self) -> u64 {
+        self.entries
+            .last()
+            .map_or(self.snapshot.last_term, |e| e.term)
+    }
+
+    // None when compacted away or not there yet
+    pub fn term_at(&self, index: u64) -> Option<u64> {
+        if index == self.snapshot.last_index {
+            return Some(self.snapshot.last_term);
+        }
+        self.entry(index).map(|e| e.term)
+    }
+
+    pub fn entry(&self, index: u64) -> Option<&Entry> {
+        if index <= self.snapshot.last_index {
+            return None;
+        }
+        self.entries
+            .get((index - self.snapshot.last_index - 1) as usize)
+    }
+
+    pub fn entries_from(&self, index: u64, max: usize) -> Vec<Entry> {
+        let start =
+            (index.max(self.snapshot.last_index + 1) - self.snapshot.last_index - 1) as usize;
+        self.entries.iter().skip(start).take(max).cloned().collect()
+    }
+
+    pub fn append(&mut self, entries: &[Entry]) -> Result<()> {
+        for entry in entries {
+            serde_json::to_writer(&mut self.log, entry)?;
+        }
+        self.log.flush()?;
+        self.log.get_ref().sync_data()?;
+        self.entries.extend_from_slice(entries);
+        Ok(())
+    }
+
+    // Drop `index` and everything after it, they conflict with the leader
+    pub fn truncate(&mut self, index: u64) -> Result<()> {
+        let keep = index.saturating_sub(self.snapshot.last_index + 1) as usize;
+        self.entries.truncate(keep);
+        self.rewrite_log()
+    }
+
+    // Members as of `index`, the latest membership change at or before it
+    pub fn members_at(&self, index: u64) -> Members {
+        let mut members = self.snapshot.members.clone();
+        for entry in self.entries.iter().take_while(|e| e.index <= index) {
+            match entry.payload {
+                Payload::AddNode(id, addr) => {
+                    members.insert(id, addr);
+                }
+                Payload::RemoveNode(id) => {
+                    members.remove(&id);
+                }
+                _ => {}
+            }
+        }
+        members
+    }
+
+    // Index of the last membership change, 0 if the snapshot holds it
+    pub fn last_change(&self) -> u64 {
+        self.entries
+            .iter()
+            .rev()
+            .find(|e| matches!(e.payload, Payload::AddNode(_, _) | Payload::RemoveNode(_)))
+            .map_or(0, |e| e.index)
+    }
+
+    // Replace the log up to `snapshot.last_index`, keeping the entries after it
+    // when they agree with the snapshot
+    pub fn save_snapshot(&mut self, snapshot: Snapshot) -> Result<()> {
+        write_durably(&self.dir, "snapshot.json", &serde_json::to_vec(&snapshot)?)?;
+
+        if self.term_at(snapshot.last_index) == Some(snapshot.last_term) {
+            let last_index = snapshot.last_index;
+            self.entries.retain(|e| e.index > last_index);
+        } else {
+            self.entries.clear();
+        }
+        self.snapshot = snapshot;
+        self.rewrite_log()
+    }
+
+    fn rewrite_log(&mut self) -> Result<()> {
+        let tmp = self.dir.join("log.tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&tmp)?);
+            for entry in &self.entries {
+                serde_json::to_writer(&mut writer, entry)?;
+            }
+            writer.flush()?;
+            writer.get_ref().sync_data()?;
+        }
+        fs::rename(tmp, self.dir.join("log"))?;
+        sync_dir(&self.dir)?;
+        self.log = open_log(&self.dir)?;
+        Ok(())
+    }
+}
+
+// Replace `dir/name` with `data`, on disk once this returns
+fn write_durably(dir: &Path, name: &str, data: &[u8]) -> Result<()> {
+    let tmp = dir.join(format!("{}.tmp", name));
+    {
+        let mut file = File::create(&tmp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+    }
+    fs::rename(tmp, dir.join(name))?;
+    sync_dir(dir)
+}
+
+// Persist the renames in `dir`
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+// Directories can't be opened for an fsync here
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> Result<()> {
+    Ok(())
+}
+
+fn open_log(dir: &Path) -> Result<BufWriter<File>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join("log"))?;
+    Ok(BufWriter::new(file))
+}
@@ -0,0 +1,141 @@
+use crate::network::decode;
+use crate::raft::node::Message;
+use crate::raft::{NodeId, Payload, RaftNode, RaftRequest, RaftResponse};
+use crate::{KvStoreError, Result};
+use crossbeam::channel::{unbounded, Sender};
+use serde::de::DeserializeOwned;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+const RPC_TIMEOUT: Duration = Duration::from_millis(500);
+// committing a membership change can take a few rounds
+const CHANGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// One JSON message per request and response, like the client protocol
+struct Connection {
+    stream: TcpStream,
+    rbuf: Vec<u8>,
+}
+
+impl Connection {
+    fn open(addr: SocketAddr, timeout: Duration) -> Result<Self> {
+        let stream = TcpStream::connect_timeout(&addr, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        stream.set_nodelay(true)?;
+        Ok(Connection {
+            stream,
+            rbuf: vec![],
+        })
+    }
+
+    fn call(&mut self, req: &RaftRequest) -> Result<RaftResponse> {
+        self.stream.write_all(&serde_json::to_vec(req)?)?;
+        match read_msg(&mut self.stream, &mut self.rbuf)? {
+            Some(resp) => Ok(resp),
+            None => Err(KvStoreError::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed by peer",
+            ))),
+        }
+    }
+}
+
+// None once the peer closed the connection
+fn read_msg<T: DeserializeOwned>(stream: &mut TcpStream, rbuf: &mut Vec<u8>) -> Result<Option<T>> {
+    let mut chunk = [0u8; 4096];
+    loop {
+        if let Some(msg) = decode(rbuf)? {
+            return Ok(Some(msg));
+        }
+        let len = stream.read(&mut chunk)?;
+        if len == 0 {
+            return Ok(None);
+        }
+        rbuf.extend_from_slice(&chunk[..len]);
+    }
+}
+
+// Thread sending requests to one peer in order, every request is
+// answered with a `Response` or `Unreachable` to the node
+pub(crate) fn peer(id: NodeId, addr: SocketAddr, inbox: Sender<Message>) -> Sender<RaftRequest> {
+    let (tx, rx) = unbounded::<RaftRequest>();
+    thread::spawn(move || {
+        let mut conn: Option<Connection> = None;
+        for req in rx {
+            let res = match conn.take() {
+                Some(c) => Ok(c),
+                None => Connection::open(addr, RPC_TIMEOUT),
+            }
+            .and_then(|mut c| c.call(&req).map(|resp| (c, resp)));
+            let msg = match res {
+                Ok((c, resp)) => {
+                    conn = Some(c);
+                    Message::Response(id, resp)
+                }
+                Err(_) => Message::Unreachable(id),
+            };
+            if inbox.send(msg).is_err() {
+                break;
+            }
+        }
+    });
+    tx
+}
+
+pub(crate) fn serve(listener: TcpListener, node: RaftNode) {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            if node.is_stopped() {
+                break;
+            }
+            if let Ok(stream) = stream {
+                let node = node.clone();
+                thread::spawn(move || {
+                    // a broken connection only affects its peer
+                    let _ = handle(stream, node);
+                });
+            }
+        }
+    });
+}
+
+fn handle(mut stream: TcpStream, node: RaftNode) -> Result<()> {
+    let mut rbuf = vec![];
+    while let Some(req) = read_msg(&mut stream, &mut rbuf)? {
+        let resp = match req {
+            // the raft port skips the server's auth, so no writes through it
+            RaftRequest::ChangeMembers(payload @ Payload::AddNode(_, _))
+            | RaftRequest::ChangeMembers(payload @ Payload::RemoveNode(_)) => {
+                RaftResponse::Changed(node.propose(payload).map_err(|e| format!("{}", e)))
+            }
+            RaftRequest::ChangeMembers(_) => {
+                RaftResponse::Changed(Err("Only membership changes are accepted".to_owned()))
+            }
+            req => node.request(req)?,
+        };
+        stream.write_all(&serde_json::to_vec(&resp)?)?;
+    }
+    Ok(())
+}
+
+/// Ask the leader at `leader` to add a node, returns once committed
+pub fn add_node(leader: SocketAddr, id: NodeId, addr: SocketAddr) -> Result<()> {
+    change_members(leader, Payload::AddNode(id, addr))
+}
+
+/// Ask the leader at `leader` to remove a node, returns once committed
+pub fn remove_node(leader: SocketAddr, id: NodeId) -> Result<()> {
+    change_members(leader, Payload::RemoveNode(id))
+}
+
+fn change_members(leader: SocketAddr, payload: Payload) -> Result<()> {
+    let mut conn = Connection::open(leader, CHANGE_TIMEOUT)?;
+    match conn.call(&RaftRequest::ChangeMembers(payload))? {
+        RaftResponse::Changed(Ok(())) => Ok(()),
+        RaftResponse::Changed(Err(e)) => Err(KvStoreError::Rpc(e)),
+        resp => Err(KvStoreError::Rpc(format!("Unexpected response {:?}", resp))),
+    }
+}
@@ -0,0 +1,189 @@
+use crate::thread_pool::{PanicHook, PoolState, ThreadPool};
+use crate::Result;
+use std::any::Any;
+use std::io;
+use std::sync::Arc;
+use std::thread;
+
+type WorkerHook = Arc<dyn Fn(usize) + Send + Sync>;
+
+/// How the workers of a pool are set up, `ThreadPool::new` takes the defaults.
+/// Workers are told apart by their index, from 0
+#[derive(Clone)]
+pub struct ThreadPoolBuilder {
+    threads: u32,
+    name_prefix: Option<String>,
+    stack_size: Option<usize>,
+    start_hook: Option<WorkerHook>,
+    stop_hook: Option<WorkerHook>,
+    panic_hook: Option<PanicHook>,
+    cpus: Vec<usize>,
+}
+
+impl ThreadPoolBuilder {
+    pub fn new(threads: u32) -> Self {
+        ThreadPoolBuilder {
+            threads,
+            name_prefix: None,
+            stack_size: None,
+            start_hook: None,
+            stop_hook: None,
+            panic_hook: None,
+            cpus: Vec::new(),
+        }
+    }
+
+    /// Workers are named `<prefix><index>`
+    pub fn name_prefix(mut self, prefix: &str) -> Self {
+        self.name_prefix = Some(prefix.to_owned());
+        self
+    }
+
+    /// Stack size of the workers in bytes
+    pub fn stack_size(mut self, bytes: usize) -> Self {
+        self.stack_size = Some(bytes);
+        self
+    }
+
+    /// Called on a worker thread with its index before it takes jobs
+    pub fn start_hook<H>(mut self, hook: H) -> Self
+    where
+        H: Fn(usize) + Send + Sync + 'static,
+    {
+        self.start_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Called on a worker thread with its index as it exits
+    pub fn stop_hook<H>(mut self, hook: H) -> Self
+    where
+        H: Fn(usize) + Send + Sync + 'static,
+    {
+        self.stop_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Called with the payload of every job that panicked, on the thread it ran on.
+    /// Panics of jobs spawned with a handle or in a scope go to their spawner instead
+    pub fn panic_hook<H>(mut self, hook: H) -> Self
+    where
+        H: Fn(&(dyn Any + Send)) + Send + Sync + 'static,
+    {
+        self.panic_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Pin worker `i` to the core `cpus[i % cpus.len()]`, only done on Linux
+    pub fn cpu_affinity(mut self, cpus: Vec<usize>) -> Self {
+        self.cpus = cpus;
+        self
+    }
+
+    pub fn threads(&self) -> u32 {
+        self.threads
+    }
+
+    pub fn build<P: ThreadPool>(self) -> Result<P> {
+        P::from_builder(self)
+    }
+
+    pub(super) fn panic_handler(&self) -> Option<PanicHook> {
+        self.panic_hook.clone()
+    }
+
+    pub(super) fn thread_name(&self, index: usize) -> Option<String> {
+        self.name_prefix
+            .as_ref()
+            .map(|prefix| format!("{}{}", prefix, index))
+    }
+
+    // Without the start and stop handlers, they are up to the pool
+    pub(super) fn rayon_builder(&self) -> rayon::ThreadPoolBuilder {
+        let mut builder = rayon::ThreadPoolBuilder::new().num_threads(self.threads as usize);
+        if self.name_prefix.is_some() {
+            let config = self.clone();
+            builder = builder.thread_name(move |index| config.thread_name(index).unwrap());
+        }
+        if let Some(size) = self.stack_size {
+            builder = builder.stack_size(size);
+        }
+        builder
+    }
+
+    // Run first thing on worker `index`
+    pub(super) fn start(&self, index: usize) {
+        if !self.cpus.is_empty() {
+            let cpu = self.cpus[index % self.cpus.len()];
+            if let Err(e) = set_affinity(cpu) {
+                log::warn!("Failed to pin worker {} to cpu {}: {}", index, cpu, e);
+            }
+        }
+        if let Some(hook) = &self.start_hook {
+            hook(index);
+        }
+    }
+
+    // Run last thing on worker `index`
+    pub(super) fn stop(&self, index: usize) {
+        if let Some(hook) = &self.stop_hook {
+            hook(index);
+        }
+    }
+
+    // Start worker `index` of the pool with `state`, running `work`.
+    // Jobs can't unwind out of `work`, see `PoolState::run`
+    pub(super) fn spawn<F>(&self, index: usize, state: Arc<PoolState>, work: F) -> io::Result<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let mut builder = thread::Builder::new();
+        if let Some(name) = self.thread_name(index) {
+            builder = builder.name(name);
+        }
+        if let Some(size) = self.stack_size {
+            builder = builder.stack_size(size);
+        }
+        let config = self.clone();
+        builder.spawn(move || {
+            state.enter();
+            // counted out even if a hook panics
+            let _exit = WorkerExit(state);
+            config.start(index);
+            work();
+            config.stop(index);
+        })?;
+        Ok(())
+    }
+}
+
+struct WorkerExit(Arc<PoolState>);
+
+impl Drop for WorkerExit {
+    fn drop(&mut self) {
+        self.0.worker_exited();
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_affinity(cpu: usize) -> io::Result<()> {
+    // `CPU_SET` panics past the end of the set
+    if cpu >= libc::CPU_SETSIZE as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("cpu {} out of range", cpu),
+        ));
+    }
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_affinity(_cpu: usize) -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,184 @@
+use crate::thread_pool::{PoolState, PoolStats, ThreadPool, ThreadPoolBuilder};
+use crate::Result;
+use crossbeam::channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// How long a worker beyond the core size waits for a job by default
+pub const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+/// Keeps `min` workers, adds more up to `max` while jobs queue up
+/// and retires those idle for longer than the keep-alive
+pub struct ElasticThreadPool {
+    // dropped on shutdown, the workers drain the queue then exit
+    sender: Mutex<Option<Sender<Job>>>,
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    receiver: Receiver<Job>,
+    min: usize,
+    max: usize,
+    keep_alive: Duration,
+    workers: Mutex<Workers>,
+    state: Arc<PoolState>,
+    builder: ThreadPoolBuilder,
+}
+
+#[derive(Default)]
+struct Workers {
+    alive: usize,
+    // waiting for a job
+    idle: usize,
+    // spawned, not taken by a worker yet. Unlike the length of the channel
+    // it's only updated under the lock, along with `idle`
+    queued: usize,
+    // index of the next worker
+    next: usize,
+}
+
+impl ElasticThreadPool {
+    pub fn with_limits(min: u32, max: u32, keep_alive: Duration) -> Result<Self> {
+        ElasticThreadPool::with_limits_from_builder(ThreadPoolBuilder::new(min), max, keep_alive)
+    }
+
+    /// Like `with_limits`, `builder.threads()` is the core size
+    pub fn with_limits_from_builder(
+        builder: ThreadPoolBuilder,
+        max: u32,
+        keep_alive: Duration,
+    ) -> Result<Self> {
+        let min = builder.threads();
+        if max == 0 || min > max {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid thread limits {}..{}", min, max),
+            )
+            .into());
+        }
+        let (s, r) = unbounded();
+        let shared = Arc::new(Shared {
+            receiver: r,
+            min: min as usize,
+            max: max as usize,
+            keep_alive,
+            workers: Mutex::new(Workers::default()),
+            state: Arc::new(PoolState::new(&builder)),
+            builder,
+        });
+        for _ in 0..min {
+            // the workers started so far exit along with the sender
+            Shared::add_worker(&shared, &mut shared.workers.lock().unwrap())?;
+        }
+        Ok(ElasticThreadPool {
+            sender: Mutex::new(Some(s)),
+            shared,
+        })
+    }
+}
+
+impl Shared {
+    fn add_worker(shared: &Arc<Shared>, workers: &mut Workers) -> io::Result<()> {
+        let index = workers.next;
+        workers.next += 1;
+        workers.alive += 1;
+        shared.state.add_workers(1);
+        let spawned = spawn_worker(index, shared.clone());
+        if spawned.is_err() {
+            workers.alive -= 1;
+            shared.state.worker_exited();
+        }
+        spawned
+    }
+
+    // Count a job about to be queued, another worker if the queued jobs
+    // outnumber the idle ones
+    fn grow(shared: &Arc<Shared>) {
+        let mut workers = shared.workers.lock().unwrap();
+        workers.queued += 1;
+        if workers.queued > workers.idle && workers.alive < shared.max {
+            // the workers there are take the job anyway
+            if let Err(e) = Shared::add_worker(shared, &mut workers) {
+                log::warn!("Failed to add a worker: {}", e);
+            }
+        }
+    }
+
+    // Next job, None once the worker should exit
+    fn next_job(&self) -> Option<Job> {
+        loop {
+            self.workers.lock().unwrap().idle += 1;
+            let job = self.receiver.recv_timeout(self.keep_alive);
+            let mut workers = self.workers.lock().unwrap();
+            workers.idle -= 1;
+            match job {
+                Ok(job) => {
+                    workers.queued -= 1;
+                    return Some(job);
+                }
+                // the core workers stay, and so does a worker `grow` counted on for a job
+                Err(RecvTimeoutError::Timeout)
+                    if workers.alive <= self.min || workers.queued > 0 => {}
+                Err(_) => {
+                    workers.alive -= 1;
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+fn spawn_worker(index: usize, shared: Arc<Shared>) -> io::Result<()> {
+    let (builder, state) = (shared.builder.clone(), shared.state.clone());
+    builder.spawn(index, state, move || {
+        while let Some(job) = shared.next_job() {
+            shared.state.run(job);
+        }
+    })
+}
+
+impl ThreadPool for ElasticThreadPool {
+    // a fixed size pool, see `with_limits`
+    fn from_builder(builder: ThreadPoolBuilder) -> Result<Self> {
+        let threads = builder.threads();
+        ElasticThreadPool::with_limits_from_builder(builder, threads, DEFAULT_KEEP_ALIVE)
+    }
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if let Some(sender) = &*self.sender.lock().unwrap() {
+            // counted before a worker may take it
+            Shared::grow(&self.shared);
+            sender.send(Box::new(job)).expect("failed to spawn job");
+        }
+    }
+    fn stats(&self) -> PoolStats {
+        let threads = self.shared.workers.lock().unwrap().alive as u32;
+        self.shared
+            .state
+            .stats(threads, self.shared.receiver.len() as u64)
+    }
+    fn shutdown(&self) {
+        if self.shared.state.close() {
+            self.sender.lock().unwrap().take();
+        }
+    }
+    fn shutdown_now(&self) {
+        self.shared.state.discard();
+        self.shutdown();
+    }
+    fn join(&self) {
+        self.shutdown();
+        self.shared.state.join();
+    }
+}
+
+impl Drop for ElasticThreadPool {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
@@ -0,0 +1,36 @@
+use crate::thread_pool::panic_message;
+use crate::{KvStoreError, Result};
+use crossbeam::channel::{Receiver, TryRecvError};
+use std::any::Any;
+
+/// Result of a job spawned with `ThreadPool::spawn_with_handle`
+pub struct JobHandle<T> {
+    result: Receiver<std::thread::Result<T>>,
+}
+
+impl<T> JobHandle<T> {
+    pub(crate) fn new(result: Receiver<std::thread::Result<T>>) -> Self {
+        JobHandle { result }
+    }
+
+    /// Block until the job finished
+    pub fn join(self) -> Result<T> {
+        match self.result.recv() {
+            Ok(result) => result.map_err(panicked),
+            Err(_) => Err(KvStoreError::JobCancelled),
+        }
+    }
+
+    /// `None` while the job is queued or running, the result is handed out once
+    pub fn try_join(&mut self) -> Result<Option<T>> {
+        match self.result.try_recv() {
+            Ok(result) => result.map(Some).map_err(panicked),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(KvStoreError::JobCancelled),
+        }
+    }
+}
+
+fn panicked(payload: Box<dyn Any + Send>) -> KvStoreError {
+    KvStoreError::JobPanicked(panic_message(&*payload))
+}
@@ -1,23 +1,270 @@
 use crate::error::Result;
+use crossbeam::channel::bounded;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::cell::Cell;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
 
+mod builder;
+mod elastic;
+mod handle;
 mod naive;
+mod priority;
 mod rayon;
+mod scheduler;
+mod scope;
 mod shared_queue;
+mod work_stealing;
 
 pub use self::rayon::RayonThreadPool;
+pub use builder::ThreadPoolBuilder;
+pub use elastic::{ElasticThreadPool, DEFAULT_KEEP_ALIVE};
+pub use handle::JobHandle;
 pub use naive::NaiveThreadPool;
-pub use shared_queue::SharedQueueThreadPool;
+pub use priority::{Priority, PriorityThreadPool};
+pub use scheduler::{ScheduleHandle, Scheduler};
+pub use scope::Scope;
+pub use shared_queue::{QueuePolicy, SharedQueueThreadPool};
+pub use work_stealing::WorkStealingThreadPool;
 
+/// Pools shut down when dropped, without waiting for their workers:
+/// a job blocking for good, e.g. a session, mustn't hang the dropping thread
 pub trait ThreadPool {
     fn new(threads: u32) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        Self::from_builder(ThreadPoolBuilder::new(threads))
+    }
+    /// A pool of `builder.threads()` workers set up by `builder`
+    fn from_builder(builder: ThreadPoolBuilder) -> Result<Self>
     where
         Self: Sized;
+    /// Jobs spawned after a shutdown are dropped without running
     fn spawn<F>(&self, job: F)
     where
         F: FnOnce() + Send + 'static;
+    /// Like `spawn`, but tells when the pool turns the job down, e.g. with
+    /// `KvStoreError::Busy` once a bounded queue is full
+    fn try_spawn<F>(&self, job: F) -> Result<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.spawn(job);
+        Ok(())
+    }
+    /// Like `spawn`, `priority` only counts for pools ordering their jobs,
+    /// see `PriorityThreadPool`
+    fn spawn_with_priority<F>(&self, priority: Priority, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let _ = self.try_spawn_with_priority(priority, job);
+    }
+    /// Like `try_spawn`, with `priority` as for `spawn_with_priority`
+    fn try_spawn_with_priority<F>(&self, _priority: Priority, job: F) -> Result<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.try_spawn(job)
+    }
+    /// Like `spawn`, a panic of the job is caught and handed to the handle.
+    /// Joining a job the pool dropped, e.g. on `shutdown_now`, fails
+    fn spawn_with_handle<F, T>(&self, job: F) -> JobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (tx, rx) = bounded(1);
+        self.spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(job));
+            let panicked = result.is_err();
+            let _ = tx.send(result);
+            if panicked {
+                // for the pool to count it
+                panic::resume_unwind(Box::new(HandedOver));
+            }
+        });
+        JobHandle::new(rx)
+    }
+    /// Run `f` with a `Scope` to spawn jobs borrowing from the caller,
+    /// returns once all of them finished and raises the first panic among them.
+    /// Called from a job of the same pool it waits holding a worker
+    fn scope<'env, F, R>(&'env self, f: F) -> R
+    where
+        F: FnOnce(&Scope<'env>) -> R,
+    {
+        let scope = Scope::new(Box::new(move |job| self.spawn(job)));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        scope.wait();
+        if let Some(payload) = scope.take_panic() {
+            panic::resume_unwind(payload);
+        }
+        match result {
+            Ok(result) => result,
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+    fn stats(&self) -> PoolStats;
+    /// Stop taking jobs, the queued ones still run
+    fn shutdown(&self);
+    /// Stop taking jobs and drop the queued ones, running jobs finish
+    fn shutdown_now(&self);
+    /// Shut down and wait for the workers to exit
+    fn join(&self);
+}
+
+/// What a pool is up to, numbers a pool can't tell are 0
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct PoolStats {
+    pub threads: u32,
+    // spawned, not picked up by a worker yet
+    pub queued: u64,
+    // workers running a job
+    #[serde(default)]
+    pub active: u32,
+    // jobs which returned, the panicked ones are counted apart
+    #[serde(default)]
+    pub completed: u64,
+    #[serde(default)]
+    pub panics: u64,
 }
 
+/// The message of a panic payload, as printed by the default panic hook
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(msg) => (*msg).to_owned(),
+        None => match payload.downcast_ref::<String>() {
+            Some(msg) => msg.clone(),
+            None => "Box<Any>".to_owned(),
+        },
+    }
+}
+
+type PanicHook = Arc<dyn Fn(&(dyn Any + Send)) + Send + Sync>;
+
+// Payload a job raises again once its panic went to the spawner,
+// counted by the pool but kept from the panic hook
+struct HandedOver;
+
 enum ThreadPoolMessage {
     RunJob(Box<dyn FnOnce() + Send + 'static>),
-    Shutdown,
+}
+
+thread_local! {
+    // `PoolState` of the pool the current thread works for
+    static CURRENT_POOL: Cell<usize> = const { Cell::new(0) };
+}
+
+// Shutdown flags, live workers and job counters of a pool
+#[derive(Default)]
+struct PoolState {
+    closed: AtomicBool,
+    discard: AtomicBool,
+    workers: Mutex<usize>,
+    exited: Condvar,
+    active: AtomicUsize,
+    completed: AtomicU64,
+    panics: AtomicU64,
+    panic_hook: Option<PanicHook>,
+}
+
+impl PoolState {
+    fn new(builder: &ThreadPoolBuilder) -> Self {
+        PoolState {
+            panic_hook: builder.panic_handler(),
+            ..PoolState::default()
+        }
+    }
+
+    // Run a job on the current worker, unless queued jobs are being dropped
+    fn run<F: FnOnce()>(&self, job: F) {
+        if self.is_discarding() {
+            return;
+        }
+        self.active.fetch_add(1, Ordering::SeqCst);
+        let result = panic::catch_unwind(AssertUnwindSafe(job));
+        self.active.fetch_sub(1, Ordering::SeqCst);
+        match result {
+            Ok(()) => {
+                self.completed.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(payload) => {
+                self.panics.fetch_add(1, Ordering::Relaxed);
+                if let (Some(hook), false) = (&self.panic_hook, payload.is::<HandedOver>()) {
+                    // the hook may not take the worker down
+                    let _ = panic::catch_unwind(AssertUnwindSafe(|| hook(&*payload)));
+                }
+            }
+        }
+    }
+
+    fn stats(&self, threads: u32, queued: u64) -> PoolStats {
+        PoolStats {
+            threads,
+            queued,
+            active: self.active.load(Ordering::SeqCst) as u32,
+            completed: self.completed.load(Ordering::Relaxed),
+            panics: self.panics.load(Ordering::Relaxed),
+        }
+    }
+
+    fn live_workers(&self) -> usize {
+        *self.workers.lock().unwrap()
+    }
+
+    // false if the pool was closed already
+    fn close(&self) -> bool {
+        !self.closed.swap(true, Ordering::SeqCst)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    // Queued jobs are dropped from now on
+    fn discard(&self) {
+        self.discard.store(true, Ordering::SeqCst);
+    }
+
+    fn is_discarding(&self) -> bool {
+        self.discard.load(Ordering::SeqCst)
+    }
+
+    fn add_workers(&self, count: usize) {
+        *self.workers.lock().unwrap() += count;
+    }
+
+    // Count a worker once `spawn` started it, it can't be counted out before
+    fn add_worker_with<F>(&self, spawn: F) -> io::Result<()>
+    where
+        F: FnOnce() -> io::Result<()>,
+    {
+        let mut workers = self.workers.lock().unwrap();
+        spawn()?;
+        *workers += 1;
+        Ok(())
+    }
+
+    // Called first thing by a worker thread, replacements of panicked workers included
+    fn enter(&self) {
+        CURRENT_POOL.with(|pool| pool.set(self as *const PoolState as usize));
+    }
+
+    fn worker_exited(&self) {
+        *self.workers.lock().unwrap() -= 1;
+        self.exited.notify_all();
+    }
+
+    // A worker joining its own pool only waits for the others
+    fn join(&self) {
+        let own = CURRENT_POOL.with(|pool| pool.get() == self as *const PoolState as usize);
+        let mut workers = self.workers.lock().unwrap();
+        while *workers > own as usize {
+            workers = self.exited.wait(workers).unwrap();
+        }
+    }
 }
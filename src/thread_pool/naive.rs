@@ -1,16 +1,59 @@
-use crate::thread_pool::ThreadPool;
+use crate::thread_pool::{PoolState, PoolStats, ThreadPool, ThreadPoolBuilder};
 use crate::Result;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
-pub struct NaiveThreadPool;
+/// A thread per job, numbered in spawn order
+pub struct NaiveThreadPool {
+    builder: ThreadPoolBuilder,
+    next: AtomicUsize,
+    state: Arc<PoolState>,
+}
 
 impl ThreadPool for NaiveThreadPool {
-    fn new(_threads: u32) -> Result<Self> {
-        Ok(NaiveThreadPool)
+    fn from_builder(builder: ThreadPoolBuilder) -> Result<Self> {
+        Ok(NaiveThreadPool {
+            state: Arc::new(PoolState::new(&builder)),
+            builder,
+            next: AtomicUsize::new(0),
+        })
     }
     fn spawn<F>(&self, job: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        std::thread::spawn(job);
+        if self.state.is_closed() {
+            return;
+        }
+        let index = self.next.fetch_add(1, Ordering::Relaxed);
+        let state = self.state.clone();
+        self.state
+            .add_worker_with(|| {
+                self.builder
+                    .spawn(index, self.state.clone(), move || state.run(job))
+            })
+            .expect("failed to spawn thread");
+    }
+    // a thread per job, none waits
+    fn stats(&self) -> PoolStats {
+        self.state.stats(self.state.live_workers() as u32, 0)
+    }
+    fn shutdown(&self) {
+        self.state.close();
+    }
+    // nothing is ever queued
+    fn shutdown_now(&self) {
+        self.shutdown();
+    }
+    // waits for the running jobs
+    fn join(&self) {
+        self.shutdown();
+        self.state.join();
+    }
+}
+
+impl Drop for NaiveThreadPool {
+    fn drop(&mut self) {
+        self.shutdown();
     }
 }
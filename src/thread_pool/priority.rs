@@ -0,0 +1,216 @@
+use crate::thread_pool::{PoolState, PoolStats, ThreadPool, ThreadPoolBuilder};
+use crate::{KvStoreError, Result};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Class of a job, only a `PriorityThreadPool` tells them apart
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Priority {
+    /// Requests a client waits for
+    High,
+    /// What `spawn` gives
+    Normal,
+    /// Background work such as compaction and stats
+    Low,
+}
+
+impl Priority {
+    // how long a job of the class waits at most by default, see `max_wait`
+    fn default_max_wait(self) -> Duration {
+        match self {
+            Priority::High => Duration::from_millis(0),
+            Priority::Normal => Duration::from_millis(100),
+            Priority::Low => Duration::from_secs(1),
+        }
+    }
+}
+
+/// Runs the job with the earliest deadline first. Jobs spawned with a priority
+/// are due once they waited the longest their class may, so a low priority job
+/// goes ahead of newer high priority ones after a while instead of starving
+pub struct PriorityThreadPool {
+    threads: u32,
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    // a job was queued or the pool shut down
+    available: Condvar,
+    state: Arc<PoolState>,
+    builder: ThreadPoolBuilder,
+}
+
+struct Queue {
+    jobs: BinaryHeap<Entry>,
+    // spawn order, the earlier of two jobs due at once goes first
+    next: u64,
+    max_wait: [Duration; 3],
+}
+
+struct Entry {
+    deadline: Instant,
+    seq: u64,
+    job: Job,
+}
+
+// Reversed, the heap pops the job due first
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .deadline
+            .cmp(&self.deadline)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+impl PriorityThreadPool {
+    /// Jobs of `priority` are due `wait` after they were spawned, 0 for `High`,
+    /// 100ms for `Normal` and 1s for `Low` unless set
+    pub fn max_wait(self, priority: Priority, wait: Duration) -> Self {
+        self.shared.queue.lock().unwrap().max_wait[priority as usize] = wait;
+        self
+    }
+
+    /// Like `spawn`, the job is due at `deadline`. It still runs after a missed
+    /// deadline, ahead of the jobs due later
+    pub fn spawn_with_deadline<F>(&self, deadline: Instant, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let _ = self.push(Some(deadline), Priority::Normal, Box::new(job));
+    }
+
+    // Queue a job due at `deadline`, or by the max wait of `priority` without one
+    fn push(&self, deadline: Option<Instant>, priority: Priority, job: Job) -> Result<()> {
+        let mut queue = self.shared.queue.lock().unwrap();
+        // checked under the lock, the workers exit once it's closed and empty
+        if self.shared.state.is_closed() {
+            return Err(KvStoreError::PoolShutdown);
+        }
+        let deadline =
+            deadline.unwrap_or_else(|| Instant::now() + queue.max_wait[priority as usize]);
+        let seq = queue.next;
+        queue.next += 1;
+        queue.jobs.push(Entry { deadline, seq, job });
+        self.shared.available.notify_one();
+        Ok(())
+    }
+}
+
+impl Shared {
+    // Next job, None once the pool is shut down and the queue ran dry
+    fn next_job(&self) -> Option<Job> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if let Some(entry) = queue.jobs.pop() {
+                return Some(entry.job);
+            }
+            if self.state.is_closed() {
+                return None;
+            }
+            queue = self.available.wait(queue).unwrap();
+        }
+    }
+}
+
+fn spawn_worker(index: usize, shared: Arc<Shared>) -> io::Result<()> {
+    let (builder, state) = (shared.builder.clone(), shared.state.clone());
+    builder.spawn(index, state, move || {
+        while let Some(job) = shared.next_job() {
+            shared.state.run(job);
+        }
+    })
+}
+
+impl ThreadPool for PriorityThreadPool {
+    fn from_builder(builder: ThreadPoolBuilder) -> Result<Self> {
+        let threads = builder.threads();
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                jobs: BinaryHeap::new(),
+                next: 0,
+                max_wait: [
+                    Priority::High.default_max_wait(),
+                    Priority::Normal.default_max_wait(),
+                    Priority::Low.default_max_wait(),
+                ],
+            }),
+            available: Condvar::new(),
+            state: Arc::new(PoolState::new(&builder)),
+            builder,
+        });
+        let pool = PriorityThreadPool { threads, shared };
+        for index in 0..threads as usize {
+            pool.shared.state.add_workers(1);
+            if let Err(e) = spawn_worker(index, pool.shared.clone()) {
+                pool.shared.state.worker_exited();
+                // dropping the pool stops the workers started so far
+                return Err(e.into());
+            }
+        }
+        Ok(pool)
+    }
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let _ = self.try_spawn(job);
+    }
+    fn try_spawn<F>(&self, job: F) -> Result<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.try_spawn_with_priority(Priority::Normal, job)
+    }
+    fn try_spawn_with_priority<F>(&self, priority: Priority, job: F) -> Result<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.push(None, priority, Box::new(job))
+    }
+    fn stats(&self) -> PoolStats {
+        let queued = self.shared.queue.lock().unwrap().jobs.len() as u64;
+        self.shared.state.stats(self.threads, queued)
+    }
+    // workers run dry, then exit
+    fn shutdown(&self) {
+        if self.shared.state.close() {
+            let _queue = self.shared.queue.lock().unwrap();
+            self.shared.available.notify_all();
+        }
+    }
+    fn shutdown_now(&self) {
+        self.shared.state.discard();
+        self.shutdown();
+    }
+    fn join(&self) {
+        self.shutdown();
+        self.shared.state.join();
+    }
+}
+
+impl Drop for PriorityThreadPool {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
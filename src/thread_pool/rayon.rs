@@ -1,20 +1,75 @@
-use crate::thread_pool::ThreadPool;
+use crate::thread_pool::{PoolState, PoolStats, ThreadPool, ThreadPoolBuilder};
 use crate::Result;
+use std::sync::{Arc, Mutex};
 
-pub struct RayonThreadPool(rayon::ThreadPool);
+/// Dropping the inner pool on shutdown lets its workers exit
+/// once they ran the queued jobs
+pub struct RayonThreadPool {
+    pool: Mutex<Option<rayon::ThreadPool>>,
+    state: Arc<PoolState>,
+}
 
 impl ThreadPool for RayonThreadPool {
-    fn new(threads: u32) -> Result<Self> {
-        Ok(RayonThreadPool(
-            rayon::ThreadPoolBuilder::new()
-                .num_threads(threads as usize)
-                .build()?,
-        ))
+    fn from_builder(builder: ThreadPoolBuilder) -> Result<Self> {
+        let state = Arc::new(PoolState::new(&builder));
+        let (start, exit) = (state.clone(), state.clone());
+        let (on_start, on_exit) = (builder.clone(), builder.clone());
+        let pool = builder
+            .rayon_builder()
+            .start_handler(move |index| {
+                start.enter();
+                on_start.start(index);
+            })
+            .exit_handler(move |index| {
+                on_exit.stop(index);
+                exit.worker_exited();
+            })
+            .build()?;
+        // 0 threads is up to rayon, workers only exit once the pool is dropped
+        state.add_workers(pool.current_num_threads());
+        Ok(RayonThreadPool {
+            pool: Mutex::new(Some(pool)),
+            state,
+        })
     }
     fn spawn<F>(&self, job: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        self.0.spawn(job);
+        if let Some(pool) = &*self.pool.lock().unwrap() {
+            let state = self.state.clone();
+            pool.spawn(move || {
+                state.run(job);
+            });
+        }
+    }
+    // rayon keeps its queues to itself
+    fn stats(&self) -> PoolStats {
+        let threads = self
+            .pool
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(0, |pool| pool.current_num_threads() as u32);
+        self.state.stats(threads, 0)
+    }
+    fn shutdown(&self) {
+        if self.state.close() {
+            self.pool.lock().unwrap().take();
+        }
+    }
+    fn shutdown_now(&self) {
+        self.state.discard();
+        self.shutdown();
+    }
+    fn join(&self) {
+        self.shutdown();
+        self.state.join();
+    }
+}
+
+impl Drop for RayonThreadPool {
+    fn drop(&mut self) {
+        self.shutdown();
     }
 }
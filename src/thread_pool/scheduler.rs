@@ -0,0 +1,295 @@
+use crate::thread_pool::{Priority, ThreadPool};
+use crate::{KvStoreError, Result};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+type PeriodicJob = Arc<dyn Fn() + Send + Sync + 'static>;
+
+/// Hands jobs to a pool once they are due, from a timer thread of its own.
+/// Dropping the scheduler or the pool stops it, the jobs not due yet never run
+pub struct Scheduler {
+    shared: Arc<Shared>,
+    timer: Option<JoinHandle<()>>,
+}
+
+/// Cancels a job of a `Scheduler`, dropping the handle leaves the job scheduled
+pub struct ScheduleHandle {
+    task: Arc<Task>,
+    shared: Weak<Shared>,
+}
+
+struct Shared {
+    timers: Mutex<Timers>,
+    // a timer was added or the scheduler stopped
+    changed: Condvar,
+}
+
+struct Timers {
+    queue: BinaryHeap<Timer>,
+    // schedule order, the earlier of two timers due at once goes first
+    next: u64,
+    priority: Priority,
+    closed: bool,
+}
+
+struct Timer {
+    due: Instant,
+    seq: u64,
+    task: Arc<Task>,
+}
+
+// Reversed, the heap pops the timer due first
+impl Ord for Timer {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .due
+            .cmp(&self.due)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Timer {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Timer {}
+
+struct Task {
+    kind: TaskKind,
+    cancelled: AtomicBool,
+}
+
+enum TaskKind {
+    // taken when handed to the pool or cancelled
+    Once(Mutex<Option<Job>>),
+    Every {
+        interval: Duration,
+        job: PeriodicJob,
+        // a run is on the pool
+        running: Arc<AtomicBool>,
+    },
+}
+
+impl Scheduler {
+    /// Scheduled jobs run on `pool`, the scheduler doesn't keep it alive
+    pub fn new<P>(pool: &Arc<P>) -> Result<Self>
+    where
+        P: ThreadPool + Send + Sync + 'static,
+    {
+        let shared = Arc::new(Shared {
+            timers: Mutex::new(Timers {
+                queue: BinaryHeap::new(),
+                next: 0,
+                priority: Priority::Normal,
+                closed: false,
+            }),
+            changed: Condvar::new(),
+        });
+        let (timer, pool) = (shared.clone(), Arc::downgrade(pool));
+        let timer = thread::Builder::new()
+            .name("kvs-scheduler".to_owned())
+            .spawn(move || timer.run(&pool))?;
+        Ok(Scheduler {
+            shared,
+            timer: Some(timer),
+        })
+    }
+
+    /// Jobs go to the pool with `priority`, `Normal` unless set
+    pub fn priority(self, priority: Priority) -> Self {
+        self.shared.timers.lock().unwrap().priority = priority;
+        self
+    }
+
+    /// Run `job` once, `delay` from now
+    pub fn schedule_after<F>(&self, delay: Duration, job: F) -> ScheduleHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.add(delay, TaskKind::Once(Mutex::new(Some(Box::new(job)))))
+    }
+
+    /// Run `job` every `interval`, the first time one interval from now.
+    /// Runs don't overlap, one due while the last is still going is skipped,
+    /// as are those the timer missed. A zero `interval` is an `InvalidRequest`
+    pub fn schedule_every<F>(&self, interval: Duration, job: F) -> Result<ScheduleHandle>
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        if interval == Duration::from_millis(0) {
+            return Err(KvStoreError::InvalidRequest(
+                "Interval must not be 0".to_owned(),
+            ));
+        }
+        Ok(self.add(
+            interval,
+            TaskKind::Every {
+                interval,
+                job: Arc::new(job),
+                running: Arc::new(AtomicBool::new(false)),
+            },
+        ))
+    }
+
+    fn add(&self, delay: Duration, kind: TaskKind) -> ScheduleHandle {
+        let task = Arc::new(Task {
+            kind,
+            cancelled: AtomicBool::new(false),
+        });
+        self.shared.push(Instant::now() + delay, task.clone());
+        ScheduleHandle {
+            task,
+            shared: Arc::downgrade(&self.shared),
+        }
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        self.shared.timers.lock().unwrap().closed = true;
+        self.shared.changed.notify_all();
+        if let Some(timer) = self.timer.take() {
+            let _ = timer.join();
+        }
+    }
+}
+
+impl ScheduleHandle {
+    /// No more runs from now on, one already on the pool still finishes.
+    /// False if the job was cancelled before, or ran already if scheduled once
+    pub fn cancel(&self) -> bool {
+        let first = !self.task.cancelled.swap(true, atomic::Ordering::SeqCst);
+        if let Some(shared) = self.shared.upgrade() {
+            shared.remove(&self.task);
+        }
+        match &self.task.kind {
+            // drops what the job holds right away
+            TaskKind::Once(job) => job.lock().unwrap().take().is_some(),
+            TaskKind::Every { .. } => first,
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.task.cancelled.load(atomic::Ordering::SeqCst)
+    }
+}
+
+impl Shared {
+    fn push(&self, due: Instant, task: Arc<Task>) {
+        let mut timers = self.timers.lock().unwrap();
+        let seq = timers.next;
+        timers.next += 1;
+        timers.queue.push(Timer { due, seq, task });
+        self.changed.notify_one();
+    }
+
+    // Take the timers of `task` out of the queue
+    fn remove(&self, task: &Arc<Task>) {
+        let mut timers = self.timers.lock().unwrap();
+        timers.queue.retain(|timer| !Arc::ptr_eq(&timer.task, task));
+    }
+
+    // The timer thread, spawns due jobs on `pool` until the scheduler
+    // or the pool is dropped
+    fn run<P: ThreadPool>(&self, pool: &Weak<P>) {
+        let mut timers = self.timers.lock().unwrap();
+        while !timers.closed {
+            let now = Instant::now();
+            let due = match timers.queue.peek() {
+                Some(timer) => timer.due,
+                None => {
+                    timers = self.changed.wait(timers).unwrap();
+                    continue;
+                }
+            };
+            if due > now {
+                timers = self.changed.wait_timeout(timers, due - now).unwrap().0;
+                continue;
+            }
+            let timer = timers.queue.pop().unwrap();
+            if timer.task.cancelled.load(atomic::Ordering::SeqCst) {
+                continue;
+            }
+            if let TaskKind::Every { interval, .. } = timer.task.kind {
+                let mut next = timer.due + interval;
+                if next <= now {
+                    next = now + interval;
+                }
+                let seq = timers.next;
+                timers.next += 1;
+                timers.queue.push(Timer {
+                    due: next,
+                    seq,
+                    task: timer.task.clone(),
+                });
+            }
+            let priority = timers.priority;
+            // the pool may block, e.g. with a full bounded queue
+            drop(timers);
+            let pool = match pool.upgrade() {
+                Some(pool) => pool,
+                None => {
+                    // the jobs have nowhere left to run
+                    self.timers.lock().unwrap().queue.clear();
+                    return;
+                }
+            };
+            // a panicking pool mustn't take the timer thread down with it
+            let spawn = AssertUnwindSafe(|| timer.task.spawn(&*pool, priority));
+            if panic::catch_unwind(spawn).is_err() {
+                log::error!("Failed to hand a scheduled job to the pool");
+            }
+            // unlocked, shutting down the pool may block if it was the last one
+            drop(pool);
+            timers = self.timers.lock().unwrap();
+        }
+    }
+}
+
+impl Task {
+    fn spawn<P: ThreadPool>(&self, pool: &P, priority: Priority) {
+        match &self.kind {
+            TaskKind::Once(job) => {
+                // not locked while spawning, a panic mustn't poison it
+                let job = job.lock().unwrap().take();
+                if let Some(job) = job {
+                    pool.spawn_with_priority(priority, job);
+                }
+            }
+            TaskKind::Every { job, running, .. } => {
+                if running.swap(true, atomic::Ordering::SeqCst) {
+                    return;
+                }
+                let (job, guard) = (job.clone(), Running(running.clone()));
+                pool.spawn_with_priority(priority, move || {
+                    let _guard = guard;
+                    job();
+                });
+            }
+        }
+    }
+}
+
+// Clears the running flag of a periodic job once its run ended, panicked
+// or was dropped by the pool
+struct Running(Arc<AtomicBool>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.store(false, atomic::Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,99 @@
+use crate::thread_pool::HandedOver;
+use std::any::Any;
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Spawns jobs borrowing from outside the `ThreadPool::scope` call,
+/// they all finished by the time it returns
+pub struct Scope<'env> {
+    spawner: Box<dyn Fn(Job) + 'env>,
+    state: Arc<ScopeState>,
+    // invariant, jobs can't borrow what lives shorter than the scope
+    marker: PhantomData<&'env mut &'env ()>,
+}
+
+#[derive(Default)]
+struct ScopeState {
+    pending: Mutex<usize>,
+    finished: Condvar,
+    // first panic of a job, raised again by the scope
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+}
+
+// A job of a scope, counted out once it ran or the pool dropped it
+struct ScopedJob<'env> {
+    job: Option<Box<dyn FnOnce() + Send + 'env>>,
+    state: Arc<ScopeState>,
+}
+
+impl<'env> ScopedJob<'env> {
+    fn run(mut self) {
+        if let Some(job) = self.job.take() {
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                {
+                    let mut panic = self.state.panic.lock().unwrap();
+                    if panic.is_none() {
+                        *panic = Some(payload);
+                    }
+                }
+                // for the pool to count it
+                panic::resume_unwind(Box::new(HandedOver));
+            }
+        }
+    }
+}
+
+impl<'env> Drop for ScopedJob<'env> {
+    fn drop(&mut self) {
+        // whatever the job borrowed goes before the scope may return
+        if self.job.take().is_some() {
+            let mut panic = self.state.panic.lock().unwrap();
+            if panic.is_none() {
+                *panic = Some(Box::new("scoped job dropped by the pool"));
+            }
+        }
+        *self.state.pending.lock().unwrap() -= 1;
+        self.state.finished.notify_all();
+    }
+}
+
+impl<'env> Scope<'env> {
+    pub(crate) fn new(spawner: Box<dyn Fn(Job) + 'env>) -> Self {
+        Scope {
+            spawner,
+            state: Arc::new(ScopeState::default()),
+            marker: PhantomData,
+        }
+    }
+
+    /// Jobs the pool drops, e.g. after a shutdown, never run and make the scope panic
+    pub fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'env,
+    {
+        *self.state.pending.lock().unwrap() += 1;
+        let scoped = ScopedJob {
+            job: Some(Box::new(job)),
+            state: self.state.clone(),
+        };
+        let job: Box<dyn FnOnce() + Send + 'env> = Box::new(move || scoped.run());
+        // safe as `wait` outlives the job, which is all `'env` needs
+        let job: Job = unsafe { mem::transmute(job) };
+        (self.spawner)(job);
+    }
+
+    pub(crate) fn wait(&self) {
+        let mut pending = self.state.pending.lock().unwrap();
+        while *pending > 0 {
+            pending = self.state.finished.wait(pending).unwrap();
+        }
+    }
+
+    pub(crate) fn take_panic(&self) -> Option<Box<dyn Any + Send>> {
+        self.state.panic.lock().unwrap().take()
+    }
+}
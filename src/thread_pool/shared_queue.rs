@@ -1,72 +1,183 @@
-use crate::thread_pool::{ThreadPool, ThreadPoolMessage};
-use crate::Result;
-use crossbeam::channel::{unbounded, Receiver, Sender};
-use std::thread;
+use crate::thread_pool::{PoolState, PoolStats, ThreadPool, ThreadPoolBuilder, ThreadPoolMessage};
+use crate::{KvStoreError, Result};
+use crossbeam::channel::{bounded, unbounded, Receiver, Sender, TrySendError};
+use crossbeam::select;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// What a job meets when the bounded queue is full
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QueuePolicy {
+    /// Wait for room
+    Block,
+    /// Turn it down with `KvStoreError::Busy`, `spawn` waits for room instead
+    Reject,
+    /// Run it on the spawning thread
+    CallerRuns,
+}
 
 pub struct SharedQueueThreadPool {
     threads: u32,
     sender: Sender<ThreadPoolMessage>,
+    policy: QueuePolicy,
+    state: Arc<PoolState>,
+    // dropped on shutdown, which wakes up the workers and blocked spawners
+    closing: Mutex<Option<Sender<()>>>,
+    closed: Receiver<()>,
 }
 
-struct ThreadedReceiver {
-    receiver: Receiver<ThreadPoolMessage>,
-}
+impl SharedQueueThreadPool {
+    /// At most `capacity` jobs wait for a thread, `policy` decides about the others
+    pub fn bounded(threads: u32, capacity: usize, policy: QueuePolicy) -> Result<Self> {
+        SharedQueueThreadPool::bounded_from_builder(
+            ThreadPoolBuilder::new(threads),
+            capacity,
+            policy,
+        )
+    }
 
-impl Drop for ThreadedReceiver {
-    fn drop(&mut self) {
-        if thread::panicking() {
-            // recreate a new thread here
-            spawn_thread(self.receiver.clone());
+    /// Like `bounded`, with the workers set up by `builder`
+    pub fn bounded_from_builder(
+        builder: ThreadPoolBuilder,
+        capacity: usize,
+        policy: QueuePolicy,
+    ) -> Result<Self> {
+        SharedQueueThreadPool::start(builder, bounded(capacity), policy)
+    }
+
+    // Queue a job, `policy` decides if the queue is full
+    fn push(&self, job: Box<dyn FnOnce() + Send + 'static>, policy: QueuePolicy) -> Result<()> {
+        if self.state.is_closed() {
+            return Err(KvStoreError::PoolShutdown);
+        }
+        let msg = ThreadPoolMessage::RunJob(job);
+        if policy == QueuePolicy::Block {
+            select! {
+                send(self.sender, msg) -> res => res.expect("failed to spawn job"),
+                recv(self.closed) -> _ => return Err(KvStoreError::PoolShutdown),
+            }
+            return Ok(());
+        }
+        match self.sender.try_send(msg) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(ThreadPoolMessage::RunJob(job)))
+                if policy == QueuePolicy::CallerRuns =>
+            {
+                self.state.run(job);
+                Ok(())
+            }
+            Err(TrySendError::Full(_)) => {
+                Err(KvStoreError::Busy("Thread pool queue is full".to_owned()))
+            }
+            Err(TrySendError::Disconnected(_)) => Err(KvStoreError::PoolShutdown),
+        }
+    }
+
+    fn start(
+        builder: ThreadPoolBuilder,
+        (s, r): (Sender<ThreadPoolMessage>, Receiver<ThreadPoolMessage>),
+        policy: QueuePolicy,
+    ) -> Result<Self> {
+        let state = Arc::new(PoolState::new(&builder));
+        let builder = Arc::new(builder);
+        let (closing, closed) = bounded(0);
+        for index in 0..builder.threads() as usize {
+            let worker = Worker {
+                index,
+                receiver: r.clone(),
+                closed: closed.clone(),
+                state: state.clone(),
+                builder: builder.clone(),
+            };
+            state.add_workers(1);
+            if let Err(e) = worker.spawn() {
+                state.worker_exited();
+                return Err(e.into());
+            }
         }
+        Ok(SharedQueueThreadPool {
+            threads: builder.threads(),
+            sender: s,
+            policy,
+            state,
+            closing: Mutex::new(Some(closing)),
+            closed,
+        })
     }
 }
 
-fn spawn_thread(r: Receiver<ThreadPoolMessage>) {
-    thread::spawn(move || {
-        let thread_rx = ThreadedReceiver { receiver: r };
-        loop {
+struct Worker {
+    index: usize,
+    receiver: Receiver<ThreadPoolMessage>,
+    closed: Receiver<()>,
+    state: Arc<PoolState>,
+    builder: Arc<ThreadPoolBuilder>,
+}
+
+impl Worker {
+    fn spawn(self) -> io::Result<()> {
+        let (builder, state) = (self.builder.clone(), self.state.clone());
+        builder.spawn(self.index, state, move || loop {
             // block here
-            match thread_rx.receiver.recv() {
-                Ok(msg) => match msg {
-                    ThreadPoolMessage::RunJob(job) => {
-                        job();
-                    }
-                    ThreadPoolMessage::Shutdown => {
-                        break;
-                    }
+            select! {
+                recv(self.receiver) -> msg => match msg {
+                    Ok(ThreadPoolMessage::RunJob(job)) => self.state.run(job),
+                    Err(_) => break,
                 },
-                _ => {}
+                // the pool shut down, what is queued still runs
+                recv(self.closed) -> _ => {
+                    while let Ok(ThreadPoolMessage::RunJob(job)) = self.receiver.try_recv() {
+                        self.state.run(job);
+                    }
+                    break;
+                }
             }
-        }
-        // thread receiver will drop here
-    });
+        })
+    }
 }
 
 impl ThreadPool for SharedQueueThreadPool {
-    fn new(threads: u32) -> Result<Self> {
-        let (s, r) = unbounded();
-        for _ in 0..threads as usize {
-            let recv: Receiver<ThreadPoolMessage> = r.clone();
-            spawn_thread(recv);
-        }
-        Ok(SharedQueueThreadPool { threads, sender: s })
+    fn from_builder(builder: ThreadPoolBuilder) -> Result<Self> {
+        SharedQueueThreadPool::start(builder, unbounded(), QueuePolicy::Block)
     }
+    // can't tell about a rejection, so waits for room instead
     fn spawn<F>(&self, job: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        self.sender
-            .send(ThreadPoolMessage::RunJob(Box::new(job)))
-            .expect("failed to spawn job");
+        let policy = match self.policy {
+            QueuePolicy::Reject => QueuePolicy::Block,
+            policy => policy,
+        };
+        let _ = self.push(Box::new(job), policy);
+    }
+    fn try_spawn<F>(&self, job: F) -> Result<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.push(Box::new(job), self.policy)
+    }
+    fn stats(&self) -> PoolStats {
+        self.state.stats(self.threads, self.sender.len() as u64)
+    }
+    // never blocks, the workers leave once they ran out of queued jobs
+    fn shutdown(&self) {
+        if self.state.close() {
+            self.closing.lock().unwrap().take();
+        }
+    }
+    fn shutdown_now(&self) {
+        self.state.discard();
+        self.shutdown();
+    }
+    fn join(&self) {
+        self.shutdown();
+        self.state.join();
     }
 }
 
-impl SharedQueueThreadPool {
-    fn quit(&self) {
-        for _ in 0..self.threads as usize {
-            self.sender
-                .send(ThreadPoolMessage::Shutdown)
-                .expect("failed to send shutdown message");
-        }
+impl Drop for SharedQueueThreadPool {
+    fn drop(&mut self) {
+        self.shutdown();
     }
 }
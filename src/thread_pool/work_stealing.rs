@@ -0,0 +1,153 @@
+use crate::thread_pool::{PoolState, PoolStats, ThreadPool, ThreadPoolBuilder};
+use crate::Result;
+use crossbeam::deque::{Injector, Stealer, Worker};
+use std::io;
+use std::iter;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Every worker has its own deque, filled in batches from a shared injector,
+/// and steals from the others once it runs dry
+pub struct WorkStealingThreadPool {
+    threads: u32,
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    injector: Injector<Job>,
+    stealers: Vec<Stealer<Job>>,
+    // spawned, not picked up by a worker yet
+    queued: AtomicU64,
+    // workers without a job wait on `wakeup`
+    idle: Mutex<()>,
+    wakeup: Condvar,
+    sleepers: AtomicUsize,
+    state: Arc<PoolState>,
+    builder: ThreadPoolBuilder,
+}
+
+impl Shared {
+    fn find_job(&self, local: &Worker<Job>) -> Option<Job> {
+        let job = local.pop().or_else(|| {
+            iter::repeat_with(|| {
+                self.injector
+                    .steal_batch_and_pop(local)
+                    .or_else(|| self.stealers.iter().map(|s| s.steal()).collect())
+            })
+            .find(|s| !s.is_retry())
+            .and_then(|s| s.success())
+        });
+        if job.is_some() {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+        }
+        job
+    }
+
+    fn has_jobs(&self) -> bool {
+        !self.injector.is_empty() || self.stealers.iter().any(|s| !s.is_empty())
+    }
+
+    // Block until there may be a job, false once the pool is shut down
+    fn wait(&self) -> bool {
+        let idle = self.idle.lock().unwrap();
+        // a job pushed before this increment is seen by `has_jobs`,
+        // one pushed after it comes with a notification
+        self.sleepers.fetch_add(1, Ordering::SeqCst);
+        let alive = !self.state.is_closed();
+        if alive && !self.has_jobs() {
+            let _idle = self.wakeup.wait(idle).unwrap();
+        }
+        self.sleepers.fetch_sub(1, Ordering::SeqCst);
+        alive
+    }
+
+    fn wake_all(&self) {
+        let _idle = self.idle.lock().unwrap();
+        self.wakeup.notify_all();
+    }
+}
+
+fn spawn_worker(index: usize, shared: Arc<Shared>, local: Worker<Job>) -> io::Result<()> {
+    let (builder, state) = (shared.builder.clone(), shared.state.clone());
+    builder.spawn(index, state, move || loop {
+        match shared.find_job(&local) {
+            Some(job) => {
+                shared.state.run(job);
+            }
+            None => {
+                if !shared.wait() {
+                    break;
+                }
+            }
+        }
+    })
+}
+
+impl ThreadPool for WorkStealingThreadPool {
+    fn from_builder(builder: ThreadPoolBuilder) -> Result<Self> {
+        let threads = builder.threads();
+        let locals: Vec<Worker<Job>> = (0..threads).map(|_| Worker::new_fifo()).collect();
+        let shared = Arc::new(Shared {
+            injector: Injector::new(),
+            stealers: locals.iter().map(|w| w.stealer()).collect(),
+            queued: AtomicU64::new(0),
+            idle: Mutex::new(()),
+            wakeup: Condvar::new(),
+            sleepers: AtomicUsize::new(0),
+            state: Arc::new(PoolState::new(&builder)),
+            builder,
+        });
+        for (index, local) in locals.into_iter().enumerate() {
+            shared.state.add_workers(1);
+            if let Err(e) = spawn_worker(index, shared.clone(), local) {
+                shared.state.worker_exited();
+                // the workers started so far exit
+                shared.state.close();
+                shared.wake_all();
+                return Err(e.into());
+            }
+        }
+        Ok(WorkStealingThreadPool { threads, shared })
+    }
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if self.shared.state.is_closed() {
+            return;
+        }
+        self.shared.queued.fetch_add(1, Ordering::Relaxed);
+        self.shared.injector.push(Box::new(job));
+        if self.shared.sleepers.load(Ordering::SeqCst) > 0 {
+            let _idle = self.shared.idle.lock().unwrap();
+            self.shared.wakeup.notify_one();
+        }
+    }
+    fn stats(&self) -> PoolStats {
+        self.shared
+            .state
+            .stats(self.threads, self.shared.queued.load(Ordering::Relaxed))
+    }
+    // workers run dry, then exit
+    fn shutdown(&self) {
+        if self.shared.state.close() {
+            self.shared.wake_all();
+        }
+    }
+    fn shutdown_now(&self) {
+        self.shared.state.discard();
+        self.shutdown();
+    }
+    fn join(&self) {
+        self.shutdown();
+        self.shared.state.join();
+    }
+}
+
+impl Drop for WorkStealingThreadPool {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
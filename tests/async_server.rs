@@ -0,0 +1,58 @@
+#![cfg(feature = "async")]
+
+use kvs::network::{AsyncKvsClient, AsyncKvsServer, KvsClient};
+use kvs::{KvStore, KvStoreError, Result};
+use std::net::SocketAddr;
+use tempfile::TempDir;
+use tokio::sync::oneshot;
+
+#[tokio::test(threaded_scheduler)]
+async fn async_client_async_server() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4111".parse().unwrap();
+    let store = KvStore::open(temp_dir.path())?;
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let server = tokio::spawn(async move {
+        let server = AsyncKvsServer::new(store);
+        server
+            .listen_until(addr, async {
+                let _ = shutdown_rx.await;
+            })
+            .await
+    });
+
+    let mut client = loop {
+        // wait for the listener to be bound
+        if let Ok(client) = AsyncKvsClient::new(addr).await {
+            break client;
+        }
+    };
+    client.handshake().await?;
+    client.set("key1".to_owned(), "value1".to_owned()).await?;
+    assert_eq!(
+        client.get("key1".to_owned()).await?,
+        Some("value1".to_owned())
+    );
+    assert_eq!(client.get("key2".to_owned()).await?, None);
+    match client.remove("key2".to_owned()).await {
+        Err(KvStoreError::KeyNotFound) => {}
+        _ => panic!("remove non-existent key should fail"),
+    }
+
+    // blocking clients speak the same protocol
+    let sync_value = tokio::task::spawn_blocking(move || -> Result<Option<String>> {
+        let mut client = KvsClient::new(addr)?;
+        client.handshake()?;
+        let v = client.get("key1".to_owned())?;
+        client.quit()?;
+        Ok(v)
+    })
+    .await
+    .unwrap()?;
+    assert_eq!(sync_value, Some("value1".to_owned()));
+
+    client.quit().await?;
+    shutdown_tx.send(()).unwrap();
+    server.await.unwrap()?;
+    Ok(())
+}
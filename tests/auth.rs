@@ -0,0 +1,273 @@
+use assert_cmd::prelude::*;
+use crossbeam::channel::unbounded;
+use kvs::network::auth::{Access, AclRule, User};
+use kvs::network::{Authenticator, Credentials, KvsClient, KvsReactorServer, KvsServer};
+use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+use kvs::{KvStore, KvStoreError, Result};
+use std::fs;
+use std::net::SocketAddr;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+fn rule(prefix: &str, access: Access) -> AclRule {
+    AclRule {
+        prefix: prefix.to_owned(),
+        access,
+    }
+}
+
+// `app` owns "app/" and may read "shared/", `reader` reads everything
+fn authenticator() -> Authenticator {
+    Authenticator {
+        users: vec![
+            User::new(
+                "app",
+                "s1",
+                "secret",
+                vec![
+                    rule("app/", Access::ReadWrite),
+                    rule("shared/", Access::Read),
+                ],
+            ),
+            User::new("reader", "s2", "hunter2", vec![rule("", Access::Read)]),
+        ],
+    }
+}
+
+fn check_access(addr: SocketAddr) -> Result<()> {
+    // no credentials
+    let mut client = KvsClient::new(addr)?;
+    match client.handshake() {
+        Err(KvStoreError::Unauthorized(_)) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+
+    // wrong password
+    let mut client = KvsClient::new(addr)?.with_credentials("app", "guess");
+    match client.handshake() {
+        Err(KvStoreError::Unauthorized(_)) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+
+    let mut app = KvsClient::new(addr)?.with_credentials("app", "secret");
+    app.handshake()?;
+    app.set("app/key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(app.get("app/key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(app.get("shared/key1".to_owned())?, None);
+    for res in [
+        app.set("shared/key1".to_owned(), "value1".to_owned()),
+        app.set("other/key1".to_owned(), "value1".to_owned()),
+        app.remove("shared/key1".to_owned()),
+    ] {
+        match res {
+            Err(KvStoreError::PermissionDenied(_)) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+    match app.get("other/key1".to_owned()) {
+        Err(KvStoreError::PermissionDenied(_)) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+    app.quit()?;
+
+    let mut reader = KvsClient::new(addr)?.with_credentials("reader", "hunter2");
+    reader.handshake()?;
+    assert_eq!(
+        reader.get("app/key1".to_owned())?,
+        Some("value1".to_owned())
+    );
+    match reader.remove("app/key1".to_owned()) {
+        Err(KvStoreError::PermissionDenied(_)) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+    reader.quit()?;
+    Ok(())
+}
+
+#[test]
+fn password_hashing() {
+    let credentials = |password: &str| Credentials {
+        user: "app".to_owned(),
+        password: password.to_owned(),
+    };
+    let mut auth = authenticator();
+    assert!(auth.users[0].password_hash.starts_with("pbkdf2-sha256$"));
+    assert!(auth.authenticate(&credentials("secret")).is_some());
+    assert!(auth.authenticate(&credentials("Secret")).is_none());
+
+    // the salt goes into the hash
+    auth.users[0].salt = "s2".to_owned();
+    assert!(auth.authenticate(&credentials("secret")).is_none());
+
+    // neither a tampered nor a plain sha256 hash lets anybody in
+    auth.users[0].salt = "s1".to_owned();
+    let hash = auth.users[0].password_hash.clone();
+    auth.users[0].password_hash = hash.replacen("$100000$", "$1$", 1);
+    assert!(auth.authenticate(&credentials("secret")).is_none());
+    auth.users[0].password_hash = hash.rsplit('$').next().unwrap().to_owned();
+    assert!(auth.authenticate(&credentials("secret")).is_none());
+
+    // an unknown user costs as much as a wrong password
+    auth.users[0].password_hash = hash;
+    let start = Instant::now();
+    assert!(auth.authenticate(&credentials("wrong")).is_none());
+    let wrong = start.elapsed();
+    let start = Instant::now();
+    let unknown = Credentials {
+        user: "nobody".to_owned(),
+        password: "wrong".to_owned(),
+    };
+    assert!(auth.authenticate(&unknown).is_none());
+    assert!(start.elapsed() * 2 >= wrong);
+}
+
+#[test]
+fn thread_server_auth() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4131".parse().unwrap();
+    let store = KvStore::open(temp_dir.path())?;
+    let pool = SharedQueueThreadPool::new(2)?;
+    let (c_tx, s_rx) = unbounded();
+    let (s_tx, c_rx) = unbounded();
+    let mut server = KvsServer::new(store, pool)
+        .rx(c_rx)
+        .tx(c_tx)
+        .auth(authenticator());
+    thread::spawn(move || {
+        server.listen(addr).unwrap();
+    });
+    thread::sleep(Duration::from_millis(500));
+
+    check_access(addr)?;
+
+    s_tx.send(()).unwrap();
+    KvsClient::new(addr)?;
+    s_rx.recv().unwrap();
+    Ok(())
+}
+
+#[test]
+fn reactor_server_auth() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4132".parse().unwrap();
+    let store = KvStore::open(temp_dir.path())?;
+    let pool = SharedQueueThreadPool::new(2)?;
+    let (c_tx, s_rx) = unbounded();
+    let (s_tx, c_rx) = unbounded();
+    let mut server = KvsReactorServer::new(store, pool)
+        .rx(c_rx)
+        .tx(c_tx)
+        .auth(authenticator());
+    thread::spawn(move || {
+        server.listen(addr).unwrap();
+    });
+    thread::sleep(Duration::from_millis(500));
+
+    check_access(addr)?;
+
+    s_tx.send(()).unwrap();
+    KvsClient::new(addr)?;
+    s_rx.recv().unwrap();
+    Ok(())
+}
+
+#[test]
+fn cli_access_server_auth() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = temp_dir.path().join("users.json");
+    fs::write(&config, serde_json::to_string(&authenticator()).unwrap()).unwrap();
+    let addr = "127.0.0.1:4133";
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(["--engine", "kvs", "--addr", addr])
+        .arg("--auth-config")
+        .arg(&config)
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "app/key1", "value1", "--addr", addr])
+        .args(["--user", "app", "--password", "secret"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "app/key1", "--addr", addr])
+        .args(["--user", "reader", "--password", "hunter2"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value1\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["rm", "app/key1", "--addr", addr])
+        .args(["--user", "reader", "--password", "hunter2"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "app/key1", "--addr", addr])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+
+    child.kill().expect("server exited before killed");
+    child.wait().unwrap();
+}
+
+#[test]
+fn cli_replica_of_auth_leader() {
+    let leader_dir = TempDir::new().unwrap();
+    let follower_dir = TempDir::new().unwrap();
+    let config = leader_dir.path().join("users.json");
+    fs::write(&config, serde_json::to_string(&authenticator()).unwrap()).unwrap();
+    let leader_addr = "127.0.0.1:4134";
+    let follower_addr = "127.0.0.1:4135";
+    let mut leader = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--engine", "kvs", "--addr", leader_addr])
+        .arg("--auth-config")
+        .arg(&config)
+        .current_dir(&leader_dir)
+        .spawn()
+        .unwrap();
+    let mut follower = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--engine", "kvs", "--addr", follower_addr])
+        .args(["--replica-of", leader_addr])
+        .args(["--leader-user", "reader", "--leader-password", "hunter2"])
+        .current_dir(&follower_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "app/key1", "value1", "--addr", leader_addr])
+        .args(["--user", "app", "--password", "secret"])
+        .assert()
+        .success();
+    thread::sleep(Duration::from_millis(500));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "app/key1", "--addr", follower_addr])
+        .assert()
+        .success()
+        .stdout("value1\n");
+
+    leader.kill().expect("server exited before killed");
+    leader.wait().unwrap();
+    follower.kill().expect("server exited before killed");
+    follower.wait().unwrap();
+}
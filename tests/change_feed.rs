@@ -0,0 +1,124 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use kvs::{Change, KvStore, KvStoreError, KvsEngine, Result};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn change(seq: u64, key: &str, value: Option<&str>) -> Change {
+    Change {
+        seq,
+        key: key.to_owned(),
+        value: value.map(|v| v.to_owned()),
+    }
+}
+
+#[test]
+fn tail_from_sequence() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.last_seq(), 0);
+    assert_eq!(store.changes(0)?.count(), 0);
+
+    store.set("a".to_owned(), "1".to_owned())?;
+    store.set("b".to_owned(), "2".to_owned())?;
+    store.remove("a".to_owned())?;
+    // a failed write takes no sequence number
+    assert!(store.remove("a".to_owned()).is_err());
+    assert_eq!(store.last_seq(), 3);
+
+    let changes = store.changes(0)?.collect::<Result<Vec<_>>>()?;
+    assert_eq!(
+        changes,
+        vec![
+            change(1, "a", Some("1")),
+            change(2, "b", Some("2")),
+            change(3, "a", None),
+        ]
+    );
+    let changes = store.changes(2)?.collect::<Result<Vec<_>>>()?;
+    assert_eq!(changes, vec![change(3, "a", None)]);
+    assert_eq!(store.changes(3)?.count(), 0);
+
+    // sequence numbers carry on after a reopen
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.last_seq(), 3);
+    store.set("c".to_owned(), "3".to_owned())?;
+    let changes = store.changes(2)?.collect::<Result<Vec<_>>>()?;
+    assert_eq!(
+        changes,
+        vec![change(3, "a", None), change(4, "c", Some("3"))]
+    );
+    Ok(())
+}
+
+#[test]
+fn retained_history_survives_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?.retain_logs(1)?;
+    common::fill(|k, v| store.set(k, v))?;
+    assert_eq!(store.last_seq(), 1500);
+
+    let seqs = |store: &KvStore| -> Result<Vec<u64>> {
+        store.changes(0)?.map(|c| c.map(|c| c.seq)).collect()
+    };
+    assert_eq!(seqs(&store)?, (1..=1500).collect::<Vec<_>>());
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.last_seq(), 1500);
+    assert_eq!(seqs(&store)?, (1..=1500).collect::<Vec<_>>());
+    assert_eq!(store.get("key42".to_owned())?, Some("x".repeat(1000)));
+
+    // the setting is kept, the next compaction keeps the last log around
+    assert_eq!(store.retained_logs(), 1);
+    common::fill(|k, v| store.set(k, v))?;
+    let seqs = store
+        .changes(1500)?
+        .map(|c| c.map(|c| c.seq))
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(seqs, (1501..=3000).collect::<Vec<_>>());
+    Ok(())
+}
+
+#[test]
+fn compacted_changes_are_gone() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    common::fill(|k, v| store.set(k, v))?;
+
+    let oldest = match store.changes(0) {
+        Err(KvStoreError::ChangesCompacted(seq)) => seq,
+        Err(e) => return Err(e),
+        Ok(_) => panic!("No compaction detected"),
+    };
+    assert!(oldest > 1 && oldest <= 1500);
+    // the live part of the current log is still there
+    let changes = store
+        .changes(oldest - 1)?
+        .map(|c| c.map(|c| c.seq))
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(changes, (oldest..=1500).collect::<Vec<_>>());
+    Ok(())
+}
+
+#[test]
+fn cli_retain_logs() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut server = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--engine", "kvs", "--addr", "127.0.0.1:4110"])
+        .args(["--retain-logs", "2"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    server.kill().expect("server exited before killed");
+    server.wait().unwrap();
+
+    assert_eq!(KvStore::open(temp_dir.path())?.retained_logs(), 2);
+    Ok(())
+}
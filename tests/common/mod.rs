@@ -0,0 +1,31 @@
+// Fixtures shared by the test crates, each uses its own share of them
+#![allow(dead_code)]
+
+use crossbeam::channel::{unbounded, Sender};
+use kvs::thread_pool::ThreadPool;
+use kvs::Result;
+
+// 1KB values over 100 keys, enough for exactly one compaction
+pub fn fill<F>(mut set: F) -> Result<()>
+where
+    F: FnMut(String, String) -> Result<()>,
+{
+    let value = "x".repeat(1000);
+    for i in 0..1500 {
+        set(format!("key{}", i % 100), value.clone())?;
+    }
+    Ok(())
+}
+
+// Holds a worker of `pool` until the returned sender is dropped,
+// returns once the worker took the job
+pub fn hold_worker<P: ThreadPool>(pool: &P) -> Sender<()> {
+    let (tx, rx) = unbounded::<()>();
+    let (started_tx, started_rx) = unbounded::<()>();
+    pool.spawn(move || {
+        started_tx.send(()).unwrap();
+        let _ = rx.recv();
+    });
+    started_rx.recv().unwrap();
+    tx
+}
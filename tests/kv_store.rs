@@ -1,6 +1,7 @@
 use kvs::{KvStore, KvsEngine, Result};
 use std::sync::{Arc, Barrier};
 use std::thread;
+use std::time::{Duration, Instant};
 use tempfile::TempDir;
 use walkdir::WalkDir;
 
@@ -81,6 +82,29 @@ fn remove_key() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn scan_prefix() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("a/2".to_owned(), "value2".to_owned())?;
+    store.set("a/1".to_owned(), "value1".to_owned())?;
+    store.set("b/1".to_owned(), "value3".to_owned())?;
+    store.set("a/3".to_owned(), "value4".to_owned())?;
+    store.remove("a/3".to_owned())?;
+
+    let pairs = store.scan("a/".to_owned())?;
+    assert_eq!(
+        pairs,
+        vec![
+            ("a/1".to_owned(), "value1".to_owned()),
+            ("a/2".to_owned(), "value2".to_owned()),
+        ]
+    );
+    assert_eq!(store.scan("".to_owned())?.len(), 3);
+    assert!(store.scan("c/".to_owned())?.is_empty());
+    Ok(())
+}
+
 // Insert data until total size of the directory decreases.
 // Test data correctness after compaction.
 #[test]
@@ -158,6 +182,75 @@ fn concurrent_set() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn concurrent_set_during_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    // about 2MB, a couple of compactions while the others keep writing
+    let handles: Vec<_> = (0..4)
+        .map(|t| {
+            let store = store.clone();
+            thread::spawn(move || {
+                for round in 0..10 {
+                    for i in 0..50 {
+                        let value = format!("{}-{}", round, "x".repeat(1000));
+                        store.set(format!("key{}-{}", t, i), value).unwrap();
+                    }
+                }
+                store.remove(format!("key{}-0", t)).unwrap();
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let check = |store: &KvStore| -> Result<()> {
+        for t in 0..4 {
+            assert_eq!(store.get(format!("key{}-0", t))?, None);
+            for i in 1..50 {
+                let value = store.get(format!("key{}-{}", t, i))?;
+                assert_eq!(value, Some(format!("9-{}", "x".repeat(1000))));
+            }
+        }
+        Ok(())
+    };
+    check(&store)?;
+    drop(store);
+    check(&KvStore::open(temp_dir.path())?)
+}
+
+#[test]
+fn set_during_maintenance() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    // as a session would, until the compaction is handed to `maintain`
+    let value = "x".repeat(1000);
+    let mut i = 0;
+    while !store.maintenance_due() {
+        store.set(format!("key{}", i), value.clone())?;
+        i += 1;
+    }
+
+    let maintenance = {
+        let store = store.clone();
+        thread::spawn(move || store.maintain())
+    };
+    thread::sleep(Duration::from_millis(5));
+    let start = Instant::now();
+    store.set("key".to_owned(), "value".to_owned())?;
+    let set = start.elapsed();
+    maintenance.join().unwrap()?;
+
+    // the writer didn't wait for the compaction, nor ran one of its own
+    let stats = store.stats()?;
+    assert_eq!(stats.compactions, 1);
+    assert!(set.as_secs_f64() * 2.0 < stats.compaction_secs);
+    assert_eq!(store.get("key".to_owned())?, Some("value".to_owned()));
+    assert_eq!(store.get("key0".to_owned())?, Some(value));
+    Ok(())
+}
+
 #[test]
 fn concurrent_get() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
@@ -0,0 +1,131 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use crossbeam::channel::{unbounded, Sender};
+use kvs::network::{metrics, KvsClient, KvsServer};
+use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+use kvs::{KvStore, KvsEngine, Result};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn connect(addr: SocketAddr) -> Result<KvsClient> {
+    let mut client = KvsClient::new(addr)?;
+    client.handshake()?;
+    Ok(client)
+}
+
+#[test]
+fn render_server_metrics() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let addr: SocketAddr = "127.0.0.1:4221".parse().unwrap();
+    let (c_tx, s_rx) = unbounded();
+    let (s_tx, c_rx): (Sender<()>, _) = unbounded();
+    let mut server = KvsServer::new(store.clone(), SharedQueueThreadPool::new(2)?)
+        .rx(c_rx)
+        .tx(c_tx);
+    let ctx = server.context();
+    thread::spawn(move || server.listen(addr).unwrap());
+    thread::sleep(Duration::from_millis(300));
+
+    let mut client = connect(addr)?;
+    client.set("a".to_owned(), "1".to_owned())?;
+    client.set("b".to_owned(), "2".to_owned())?;
+    client.remove("b".to_owned())?;
+    assert!(client.remove("b".to_owned()).is_err());
+    client.get("a".to_owned())?;
+
+    let out = metrics::render(&store, &ctx);
+    for line in &[
+        "# TYPE kvs_requests_total counter",
+        "kvs_requests_total{command=\"set\"} 2",
+        "kvs_requests_total{command=\"rm\"} 2",
+        "kvs_request_errors_total{command=\"rm\"} 1",
+        "kvs_request_errors_total{command=\"get\"} 0",
+        "# TYPE kvs_request_duration_seconds histogram",
+        "kvs_request_duration_seconds_bucket{command=\"set\",le=\"+Inf\"} 2",
+        "kvs_request_duration_seconds_count{command=\"get\"} 1",
+        "kvs_active_sessions 1",
+        "kvs_connections_total 1",
+        "kvs_keys 1",
+        "kvs_compactions_total 0",
+        "kvs_pool_threads 2",
+        "kvs_pool_queued_jobs 0",
+        // the session of `client`
+        "kvs_pool_active_workers 1",
+        "kvs_pool_panics_total 0",
+    ] {
+        assert!(
+            out.lines().any(|l| l == *line),
+            "missing {} in\n{}",
+            line,
+            out
+        );
+    }
+    // buckets are cumulative
+    let buckets: Vec<u64> = out
+        .lines()
+        .filter(|l| l.starts_with("kvs_request_duration_seconds_bucket{command=\"set\""))
+        .map(|l| l.rsplit(' ').next().unwrap().parse().unwrap())
+        .collect();
+    assert!(buckets.windows(2).all(|w| w[0] <= w[1]));
+    assert_eq!(buckets.last(), Some(&2));
+
+    s_tx.send(()).unwrap();
+    let _ = KvsClient::new(addr);
+    s_rx.recv().unwrap();
+    Ok(())
+}
+
+#[test]
+fn compaction_stats() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    common::fill(|k, v| store.set(k, v))?;
+    let stats = store.stats()?;
+    assert_eq!(stats.compactions, 1);
+    assert!(stats.compaction_secs > 0.0);
+    Ok(())
+}
+
+fn scrape(addr: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).unwrap();
+    resp
+}
+
+#[test]
+fn cli_metrics() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut server = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args([
+            "--addr",
+            "127.0.0.1:4222",
+            "--metrics-addr",
+            "127.0.0.1:4223",
+        ])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key1", "value1", "--addr", "127.0.0.1:4222"])
+        .assert()
+        .success();
+    let resp = scrape("127.0.0.1:4223");
+    assert!(resp.starts_with("HTTP/1.1 200 OK"), "{}", resp);
+    assert!(resp.contains("\nkvs_requests_total{command=\"set\"} 1\n"));
+    assert!(resp.contains("\nkvs_keys 1\n"));
+
+    server.kill().expect("server exited before killed");
+    server.wait().unwrap();
+}
@@ -0,0 +1,263 @@
+use assert_cmd::prelude::*;
+use crossbeam::channel::{unbounded, Sender};
+use kvs::network::auth::{Access, AclRule, User};
+use kvs::network::{
+    Authenticator, KvsClient, KvsClientPool, KvsServer, ProxyKvsEngine, ShardedKvsClient,
+};
+use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+use kvs::{KvStore, KvStoreError, KvsEngine, Result};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+// Serve `store` in the background, the returned closure stops the server
+fn start_server<E: KvsEngine>(addr: SocketAddr, store: E) -> Result<impl FnOnce()> {
+    let pool = SharedQueueThreadPool::new(4)?;
+    let (c_tx, s_rx) = unbounded();
+    let (s_tx, c_rx): (Sender<()>, _) = unbounded();
+    let mut server = KvsServer::new(store, pool).rx(c_rx).tx(c_tx);
+    thread::spawn(move || {
+        server.listen(addr).unwrap();
+    });
+    thread::sleep(Duration::from_millis(300));
+    Ok(move || {
+        s_tx.send(()).unwrap();
+        let _ = KvsClient::new(addr);
+        s_rx.recv().unwrap();
+    })
+}
+
+fn connect(addr: SocketAddr) -> Result<KvsClient> {
+    let mut client = KvsClient::new(addr)?;
+    client.handshake()?;
+    Ok(client)
+}
+
+#[test]
+fn proxy_routes_by_key() -> Result<()> {
+    let dirs: Vec<TempDir> = (0..2)
+        .map(|_| TempDir::new().expect("unable to create temporary working directory"))
+        .collect();
+    let backends: Vec<SocketAddr> = vec![
+        "127.0.0.1:4191".parse().unwrap(),
+        "127.0.0.1:4192".parse().unwrap(),
+    ];
+    let proxy_addr: SocketAddr = "127.0.0.1:4193".parse().unwrap();
+    let mut stops = vec![];
+    for (addr, dir) in backends.iter().zip(&dirs) {
+        stops.push(start_server(*addr, KvStore::open(dir.path())?)?);
+    }
+    let pools = backends
+        .iter()
+        .map(|addr| KvsClientPool::new(*addr, 4))
+        .collect();
+    let proxy = ProxyKvsEngine::new(pools, 100);
+    assert!(proxy.check_health().iter().all(|h| h.healthy));
+    let stop_proxy = start_server(proxy_addr, proxy.clone())?;
+
+    let mut client = connect(proxy_addr)?;
+    for i in 0..20 {
+        client.set(format!("key{:02}", i), format!("value{}", i))?;
+    }
+    assert_eq!(client.get("key03".to_owned())?, Some("value3".to_owned()));
+    match client.remove("missing".to_owned()) {
+        Err(KvStoreError::KeyNotFound) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+    assert_eq!(client.scan("key".to_owned())?.len(), 20);
+    assert_eq!(proxy.stats()?.keys, 20);
+
+    // same layout as the sharded client
+    let mut sharded = ShardedKvsClient::new(&backends);
+    let owners: Vec<SocketAddr> = (0..20)
+        .map(|i| sharded.node(&format!("key{:02}", i)).unwrap())
+        .collect();
+    for addr in &backends {
+        let keys = connect(*addr)?.scan(String::new())?;
+        assert_eq!(keys.len(), owners.iter().filter(|a| *a == addr).count());
+    }
+    assert_eq!(sharded.get("key03".to_owned())?, Some("value3".to_owned()));
+
+    // a dead backend only takes its own keys down
+    stops.pop().unwrap()();
+    let health = proxy.check_health();
+    assert!(health[0].healthy);
+    assert!(!health[1].healthy);
+    assert!(format!("{}", health[1]).contains("down"));
+    for (i, owner) in owners.iter().enumerate() {
+        let res = client.get(format!("key{:02}", i));
+        if *owner == backends[0] {
+            assert_eq!(res?, Some(format!("value{}", i)));
+        } else {
+            assert!(res.is_err());
+        }
+    }
+    // scans leave out the backend found down
+    let keys: Vec<String> = client
+        .scan("key".to_owned())?
+        .into_iter()
+        .map(|(k, _)| k)
+        .collect();
+    let expected: Vec<String> = (0..20)
+        .filter(|&i| owners[i] == backends[0])
+        .map(|i| format!("key{:02}", i))
+        .collect();
+    assert_eq!(keys, expected);
+
+    stop_proxy();
+    for stop in stops {
+        stop();
+    }
+    Ok(())
+}
+
+fn status(addr: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).unwrap();
+    resp
+}
+
+#[test]
+fn cli_proxy() {
+    let dirs: Vec<TempDir> = (0..3)
+        .map(|_| TempDir::new().expect("unable to create temporary working directory"))
+        .collect();
+    let mut children = vec![];
+    // the pooled connections stay open, a thread per connection
+    // server could run out of threads for the health checks
+    for (i, addr) in ["127.0.0.1:4194", "127.0.0.1:4195"].iter().enumerate() {
+        let mut server = Command::cargo_bin("kvs-server").unwrap();
+        server
+            .args(["--addr", addr, "--mode", "reactor"])
+            .current_dir(&dirs[i]);
+        children.push(server.spawn().unwrap());
+    }
+    let mut proxy = Command::cargo_bin("kvs-proxy").unwrap();
+    proxy
+        .args([
+            "--addr",
+            "127.0.0.1:4196",
+            "--status-addr",
+            "127.0.0.1:4197",
+        ])
+        .args(["--backend", "127.0.0.1:4194", "--backend", "127.0.0.1:4195"])
+        .args(["--health-interval", "100"])
+        .current_dir(&dirs[2]);
+    children.push(proxy.spawn().unwrap());
+    thread::sleep(Duration::from_secs(1));
+
+    let resp = status("127.0.0.1:4197");
+    assert!(resp.starts_with("HTTP/1.1 200 OK"), "{}", resp);
+    assert!(resp.contains("127.0.0.1:4194 up"));
+    assert!(resp.contains("127.0.0.1:4195 up"));
+
+    for i in 0..10 {
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(["set", &format!("key{}", i), &format!("value{}", i)])
+            .args(["--addr", "127.0.0.1:4196"])
+            .assert()
+            .success();
+    }
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key7", "--addr", "127.0.0.1:4196"])
+        .assert()
+        .success()
+        .stdout("value7\n");
+
+    // the proxy notices a backend going away
+    children[1].kill().unwrap();
+    children[1].wait().unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let resp = loop {
+        let resp = status("127.0.0.1:4197");
+        if resp.starts_with("HTTP/1.1 503") || Instant::now() > deadline {
+            break resp;
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    assert!(resp.starts_with("HTTP/1.1 503"), "{}", resp);
+    assert!(resp.contains("127.0.0.1:4195 down"));
+
+    for mut child in children {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+#[test]
+fn cli_proxy_auth() {
+    let dirs: Vec<TempDir> = (0..2)
+        .map(|_| TempDir::new().expect("unable to create temporary working directory"))
+        .collect();
+    let rule = AclRule {
+        prefix: "app/".to_owned(),
+        access: Access::ReadWrite,
+    };
+    let auth = Authenticator {
+        users: vec![User::new("app", "s1", "secret", vec![rule])],
+    };
+    let config = dirs[0].path().join("users.json");
+    fs::write(&config, serde_json::to_string(&auth).unwrap()).unwrap();
+
+    // backend credentials would be open to anybody reaching the proxy
+    Command::cargo_bin("kvs-proxy")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4199", "--backend", "127.0.0.1:4198"])
+        .args(["--backend-user", "app", "--backend-password", "secret"])
+        .current_dir(&dirs[1])
+        .assert()
+        .failure();
+
+    let mut backend = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4198", "--mode", "reactor"])
+        .arg("--auth-config")
+        .arg(&config)
+        .current_dir(&dirs[0])
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    let mut proxy = Command::cargo_bin("kvs-proxy")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4199", "--backend", "127.0.0.1:4198"])
+        .args(["--backend-user", "app", "--backend-password", "secret"])
+        .args(["--health-interval", "100"])
+        .arg("--auth-config")
+        .arg(&config)
+        .current_dir(&dirs[1])
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "app/key1", "value1", "--addr", "127.0.0.1:4199"])
+        .assert()
+        .failure();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "app/key1", "value1", "--addr", "127.0.0.1:4199"])
+        .args(["--user", "app", "--password", "secret"])
+        .assert()
+        .success();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "app/key1", "--addr", "127.0.0.1:4199"])
+        .args(["--user", "app", "--password", "secret"])
+        .assert()
+        .success()
+        .stdout("value1\n");
+
+    let _ = proxy.kill();
+    let _ = proxy.wait();
+    let _ = backend.kill();
+    let _ = backend.wait();
+}
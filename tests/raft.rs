@@ -0,0 +1,299 @@
+use assert_cmd::prelude::*;
+use kvs::raft::{self, Payload, RaftConfig, RaftKvsEngine, RaftRequest, RaftResponse, Role};
+use kvs::{KvStore, KvStoreError, KvsEngine, Result};
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+fn addr(port: u16) -> SocketAddr {
+    format!("127.0.0.1:{}", port).parse().unwrap()
+}
+
+// Node `id` listening on `port`, peers are (id, port) pairs, none joins an existing cluster
+fn start_node(
+    id: u64,
+    port: u16,
+    peers: &[(u64, u16)],
+    dir: &TempDir,
+) -> Result<RaftKvsEngine<KvStore>> {
+    let store = KvStore::open(dir.path())?;
+    let mut config = RaftConfig::new(id, addr(port), dir.path().join("raft"))
+        .heartbeat_interval(Duration::from_millis(20))
+        .election_timeout(Duration::from_millis(150), Duration::from_millis(300))
+        .snapshot_threshold(5);
+    if !peers.is_empty() {
+        config = config.peers(peers.iter().map(|&(id, port)| (id, addr(port))).collect());
+    }
+    RaftKvsEngine::start(config, store)
+}
+
+fn start_cluster(ports: &[u16], dirs: &[TempDir]) -> Result<Vec<RaftKvsEngine<KvStore>>> {
+    let mut nodes = vec![];
+    for (i, &port) in ports.iter().enumerate() {
+        let peers: Vec<(u64, u16)> = ports
+            .iter()
+            .enumerate()
+            .filter(|&(j, _)| j != i)
+            .map(|(j, &p)| (j as u64 + 1, p))
+            .collect();
+        nodes.push(start_node(i as u64 + 1, port, &peers, &dirs[i])?);
+    }
+    Ok(nodes)
+}
+
+fn wait_leader(nodes: &[&RaftKvsEngine<KvStore>]) -> usize {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        if let Some(i) = nodes.iter().position(|n| n.node().is_leader()) {
+            return i;
+        }
+        assert!(Instant::now() < deadline, "no leader elected");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+// Followers apply committed entries a heartbeat later
+fn wait_value(dir: &TempDir, key: &str, value: Option<&str>) -> Result<()> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let got = KvStore::open(dir.path())?.get(key.to_owned())?;
+        if got.as_deref() == value {
+            return Ok(());
+        }
+        assert!(Instant::now() < deadline, "{} is {:?}", key, got);
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+fn temp_dirs(n: usize) -> Vec<TempDir> {
+    (0..n)
+        .map(|_| TempDir::new().expect("unable to create temporary working directory"))
+        .collect()
+}
+
+#[test]
+fn three_node_cluster() -> Result<()> {
+    let dirs = temp_dirs(3);
+    let nodes = start_cluster(&[4161, 4162, 4163], &dirs)?;
+    let leader = wait_leader(&nodes.iter().collect::<Vec<_>>());
+
+    nodes[leader].set("key1".to_owned(), "value1".to_owned())?;
+    nodes[leader].set("key2".to_owned(), "value2".to_owned())?;
+    assert_eq!(
+        nodes[leader].get("key1".to_owned())?,
+        Some("value1".to_owned())
+    );
+    // reads don't go through the log
+    let commit = nodes[leader].node().status().commit;
+    for _ in 0..10 {
+        nodes[leader].get("key1".to_owned())?;
+    }
+    assert_eq!(nodes[leader].node().status().commit, commit);
+
+    // the raft port takes membership changes only, writes need a session
+    let stream = TcpStream::connect(addr(4161 + leader as u16))?;
+    let set = Payload::Set("key3".to_owned(), "value3".to_owned());
+    (&stream).write_all(&serde_json::to_vec(&RaftRequest::ChangeMembers(set))?)?;
+    match serde_json::Deserializer::from_reader(&stream)
+        .into_iter::<RaftResponse>()
+        .next()
+    {
+        Some(Ok(RaftResponse::Changed(Err(_)))) => {}
+        resp => panic!("unexpected response {:?}", resp),
+    }
+    assert_eq!(nodes[leader].get("key3".to_owned())?, None);
+
+    nodes[leader].remove("key2".to_owned())?;
+    match nodes[leader].remove("key2".to_owned()) {
+        Err(KvStoreError::KeyNotFound) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+
+    for (i, node) in nodes.iter().enumerate() {
+        wait_value(&dirs[i], "key1", Some("value1"))?;
+        wait_value(&dirs[i], "key2", None)?;
+        if i != leader {
+            match node.set("key3".to_owned(), "value3".to_owned()) {
+                Err(KvStoreError::NotLeader(_)) => {}
+                res => panic!("unexpected result {:?}", res),
+            }
+            match node.get("key1".to_owned()) {
+                Err(KvStoreError::NotLeader(_)) => {}
+                res => panic!("unexpected result {:?}", res),
+            }
+        }
+    }
+
+    for node in &nodes {
+        node.node().shutdown();
+    }
+    Ok(())
+}
+
+#[test]
+fn leader_failover() -> Result<()> {
+    let dirs = temp_dirs(3);
+    let ports = [4164, 4165, 4166];
+    let mut nodes = start_cluster(&ports, &dirs)?;
+    let old = wait_leader(&nodes.iter().collect::<Vec<_>>());
+    nodes[old].set("key1".to_owned(), "value1".to_owned())?;
+
+    nodes[old].node().shutdown();
+    let rest: Vec<_> = (0..3).filter(|&i| i != old).collect();
+    let new = rest[wait_leader(&rest.iter().map(|&i| &nodes[i]).collect::<Vec<_>>())];
+    assert!(nodes[new].node().status().term > 1);
+    assert_eq!(
+        nodes[new].get("key1".to_owned())?,
+        Some("value1".to_owned())
+    );
+    // a majority is still there
+    nodes[new].set("key2".to_owned(), "value2".to_owned())?;
+
+    // the old leader recovers its log and catches up as a follower
+    thread::sleep(Duration::from_millis(200));
+    let peers: Vec<(u64, u16)> = rest.iter().map(|&i| (i as u64 + 1, ports[i])).collect();
+    nodes[old] = start_node(old as u64 + 1, ports[old], &peers, &dirs[old])?;
+    wait_value(&dirs[old], "key2", Some("value2"))?;
+    assert_eq!(nodes[old].node().status().role, Role::Follower);
+
+    for node in &nodes {
+        node.node().shutdown();
+    }
+    Ok(())
+}
+
+#[test]
+fn isolated_leader_reads() -> Result<()> {
+    let dirs = temp_dirs(3);
+    let nodes = start_cluster(&[4167, 4168, 4169], &dirs)?;
+    let leader = wait_leader(&nodes.iter().collect::<Vec<_>>());
+    nodes[leader].set("key1".to_owned(), "value1".to_owned())?;
+
+    // cut off from the others, the old leader may have been replaced
+    for (i, node) in nodes.iter().enumerate() {
+        if i != leader {
+            node.node().shutdown();
+        }
+    }
+    let (tx, rx) = crossbeam::channel::bounded(1);
+    let node = nodes[leader].clone();
+    thread::spawn(move || tx.send(node.get("key1".to_owned())).unwrap());
+    assert!(
+        rx.recv_timeout(Duration::from_secs(1)).is_err(),
+        "a leader without a majority answered a read"
+    );
+
+    nodes[leader].node().shutdown();
+    match rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+        Err(KvStoreError::Rpc(_)) | Err(KvStoreError::NotLeader(_)) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+    Ok(())
+}
+
+#[test]
+fn snapshot_and_membership_change() -> Result<()> {
+    let dirs = temp_dirs(4);
+    let ports = [4167, 4168, 4169];
+    let mut nodes = start_cluster(&ports, &dirs)?;
+    let leader = wait_leader(&nodes.iter().collect::<Vec<_>>());
+    for i in 0..30 {
+        nodes[leader].set(format!("key{}", i), format!("value{}", i))?;
+    }
+    // the log has been compacted well past the start
+    assert!(dirs[leader]
+        .path()
+        .join("raft")
+        .join("snapshot.json")
+        .exists());
+
+    // a new node only gets the tail of the log after the snapshot
+    nodes.push(start_node(4, 4170, &[], &dirs[3])?);
+    raft::add_node(addr(ports[leader]), 4, addr(4170))?;
+    wait_value(&dirs[3], "key0", Some("value0"))?;
+    wait_value(&dirs[3], "key29", Some("value29"))?;
+    assert_eq!(nodes[leader].node().status().members.len(), 4);
+
+    // only one change at a time, and only through the leader
+    let follower = (0..3).find(|&i| i != leader).unwrap();
+    assert!(raft::remove_node(addr(ports[follower]), 4).is_err());
+    raft::remove_node(addr(ports[leader]), follower as u64 + 1)?;
+    let members = nodes[leader].node().status().members;
+    assert_eq!(members.len(), 3);
+    assert!(!members.contains_key(&(follower as u64 + 1)));
+    nodes[follower].node().shutdown();
+
+    nodes[leader].set("key30".to_owned(), "value30".to_owned())?;
+    wait_value(&dirs[3], "key30", Some("value30"))?;
+
+    for node in &nodes {
+        node.node().shutdown();
+    }
+    Ok(())
+}
+
+#[test]
+fn cli_raft_cluster() {
+    let dirs = temp_dirs(3);
+    let client_addrs = ["127.0.0.1:4171", "127.0.0.1:4172", "127.0.0.1:4173"];
+    let raft_addrs = ["127.0.0.1:4174", "127.0.0.1:4175", "127.0.0.1:4176"];
+    let mut children = vec![];
+    for i in 0..3 {
+        let mut server = Command::cargo_bin("kvs-server").unwrap();
+        server
+            .args(["--engine", "kvs", "--addr", client_addrs[i]])
+            .args([
+                "--raft-id",
+                &(i + 1).to_string(),
+                "--raft-addr",
+                raft_addrs[i],
+            ])
+            .current_dir(&dirs[i]);
+        for j in (0..3).filter(|&j| j != i) {
+            server
+                .arg("--raft-peer")
+                .arg(format!("{}={}", j + 1, raft_addrs[j]));
+        }
+        children.push(server.spawn().unwrap());
+    }
+
+    // only the leader takes the write, wait for the election
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let leader = loop {
+        let leader = (0..3).find(|&i| {
+            Command::cargo_bin("kvs-client")
+                .unwrap()
+                .args(["set", "key1", "value1", "--addr", client_addrs[i]])
+                .output()
+                .unwrap()
+                .status
+                .success()
+        });
+        if let Some(leader) = leader {
+            break leader;
+        }
+        assert!(Instant::now() < deadline, "no leader elected");
+        thread::sleep(Duration::from_millis(200));
+    };
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "--addr", client_addrs[leader]])
+        .assert()
+        .success()
+        .stdout("value1\n");
+    let follower = (leader + 1) % 3;
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "--addr", client_addrs[follower]])
+        .assert()
+        .failure();
+
+    for mut child in children {
+        child.kill().expect("server exited before killed");
+        child.wait().unwrap();
+    }
+}
@@ -0,0 +1,262 @@
+use assert_cmd::prelude::*;
+use crossbeam::channel::{unbounded, Sender};
+use kvs::network::{Follower, KvsClient, KvsServer, Replication, ReplicationEvent};
+use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+use kvs::{KvStore, KvStoreError, KvsEngine, Result};
+use std::net::SocketAddr;
+use std::process::{Child, Command};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+// Serve `store` in the background, the returned closure stops the server
+fn start_server(
+    addr: SocketAddr,
+    store: KvStore,
+    replication: Arc<Replication>,
+) -> Result<impl FnOnce()> {
+    let pool = SharedQueueThreadPool::new(4)?;
+    let (c_tx, s_rx) = unbounded();
+    let (s_tx, c_rx): (Sender<()>, _) = unbounded();
+    let mut server = KvsServer::new(store, pool)
+        .rx(c_rx)
+        .tx(c_tx)
+        .replication(replication);
+    thread::spawn(move || {
+        server.listen(addr).unwrap();
+    });
+    thread::sleep(Duration::from_millis(300));
+    Ok(move || {
+        s_tx.send(()).unwrap();
+        let _ = KvsClient::new(addr);
+        s_rx.recv().unwrap();
+    })
+}
+
+fn connect(addr: SocketAddr) -> Result<KvsClient> {
+    let mut client = KvsClient::new(addr)?;
+    client.handshake()?;
+    Ok(client)
+}
+
+// Replication is asynchronous, poll the follower for a while
+fn wait_for(client: &mut KvsClient, key: &str, value: Option<&str>) -> Result<()> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let got = client.get(key.to_owned())?;
+        if got.as_deref() == value {
+            return Ok(());
+        }
+        assert!(Instant::now() < deadline, "{} is {:?}", key, got);
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn follower_snapshot_and_stream() -> Result<()> {
+    let leader_dir = TempDir::new().expect("unable to create temporary working directory");
+    let follower_dir = TempDir::new().expect("unable to create temporary working directory");
+    let leader_addr: SocketAddr = "127.0.0.1:4141".parse().unwrap();
+    let follower_addr: SocketAddr = "127.0.0.1:4142".parse().unwrap();
+
+    let leader_store = KvStore::open(leader_dir.path())?;
+    leader_store.set("key1".to_owned(), "value1".to_owned())?;
+    leader_store.set("key2".to_owned(), "value2".to_owned())?;
+    let stop_leader = start_server(leader_addr, leader_store, Arc::new(Replication::leader()))?;
+
+    // left over from an earlier run, not on the leader anymore
+    let follower_store = KvStore::open(follower_dir.path())?;
+    follower_store.set("stale".to_owned(), "value".to_owned())?;
+    let replication = Arc::new(Replication::follower());
+    let follower = Follower::new(leader_addr, follower_store.clone(), replication.clone()).spawn();
+    let stop_follower = start_server(follower_addr, follower_store, replication)?;
+
+    let mut follower_client = connect(follower_addr)?;
+    wait_for(&mut follower_client, "key1", Some("value1"))?;
+    wait_for(&mut follower_client, "key2", Some("value2"))?;
+    wait_for(&mut follower_client, "stale", None)?;
+
+    let mut leader_client = connect(leader_addr)?;
+    leader_client.set("key3".to_owned(), "value3".to_owned())?;
+    leader_client.set("key1".to_owned(), "value4".to_owned())?;
+    leader_client.remove("key2".to_owned())?;
+    wait_for(&mut follower_client, "key3", Some("value3"))?;
+    wait_for(&mut follower_client, "key1", Some("value4"))?;
+    wait_for(&mut follower_client, "key2", None)?;
+
+    match follower_client.set("key5".to_owned(), "value5".to_owned()) {
+        Err(KvStoreError::ReadOnly(_)) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+    match follower_client.remove("key1".to_owned()) {
+        Err(KvStoreError::ReadOnly(_)) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+
+    // the leader goes away, the follower takes over
+    stop_leader();
+    follower_client.promote()?;
+    follower.join().unwrap();
+    follower_client.set("key5".to_owned(), "value5".to_owned())?;
+    assert_eq!(
+        follower_client.get("key5".to_owned())?,
+        Some("value5".to_owned())
+    );
+    assert_eq!(
+        follower_client.get("key1".to_owned())?,
+        Some("value4".to_owned())
+    );
+
+    stop_follower();
+    Ok(())
+}
+
+fn spawn_leader(addr: &str, dir: &TempDir) -> Child {
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--engine", "kvs", "--addr", addr])
+        .current_dir(dir)
+        .spawn()
+        .unwrap()
+}
+
+#[test]
+fn follower_reconnects_to_restarted_leader() -> Result<()> {
+    let leader_dir = TempDir::new().expect("unable to create temporary working directory");
+    let follower_dir = TempDir::new().expect("unable to create temporary working directory");
+    let leader_addr: SocketAddr = "127.0.0.1:4143".parse().unwrap();
+    let follower_addr: SocketAddr = "127.0.0.1:4144".parse().unwrap();
+
+    // the leader is not up yet
+    let follower_store = KvStore::open(follower_dir.path())?;
+    let replication = Arc::new(Replication::follower());
+    Follower::new(leader_addr, follower_store.clone(), replication.clone()).spawn();
+    let stop_follower = start_server(follower_addr, follower_store, replication)?;
+
+    let mut leader = spawn_leader("127.0.0.1:4143", &leader_dir);
+    thread::sleep(Duration::from_secs(1));
+    let mut follower_client = connect(follower_addr).unwrap();
+    connect(leader_addr)
+        .unwrap()
+        .set("key1".to_owned(), "value1".to_owned())
+        .unwrap();
+    wait_for(&mut follower_client, "key1", Some("value1")).unwrap();
+
+    leader.kill().expect("server exited before killed");
+    leader.wait().unwrap();
+    let mut leader = spawn_leader("127.0.0.1:4143", &leader_dir);
+    thread::sleep(Duration::from_secs(1));
+    connect(leader_addr)
+        .unwrap()
+        .set("key2".to_owned(), "value2".to_owned())
+        .unwrap();
+    wait_for(&mut follower_client, "key2", Some("value2")).unwrap();
+    wait_for(&mut follower_client, "key1", Some("value1")).unwrap();
+
+    leader.kill().expect("server exited before killed");
+    leader.wait().unwrap();
+    stop_follower();
+    Ok(())
+}
+
+#[test]
+fn cli_replica_of() {
+    let leader_dir = TempDir::new().unwrap();
+    let follower_dir = TempDir::new().unwrap();
+    let leader_addr = "127.0.0.1:4145";
+    let follower_addr = "127.0.0.1:4146";
+    let mut leader = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--engine", "kvs", "--addr", leader_addr])
+        .current_dir(&leader_dir)
+        .spawn()
+        .unwrap();
+    let mut follower = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--engine", "kvs", "--addr", follower_addr])
+        .args(["--replica-of", leader_addr])
+        .current_dir(&follower_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key1", "value1", "--addr", leader_addr])
+        .assert()
+        .success();
+    thread::sleep(Duration::from_millis(500));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "--addr", follower_addr])
+        .assert()
+        .success()
+        .stdout("value1\n");
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key2", "value2", "--addr", follower_addr])
+        .assert()
+        .failure();
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["promote", "--addr", follower_addr])
+        .assert()
+        .success();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key2", "value2", "--addr", follower_addr])
+        .assert()
+        .success();
+
+    leader.kill().expect("server exited before killed");
+    leader.wait().unwrap();
+    follower.kill().expect("server exited before killed");
+    follower.wait().unwrap();
+}
+
+#[test]
+fn replication_write_order() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let replication = Arc::new(Replication::leader());
+
+    // nobody follows, a slow write doesn't hold up the others
+    let (release_tx, release_rx) = unbounded::<()>();
+    let slow = {
+        let replication = replication.clone();
+        thread::spawn(move || {
+            replication.write(ReplicationEvent::Remove("slow".to_owned()), || {
+                let _ = release_rx.recv();
+                Ok(())
+            })
+        })
+    };
+    thread::sleep(Duration::from_millis(50));
+    let (done_tx, done_rx) = unbounded();
+    {
+        let (replication, store) = (replication.clone(), store.clone());
+        thread::spawn(move || {
+            let event = ReplicationEvent::Set("key1".to_owned(), "value1".to_owned());
+            let res =
+                replication.write(event, || store.set("key1".to_owned(), "value1".to_owned()));
+            done_tx.send(res).unwrap();
+        });
+    }
+    done_rx.recv_timeout(Duration::from_secs(1)).unwrap()?;
+    drop(release_tx);
+    slow.join().unwrap()?;
+
+    // a follower falling behind is dropped instead of queuing without end
+    let (_snapshot, events) = replication.subscribe(&store)?;
+    assert_eq!(replication.follower_count(), 1);
+    for i in 0..20_000 {
+        let event = ReplicationEvent::Set(format!("key{}", i), "value".to_owned());
+        replication.write(event, || Ok(()))?;
+    }
+    assert_eq!(replication.follower_count(), 0);
+    assert!(!events.is_empty());
+    Ok(())
+}
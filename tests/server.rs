@@ -0,0 +1,382 @@
+mod common;
+
+use crossbeam::channel::unbounded;
+use kvs::network::{
+    ErrorCode, Feature, HandshakeRequest, HandshakeResp, KvsClient, KvsClientPool,
+    KvsReactorServer, KvsServer, SessionClientCommand, SessionError, SessionServerResp,
+    PROTOCOL_VERSION,
+};
+use kvs::thread_pool::{PriorityThreadPool, QueuePolicy, SharedQueueThreadPool, ThreadPool};
+use kvs::{KvStore, KvStoreError, KvsEngine, Result};
+use std::io::{self, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn access_server(addr: SocketAddr) -> Result<()> {
+    let mut client = KvsClient::new(addr)?;
+    client.handshake()?;
+    client.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(client.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(client.get("key2".to_owned())?, None);
+    match client.remove("key2".to_owned()) {
+        Err(KvStoreError::KeyNotFound) => {}
+        _ => panic!("remove non-existent key should fail"),
+    }
+    client.remove("key1".to_owned())?;
+    assert_eq!(client.get("key1".to_owned())?, None);
+    client.quit()?;
+    Ok(())
+}
+
+// Sessions which never quit must not block the other clients
+#[test]
+fn reactor_server_long_lived_clients() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4101".parse().unwrap();
+    let store = KvStore::open(temp_dir.path())?;
+    let pool = SharedQueueThreadPool::new(1)?;
+    let (c_tx, s_rx) = unbounded();
+    let (s_tx, c_rx) = unbounded();
+    let mut server = KvsReactorServer::new(store, pool)
+        .io_threads(2)
+        .rx(c_rx)
+        .tx(c_tx);
+    thread::spawn(move || {
+        server.listen(addr).unwrap();
+    });
+    thread::sleep(Duration::from_millis(500));
+
+    let mut idle = vec![];
+    for _ in 0..4 {
+        let mut client = KvsClient::new(addr)?;
+        client.handshake()?;
+        idle.push(client);
+    }
+    access_server(addr)?;
+    for mut client in idle {
+        client.quit()?;
+    }
+
+    s_tx.send(()).unwrap();
+    KvsClient::new(addr)?;
+    s_rx.recv().unwrap();
+    Ok(())
+}
+
+// A client which shuts down its write side after sending still gets every answer
+#[test]
+fn reactor_server_half_close() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4108".parse().unwrap();
+    let store = KvStore::open(temp_dir.path())?;
+    let pool = SharedQueueThreadPool::new(2)?;
+    let (c_tx, s_rx) = unbounded();
+    let (s_tx, c_rx) = unbounded();
+    let mut server = KvsReactorServer::new(store, pool).rx(c_rx).tx(c_tx);
+    thread::spawn(move || server.listen(addr).unwrap());
+    thread::sleep(Duration::from_millis(300));
+
+    let mut stream = TcpStream::connect(addr)?;
+    let mut cmds = vec![SessionClientCommand::Handshake(HandshakeRequest::default())];
+    for i in 0..10 {
+        cmds.push(SessionClientCommand::Set(
+            format!("key{}", i),
+            "value".to_owned(),
+        ));
+    }
+    cmds.push(SessionClientCommand::Get("key9".to_owned()));
+    for cmd in &cmds {
+        serde_json::to_writer(&mut stream, cmd)?;
+    }
+    stream.shutdown(Shutdown::Write)?;
+    let resps = serde_json::Deserializer::from_reader(stream)
+        .into_iter::<SessionServerResp>()
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    assert_eq!(resps.len(), cmds.len());
+    match resps.last() {
+        Some(SessionServerResp::Value(v)) => assert_eq!(v, "value"),
+        resp => panic!("unexpected response {:?}", resp),
+    }
+
+    s_tx.send(()).unwrap();
+    KvsClient::new(addr)?;
+    s_rx.recv().unwrap();
+    Ok(())
+}
+
+#[test]
+fn thread_server_access() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4102".parse().unwrap();
+    let store = KvStore::open(temp_dir.path())?;
+    let pool = SharedQueueThreadPool::new(2)?;
+    let (c_tx, s_rx) = unbounded();
+    let (s_tx, c_rx) = unbounded();
+    let mut server = KvsServer::new(store, pool).rx(c_rx).tx(c_tx);
+    thread::spawn(move || {
+        server.listen(addr).unwrap();
+    });
+    thread::sleep(Duration::from_millis(500));
+
+    access_server(addr)?;
+
+    s_tx.send(()).unwrap();
+    KvsClient::new(addr)?;
+    s_rx.recv().unwrap();
+    Ok(())
+}
+
+// Connections broken by a server restart are replaced transparently
+#[test]
+fn client_pool_reconnect() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4103".parse().unwrap();
+    let start_server = || -> Result<_> {
+        let store = KvStore::open(temp_dir.path())?;
+        let pool = SharedQueueThreadPool::new(2)?;
+        let (c_tx, s_rx) = unbounded();
+        let (s_tx, c_rx) = unbounded();
+        let mut server = KvsReactorServer::new(store, pool).rx(c_rx).tx(c_tx);
+        thread::spawn(move || {
+            server.listen(addr).unwrap();
+        });
+        thread::sleep(Duration::from_millis(500));
+        Ok((s_tx, s_rx))
+    };
+
+    let pool = KvsClientPool::new(addr, 4).retry_set(true);
+    let (s_tx, s_rx) = start_server()?;
+    pool.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(pool.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(pool.idle_count(), 1);
+    s_tx.send(()).unwrap();
+    KvsClient::new(addr)?;
+    s_rx.recv().unwrap();
+
+    let (s_tx, s_rx) = start_server()?;
+    assert_eq!(pool.get("key1".to_owned())?, Some("value1".to_owned()));
+    pool.remove("key1".to_owned())?;
+    assert_eq!(pool.get("key1".to_owned())?, None);
+    assert_eq!(pool.idle_count(), 1);
+    s_tx.send(()).unwrap();
+    KvsClient::new(addr)?;
+    s_rx.recv().unwrap();
+    Ok(())
+}
+
+#[test]
+fn client_pool_no_retry_on_server_error() -> Result<()> {
+    let addr: SocketAddr = "127.0.0.1:4109".parse().unwrap();
+    // handshakes, then fails every command with a storage error
+    let listener = TcpListener::bind(addr)?;
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let counter = accepted.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            counter.fetch_add(1, Ordering::SeqCst);
+            let cmds = serde_json::Deserializer::from_reader(stream.try_clone().unwrap())
+                .into_iter::<SessionClientCommand>();
+            for cmd in cmds {
+                let resp = match cmd {
+                    Ok(SessionClientCommand::Handshake(_)) => {
+                        SessionServerResp::Handshake(HandshakeResp {
+                            version: PROTOCOL_VERSION,
+                            features: vec![],
+                            server: "fake".to_owned(),
+                        })
+                    }
+                    Ok(_) => SessionServerResp::ERR(SessionError::new(ErrorCode::Io, "disk full")),
+                    Err(_) => break,
+                };
+                serde_json::to_writer(&mut stream, &resp).unwrap();
+                stream.flush().unwrap();
+            }
+        }
+    });
+
+    let pool = KvsClientPool::new(addr, 4);
+    match pool.get("key1".to_owned()) {
+        Err(KvStoreError::RemoteIo(e)) => assert!(e.contains("disk full")),
+        res => panic!("unexpected result {:?}", res),
+    }
+    // answered by the server, no reconnect
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+    assert_eq!(pool.idle_count(), 1);
+    Ok(())
+}
+
+#[test]
+fn handshake_negotiation() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4104".parse().unwrap();
+    let store = KvStore::open(temp_dir.path())?;
+    let pool = SharedQueueThreadPool::new(2)?;
+    let (c_tx, s_rx) = unbounded();
+    let (s_tx, c_rx) = unbounded();
+    let mut server = KvsServer::new(store, pool).rx(c_rx).tx(c_tx);
+    thread::spawn(move || {
+        server.listen(addr).unwrap();
+    });
+    thread::sleep(Duration::from_millis(500));
+
+    // commands are refused before the handshake
+    let mut client = KvsClient::new(addr)?.with_features(vec![Feature::Compression]);
+    assert!(client.negotiated().is_none());
+    match client.get("key1".to_owned()) {
+        Err(KvStoreError::InvalidRequest(e)) => assert!(e.contains("Handshake required")),
+        _ => panic!("get before handshake should fail"),
+    }
+    client.handshake()?;
+    {
+        let negotiated = client.negotiated().unwrap();
+        assert_eq!(negotiated.version, PROTOCOL_VERSION);
+        assert!(negotiated.server.starts_with("kvs/"));
+        // not offered by the server
+        assert!(!negotiated.supports(Feature::Compression));
+    }
+    assert_eq!(client.get("key1".to_owned())?, None);
+    client.quit()?;
+
+    // a peer from the future is rejected
+    let mut client = KvsClient::new(addr)?;
+    let req = HandshakeRequest {
+        version: PROTOCOL_VERSION + 10,
+        min_version: PROTOCOL_VERSION + 10,
+        ..HandshakeRequest::default()
+    };
+    match client.cmd(&SessionClientCommand::Handshake(req))? {
+        SessionServerResp::Incompatible { version, .. } => assert_eq!(version, PROTOCOL_VERSION),
+        resp => panic!("unexpected response {:?}", resp),
+    }
+
+    // so are version 1 clients, with or without a payload
+    for legacy in &[r#""Handshake""#, r#"{"Handshake":{"client":"kvs/0.1"}}"#] {
+        let mut stream = TcpStream::connect(addr)?;
+        stream.write_all(legacy.as_bytes())?;
+        let mut resps =
+            serde_json::Deserializer::from_reader(stream).into_iter::<SessionServerResp>();
+        match resps.next() {
+            Some(Ok(SessionServerResp::Incompatible { version, .. })) => {
+                assert_eq!(version, PROTOCOL_VERSION)
+            }
+            resp => panic!("unexpected response {:?}", resp),
+        }
+    }
+
+    s_tx.send(()).unwrap();
+    KvsClient::new(addr)?;
+    s_rx.recv().unwrap();
+    Ok(())
+}
+
+#[test]
+fn error_code_round_trip() {
+    let e = SessionError::from(&KvStoreError::KeyNotFound);
+    assert_eq!(e.code, ErrorCode::KeyNotFound);
+    match KvStoreError::from(e) {
+        KvStoreError::KeyNotFound => {}
+        e => panic!("unexpected error {:?}", e),
+    }
+
+    let io_e = io::Error::new(io::ErrorKind::TimedOut, "disk stalled");
+    let e = SessionError::from(&KvStoreError::Io(io_e));
+    assert_eq!(e.code, ErrorCode::Timeout);
+
+    let io_e = io::Error::new(io::ErrorKind::PermissionDenied, "read only fs");
+    let e = SessionError::from(&KvStoreError::Io(io_e));
+    assert_eq!(e.code, ErrorCode::Io);
+    match KvStoreError::from(e) {
+        KvStoreError::RemoteIo(e) => assert!(e.contains("read only fs")),
+        e => panic!("unexpected error {:?}", e),
+    }
+}
+
+#[test]
+fn client_timeout() -> Result<()> {
+    let addr: SocketAddr = "127.0.0.1:4105".parse().unwrap();
+    // accepts but never answers
+    let listener = TcpListener::bind(addr)?;
+    let mut client = KvsClient::new(addr)?;
+    client.set_timeout(Some(Duration::from_millis(100)))?;
+    match client.handshake() {
+        Err(KvStoreError::Timeout) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+    drop(listener);
+    Ok(())
+}
+
+#[test]
+fn server_busy() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4106".parse().unwrap();
+    let store = KvStore::open(temp_dir.path())?;
+    // no queue, a session is only taken by an idle thread
+    let pool = SharedQueueThreadPool::bounded(1, 0, QueuePolicy::Reject)?;
+    let (c_tx, s_rx) = unbounded();
+    let (s_tx, c_rx) = unbounded();
+    let mut server = KvsServer::new(store, pool).rx(c_rx).tx(c_tx);
+    thread::spawn(move || server.listen(addr).unwrap());
+    thread::sleep(Duration::from_millis(300));
+
+    let mut first = KvsClient::new(addr)?;
+    first.handshake()?;
+    first.set("key1".to_owned(), "value1".to_owned())?;
+    let mut second = KvsClient::new(addr)?;
+    match second.handshake() {
+        Err(KvStoreError::Busy(_)) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+
+    // the thread is free again
+    first.quit()?;
+    thread::sleep(Duration::from_millis(100));
+    let mut third = KvsClient::new(addr)?;
+    third.handshake()?;
+    assert_eq!(third.get("key1".to_owned())?, Some("value1".to_owned()));
+    third.quit()?;
+
+    s_tx.send(()).unwrap();
+    let _ = KvsClient::new(addr);
+    s_rx.recv().unwrap();
+    Ok(())
+}
+
+// The server runs the compaction in the background once due, at most one of it and the writers compacts
+#[test]
+fn background_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4107".parse().unwrap();
+    let store = KvStore::open(temp_dir.path())?;
+    let pool = PriorityThreadPool::new(2)?;
+    let (c_tx, s_rx) = unbounded();
+    let (s_tx, c_rx) = unbounded();
+    let mut server = KvsServer::new(store.clone(), pool).rx(c_rx).tx(c_tx);
+    thread::spawn(move || server.listen(addr).unwrap());
+    thread::sleep(Duration::from_millis(300));
+
+    let mut client = KvsClient::new(addr)?;
+    client.handshake()?;
+    common::fill(|k, v| client.set(k, v))?;
+    let mut compactions = 0;
+    for _ in 0..20 {
+        compactions = store.stats()?.compactions;
+        if compactions > 0 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert_eq!(compactions, 1);
+    assert_eq!(client.get("key99".to_owned())?, Some("x".repeat(1000)));
+    client.quit()?;
+
+    s_tx.send(()).unwrap();
+    let _ = KvsClient::new(addr);
+    s_rx.recv().unwrap();
+    Ok(())
+}
@@ -0,0 +1,222 @@
+use crossbeam::channel::{unbounded, Sender};
+use kvs::network::auth::{Access, AclRule, User};
+use kvs::network::{Authenticator, HashRing, KvsClient, KvsServer, ShardedKvsClient};
+use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+use kvs::{KvStore, KvStoreError, Result};
+use std::net::SocketAddr;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// Serve a fresh store in the background, the returned closure stops the server
+fn start_server(addr: SocketAddr, dir: &TempDir) -> Result<impl FnOnce()> {
+    let store = KvStore::open(dir.path())?;
+    let pool = SharedQueueThreadPool::new(4)?;
+    let (c_tx, s_rx) = unbounded();
+    let (s_tx, c_rx): (Sender<()>, _) = unbounded();
+    let mut server = KvsServer::new(store, pool).rx(c_rx).tx(c_tx);
+    thread::spawn(move || {
+        server.listen(addr).unwrap();
+    });
+    thread::sleep(Duration::from_millis(300));
+    Ok(move || {
+        s_tx.send(()).unwrap();
+        let _ = KvsClient::new(addr);
+        s_rx.recv().unwrap();
+    })
+}
+
+fn keys_on(addr: SocketAddr) -> Result<Vec<String>> {
+    let mut client = KvsClient::new(addr)?;
+    client.handshake()?;
+    Ok(client
+        .scan(String::new())?
+        .into_iter()
+        .map(|(k, _)| k)
+        .collect())
+}
+
+#[test]
+fn hash_ring_moves_few_keys() {
+    let addrs: Vec<SocketAddr> = (0..4)
+        .map(|i| format!("127.0.0.1:{}", 5000 + i).parse().unwrap())
+        .collect();
+    let mut ring = HashRing::new(100);
+    for addr in &addrs[..3] {
+        ring.add(*addr);
+    }
+    let keys: Vec<String> = (0..3000).map(|i| format!("key{}", i)).collect();
+    let before: Vec<_> = keys.iter().map(|k| ring.node(k).unwrap()).collect();
+    for addr in &addrs[..3] {
+        let owned = before.iter().filter(|a| *a == addr).count();
+        assert!(owned > 600 && owned < 1400, "{} owns {}", addr, owned);
+    }
+
+    ring.add(addrs[3]);
+    let after: Vec<_> = keys.iter().map(|k| ring.node(k).unwrap()).collect();
+    for (b, a) in before.iter().zip(&after) {
+        // keys only ever move to the new server
+        assert!(a == b || *a == addrs[3]);
+    }
+    let moved = after.iter().filter(|a| **a == addrs[3]).count();
+    assert!(moved > 400 && moved < 1100, "moved {}", moved);
+
+    ring.remove(addrs[3]);
+    let back: Vec<_> = keys.iter().map(|k| ring.node(k).unwrap()).collect();
+    assert_eq!(before, back);
+}
+
+#[test]
+fn sharded_client_routes_and_fans_out() -> Result<()> {
+    let dirs: Vec<TempDir> = (0..3)
+        .map(|_| TempDir::new().expect("unable to create temporary working directory"))
+        .collect();
+    let addrs: Vec<SocketAddr> = (0..3)
+        .map(|i| format!("127.0.0.1:{}", 4181 + i).parse().unwrap())
+        .collect();
+    let mut stops = vec![];
+    for (addr, dir) in addrs.iter().zip(&dirs) {
+        stops.push(start_server(*addr, dir)?);
+    }
+
+    let mut client = ShardedKvsClient::new(&addrs);
+    for i in 0..60 {
+        client.set(format!("key{:02}", i), format!("value{}", i))?;
+    }
+    assert_eq!(client.get("key07".to_owned())?, Some("value7".to_owned()));
+    client.remove("key07".to_owned())?;
+    assert_eq!(client.get("key07".to_owned())?, None);
+
+    // every server got a share, each key on its owner only
+    let mut total = 0;
+    for addr in &addrs {
+        let keys = keys_on(*addr)?;
+        assert!(!keys.is_empty());
+        for k in &keys {
+            assert_eq!(client.node(k)?, *addr);
+        }
+        total += keys.len();
+    }
+    assert_eq!(total, 59);
+
+    let pairs = client.scan("key1".to_owned())?;
+    let expected: Vec<(String, String)> = (10..20)
+        .map(|i| (format!("key{}", i), format!("value{}", i)))
+        .collect();
+    assert_eq!(pairs, expected);
+
+    let keys: Vec<String> = vec!["key30", "key07", "key01", "missing", "key59"]
+        .into_iter()
+        .map(|k| k.to_owned())
+        .collect();
+    assert_eq!(
+        client.get_many(&keys)?,
+        vec![
+            Some("value30".to_owned()),
+            None,
+            Some("value1".to_owned()),
+            None,
+            Some("value59".to_owned()),
+        ]
+    );
+
+    for stop in stops {
+        stop();
+    }
+    Ok(())
+}
+
+#[test]
+fn add_and_remove_node_migrate_keys() -> Result<()> {
+    let dirs: Vec<TempDir> = (0..3)
+        .map(|_| TempDir::new().expect("unable to create temporary working directory"))
+        .collect();
+    let addrs: Vec<SocketAddr> = (0..3)
+        .map(|i| format!("127.0.0.1:{}", 4184 + i).parse().unwrap())
+        .collect();
+    let mut stops = vec![];
+    for (addr, dir) in addrs.iter().zip(&dirs) {
+        stops.push(start_server(*addr, dir)?);
+    }
+
+    let mut client = ShardedKvsClient::new(&addrs[..2]);
+    for i in 0..100 {
+        client.set(format!("key{}", i), format!("value{}", i))?;
+    }
+
+    let moved = client.add_node(addrs[2])?;
+    let on_new = keys_on(addrs[2])?;
+    assert!(moved > 0);
+    assert_eq!(moved, on_new.len());
+    for addr in &addrs {
+        for k in keys_on(*addr)? {
+            assert_eq!(client.node(&k)?, *addr);
+        }
+    }
+    assert_eq!(client.scan(String::new())?.len(), 100);
+    assert_eq!(client.rebalance()?, 0);
+
+    let moved = client.remove_node(addrs[0])?;
+    assert!(moved > 0);
+    assert!(keys_on(addrs[0])?.is_empty());
+    for i in 0..100 {
+        assert_eq!(
+            client.get(format!("key{}", i))?,
+            Some(format!("value{}", i))
+        );
+    }
+    assert!(client.remove_node(addrs[1]).is_ok());
+    assert!(client.remove_node(addrs[2]).is_err());
+    assert_eq!(keys_on(addrs[2])?.len(), 100);
+
+    for stop in stops {
+        stop();
+    }
+    Ok(())
+}
+
+#[test]
+fn get_many_failure_keeps_connection_in_sync() -> Result<()> {
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4187".parse().unwrap();
+    let rule = AclRule {
+        prefix: "app/".to_owned(),
+        access: Access::ReadWrite,
+    };
+    let auth = Authenticator {
+        users: vec![User::new("app", "s1", "secret", vec![rule])],
+    };
+    let pool = SharedQueueThreadPool::new(2)?;
+    let (c_tx, s_rx) = unbounded();
+    let (s_tx, c_rx): (Sender<()>, _) = unbounded();
+    let mut server = KvsServer::new(KvStore::open(dir.path())?, pool)
+        .auth(auth)
+        .rx(c_rx)
+        .tx(c_tx);
+    thread::spawn(move || {
+        server.listen(addr).unwrap();
+    });
+    thread::sleep(Duration::from_millis(300));
+
+    let mut client = ShardedKvsClient::new(&[addr]).with_credentials("app", "secret");
+    client.set("app/key1".to_owned(), "value1".to_owned())?;
+    client.set("app/key2".to_owned(), "value2".to_owned())?;
+    let keys: Vec<String> = vec!["app/key1", "other/key", "app/key2"]
+        .into_iter()
+        .map(|k| k.to_owned())
+        .collect();
+    match client.get_many(&keys) {
+        Err(KvStoreError::PermissionDenied(_)) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+    // the answers to the rest of the batch are not taken for this one
+    assert_eq!(
+        client.get("app/key1".to_owned())?,
+        Some("value1".to_owned())
+    );
+
+    s_tx.send(()).unwrap();
+    let _ = KvsClient::new(addr);
+    s_rx.recv().unwrap();
+    Ok(())
+}
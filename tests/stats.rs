@@ -0,0 +1,203 @@
+use assert_cmd::prelude::*;
+use crossbeam::channel::{unbounded, Sender};
+use kvs::network::{KvsClient, KvsReactorServer, KvsServer};
+use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+use kvs::{KvStore, KvStoreError, KvsEngine, Result, SledKvsEngine};
+use predicates::str::contains;
+use std::net::SocketAddr;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn connect(addr: SocketAddr) -> Result<KvsClient> {
+    let mut client = KvsClient::new(addr)?;
+    client.handshake()?;
+    Ok(client)
+}
+
+#[test]
+fn kv_store_stats() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let stats = store.stats()?;
+    assert_eq!(stats.engine, "kvs");
+    assert_eq!(stats.keys, 0);
+    assert_eq!(stats.dead_bytes, 0);
+
+    store.set("a".to_owned(), "1".to_owned())?;
+    store.set("b".to_owned(), "2".to_owned())?;
+    assert_eq!(store.stats()?.keys, 2);
+    assert_eq!(store.stats()?.dead_bytes, 0);
+    store.set("a".to_owned(), "3".to_owned())?;
+    store.remove("b".to_owned())?;
+    let stats = store.stats()?;
+    assert_eq!(stats.keys, 1);
+    assert!(stats.dead_bytes > 0);
+    assert!(stats.disk_bytes > stats.dead_bytes);
+
+    // the removal isn't counted as a key after a reopen
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.stats()?.keys, 1);
+    assert!(store.stats()?.dead_bytes > 0);
+    match store.remove("b".to_owned()) {
+        Err(KvStoreError::KeyNotFound) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+    Ok(())
+}
+
+#[test]
+fn sled_stats() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = SledKvsEngine::open(temp_dir.path())?;
+    store.set("a".to_owned(), "1".to_owned())?;
+    store.set("b".to_owned(), "2".to_owned())?;
+    store.remove("a".to_owned())?;
+    let stats = store.stats()?;
+    assert_eq!(stats.engine, "sled");
+    assert_eq!(stats.keys, 1);
+    Ok(())
+}
+
+#[test]
+fn server_info() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let addr: SocketAddr = "127.0.0.1:4211".parse().unwrap();
+    let (c_tx, s_rx) = unbounded();
+    let (s_tx, c_rx): (Sender<()>, _) = unbounded();
+    let mut server = KvsServer::new(store.clone(), SharedQueueThreadPool::new(2)?)
+        .rx(c_rx)
+        .tx(c_tx);
+    thread::spawn(move || server.listen(addr).unwrap());
+    thread::sleep(Duration::from_millis(300));
+
+    let mut client = connect(addr)?;
+    client.set("a".to_owned(), "1".to_owned())?;
+    client.set("b".to_owned(), "2".to_owned())?;
+    let info = client.info()?;
+    assert_eq!(info.engine, store.stats()?);
+    assert!(!info.read_only);
+    assert_eq!(info.connections, 1);
+    assert_eq!(info.active_sessions, 1);
+    // handshake and two sets, the info is counted once answered
+    assert_eq!(info.commands, 3);
+    assert!(format!("{}", info).contains("keys: 2"));
+    let pool = info.pool.expect("no pool stats");
+    assert_eq!(pool.threads, 2);
+
+    // a closed session is no longer active
+    client.quit()?;
+    drop(client);
+    thread::sleep(Duration::from_millis(100));
+    let info = connect(addr)?.info()?;
+    assert_eq!(info.connections, 2);
+    assert_eq!(info.active_sessions, 1);
+
+    s_tx.send(()).unwrap();
+    let _ = KvsClient::new(addr);
+    s_rx.recv().unwrap();
+
+    // the reactor runs engine operations on its pool as well
+    let addr: SocketAddr = "127.0.0.1:4212".parse().unwrap();
+    let (c_tx, s_rx) = unbounded();
+    let (s_tx, c_rx): (Sender<()>, _) = unbounded();
+    let mut server = KvsReactorServer::new(store, SharedQueueThreadPool::new(3)?)
+        .rx(c_rx)
+        .tx(c_tx);
+    thread::spawn(move || server.listen(addr).unwrap());
+    thread::sleep(Duration::from_millis(300));
+    let info = connect(addr)?.info()?;
+    assert_eq!(info.engine.keys, 2);
+    assert_eq!(info.pool.expect("no pool stats").threads, 3);
+    s_tx.send(()).unwrap();
+    let _ = KvsClient::new(addr);
+    s_rx.recv().unwrap();
+    Ok(())
+}
+
+#[test]
+fn cli_info() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut server = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4213"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key1", "value1", "--addr", "127.0.0.1:4213"])
+        .assert()
+        .success();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["info", "--addr", "127.0.0.1:4213"])
+        .assert()
+        .success()
+        .stdout(contains("engine: kvs\nkeys: 1\n"))
+        .stdout(contains("pool_threads: "));
+
+    server.kill().expect("server exited before killed");
+    server.wait().unwrap();
+}
+
+#[test]
+fn cli_elastic_pool() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut server = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args([
+            "--addr",
+            "127.0.0.1:4214",
+            "--min-threads",
+            "2",
+            "--max-threads",
+            "8",
+        ])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["info", "--addr", "127.0.0.1:4214"])
+        .assert()
+        .success()
+        .stdout(contains("pool_threads: 2\n"));
+
+    server.kill().expect("server exited before killed");
+    server.wait().unwrap();
+}
+
+#[test]
+fn cli_priority_pool() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut server = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4215", "--priority-pool"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key1", "value1", "--addr", "127.0.0.1:4215"])
+        .assert()
+        .success();
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "--addr", "127.0.0.1:4215"])
+        .assert()
+        .success()
+        .stdout("value1\n");
+
+    server.kill().expect("server exited before killed");
+    server.wait().unwrap();
+}
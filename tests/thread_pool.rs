@@ -1,10 +1,16 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
+mod common;
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use kvs::thread_pool::*;
-use kvs::Result;
+use kvs::{KvStore, KvStoreError, KvsEngine, Result};
 
 use crossbeam_utils::sync::WaitGroup;
+use tempfile::TempDir;
 
 fn spawn_counter<P: ThreadPool>(pool: P) -> Result<()> {
     const TASK_NUM: usize = 20;
@@ -64,7 +70,779 @@ fn rayon_thread_pool_spawn_counter() -> Result<()> {
     spawn_counter(pool)
 }
 
+#[test]
+fn work_stealing_thread_pool_spawn_counter() -> Result<()> {
+    let pool = WorkStealingThreadPool::new(4)?;
+    spawn_counter(pool)
+}
+
 #[test]
 fn shared_queue_thread_pool_panic_task() -> Result<()> {
     spawn_panic_task::<SharedQueueThreadPool>()
 }
+
+#[test]
+fn work_stealing_thread_pool_panic_task() -> Result<()> {
+    spawn_panic_task::<WorkStealingThreadPool>()
+}
+
+#[test]
+fn work_stealing_thread_pool_busy_worker() -> Result<()> {
+    let pool = WorkStealingThreadPool::new(2)?;
+    // the jobs the held worker took in its batch have to be stolen
+    let release = common::hold_worker(&pool);
+    spawn_counter(pool)?;
+    drop(release);
+    Ok(())
+}
+
+fn shutdown_runs_queued_jobs<P: ThreadPool>() -> Result<()> {
+    const TASK_NUM: usize = 20;
+
+    let pool = P::new(2)?;
+    let counter = Arc::new(AtomicUsize::new(0));
+    for _ in 0..TASK_NUM {
+        let counter = Arc::clone(&counter);
+        pool.spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            counter.fetch_add(1, Ordering::SeqCst);
+        })
+    }
+    pool.shutdown();
+    pool.join();
+    assert_eq!(counter.load(Ordering::SeqCst), TASK_NUM);
+
+    // ignored once shut down
+    let late = Arc::clone(&counter);
+    pool.spawn(move || {
+        late.fetch_add(1, Ordering::SeqCst);
+    });
+    drop(pool);
+    assert_eq!(counter.load(Ordering::SeqCst), TASK_NUM);
+    Ok(())
+}
+
+fn shutdown_now_drops_queued_jobs<P: ThreadPool>() -> Result<()> {
+    let pool = P::new(1)?;
+    let counter = Arc::new(AtomicUsize::new(0));
+    // the other jobs queue up
+    let release = common::hold_worker(&pool);
+    for _ in 0..10 {
+        let counter = Arc::clone(&counter);
+        pool.spawn(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        })
+    }
+    pool.shutdown_now();
+    drop(release);
+    // the running job finished, the queued ones never ran
+    pool.join();
+    assert_eq!(counter.load(Ordering::SeqCst), 0);
+    Ok(())
+}
+
+fn drop_leaves_workers_running<P: ThreadPool>() -> Result<()> {
+    let pool = P::new(2)?;
+    let (tx, rx) = crossbeam::channel::unbounded::<()>();
+    let (done_tx, done_rx) = crossbeam::channel::unbounded();
+    // a job that outlives the pool, like a session of a connected client
+    let blocked = done_tx.clone();
+    pool.spawn(move || {
+        rx.recv().unwrap();
+        blocked.send(()).unwrap();
+    });
+    for _ in 0..4 {
+        let done_tx = done_tx.clone();
+        pool.spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            done_tx.send(()).unwrap();
+        })
+    }
+    drop(pool);
+    tx.send(()).unwrap();
+    // the queued jobs still run
+    for _ in 0..5 {
+        done_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    // joining shuts the pool down first
+    let pool = P::new(2)?;
+    let counter = Arc::new(AtomicUsize::new(0));
+    for _ in 0..4 {
+        let counter = Arc::clone(&counter);
+        pool.spawn(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        })
+    }
+    pool.join();
+    assert_eq!(counter.load(Ordering::SeqCst), 4);
+    Ok(())
+}
+
+#[test]
+fn naive_thread_pool_shutdown() -> Result<()> {
+    shutdown_runs_queued_jobs::<NaiveThreadPool>()?;
+    drop_leaves_workers_running::<NaiveThreadPool>()
+}
+
+#[test]
+fn shared_queue_thread_pool_shutdown() -> Result<()> {
+    shutdown_runs_queued_jobs::<SharedQueueThreadPool>()?;
+    shutdown_now_drops_queued_jobs::<SharedQueueThreadPool>()?;
+    drop_leaves_workers_running::<SharedQueueThreadPool>()
+}
+
+#[test]
+fn rayon_thread_pool_shutdown() -> Result<()> {
+    shutdown_runs_queued_jobs::<RayonThreadPool>()?;
+    shutdown_now_drops_queued_jobs::<RayonThreadPool>()?;
+    drop_leaves_workers_running::<RayonThreadPool>()
+}
+
+#[test]
+fn work_stealing_thread_pool_shutdown() -> Result<()> {
+    shutdown_runs_queued_jobs::<WorkStealingThreadPool>()?;
+    shutdown_now_drops_queued_jobs::<WorkStealingThreadPool>()?;
+    drop_leaves_workers_running::<WorkStealingThreadPool>()
+}
+
+#[test]
+fn join_from_a_worker() -> Result<()> {
+    let pool = Arc::new(SharedQueueThreadPool::new(2)?);
+    let (tx, rx) = crossbeam::channel::unbounded();
+    let inner = Arc::clone(&pool);
+    pool.spawn(move || {
+        inner.shutdown();
+        // only waits for the other worker
+        inner.join();
+        tx.send(()).unwrap();
+    });
+    rx.recv().unwrap();
+    Ok(())
+}
+
+fn job_handles<P: ThreadPool>() -> Result<()> {
+    let pool = P::new(2)?;
+    let handles: Vec<JobHandle<usize>> = (0..10)
+        .map(|i| pool.spawn_with_handle(move || i * i))
+        .collect();
+    let squares = handles
+        .into_iter()
+        .map(JobHandle::join)
+        .collect::<Result<Vec<usize>>>()?;
+    assert_eq!(squares, (0..10).map(|i| i * i).collect::<Vec<usize>>());
+
+    let handle = pool.spawn_with_handle(|| {
+        panic_control::disable_hook_in_current_thread();
+        panic!("boom");
+    });
+    match handle.join() {
+        Err(KvStoreError::JobPanicked(msg)) => assert_eq!(msg, "boom"),
+        _ => panic!("expected the panic of the job"),
+    }
+    // the pool survives the panic
+    assert_eq!(pool.spawn_with_handle(|| 42).join()?, 42);
+
+    let (tx, rx) = crossbeam::channel::unbounded::<()>();
+    let mut handle = pool.spawn_with_handle(move || rx.recv().unwrap());
+    assert!(handle.try_join()?.is_none());
+    tx.send(()).unwrap();
+    let mut joined = None;
+    while joined.is_none() {
+        joined = handle.try_join()?;
+        thread::yield_now();
+    }
+    Ok(())
+}
+
+fn cancelled_job_handle<P: ThreadPool>() -> Result<()> {
+    let pool = P::new(1)?;
+    let release = common::hold_worker(&pool);
+    let queued = pool.spawn_with_handle(|| ());
+    pool.shutdown_now();
+    drop(release);
+    match queued.join() {
+        Err(KvStoreError::JobCancelled) => {}
+        _ => panic!("expected the job to be dropped"),
+    }
+    // spawned after the shutdown
+    match pool.spawn_with_handle(|| ()).join() {
+        Err(KvStoreError::JobCancelled) => {}
+        _ => panic!("expected the job to be dropped"),
+    }
+    Ok(())
+}
+
+#[test]
+fn naive_thread_pool_job_handles() -> Result<()> {
+    job_handles::<NaiveThreadPool>()
+}
+
+#[test]
+fn shared_queue_thread_pool_job_handles() -> Result<()> {
+    job_handles::<SharedQueueThreadPool>()?;
+    cancelled_job_handle::<SharedQueueThreadPool>()
+}
+
+#[test]
+fn rayon_thread_pool_job_handles() -> Result<()> {
+    job_handles::<RayonThreadPool>()?;
+    cancelled_job_handle::<RayonThreadPool>()
+}
+
+#[test]
+fn work_stealing_thread_pool_job_handles() -> Result<()> {
+    job_handles::<WorkStealingThreadPool>()?;
+    cancelled_job_handle::<WorkStealingThreadPool>()
+}
+
+fn scoped_jobs<P: ThreadPool>() -> Result<()> {
+    let pool = P::new(4)?;
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let keys: Vec<String> = (0..100).map(|i| format!("key{}", i)).collect();
+    for key in &keys {
+        store.set(key.clone(), format!("value of {}", key))?;
+    }
+
+    // jobs borrow the keys and the counter
+    let found = AtomicUsize::new(0);
+    let total = pool.scope(|s| {
+        for chunk in keys.chunks(10) {
+            let store = store.clone();
+            let found = &found;
+            s.spawn(move || {
+                for key in chunk {
+                    let value = store.get(key.clone()).unwrap();
+                    assert_eq!(value, Some(format!("value of {}", key)));
+                    found.fetch_add(1, Ordering::SeqCst);
+                }
+            });
+        }
+        keys.len()
+    });
+    assert_eq!(found.load(Ordering::SeqCst), total);
+
+    // a panicking job is raised by the scope once the others finished
+    let finished = AtomicUsize::new(0);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        pool.scope(|s| {
+            s.spawn(|| {
+                panic_control::disable_hook_in_current_thread();
+                panic!("boom");
+            });
+            for _ in 0..10 {
+                s.spawn(|| {
+                    thread::sleep(Duration::from_millis(10));
+                    finished.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+        })
+    }));
+    assert!(result.is_err());
+    assert_eq!(finished.load(Ordering::SeqCst), 10);
+
+    // so is a job the pool dropped
+    pool.shutdown();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| pool.scope(|s| s.spawn(|| ()))));
+    assert!(result.is_err());
+    Ok(())
+}
+
+#[test]
+fn naive_thread_pool_scope() -> Result<()> {
+    scoped_jobs::<NaiveThreadPool>()
+}
+
+#[test]
+fn shared_queue_thread_pool_scope() -> Result<()> {
+    scoped_jobs::<SharedQueueThreadPool>()
+}
+
+#[test]
+fn rayon_thread_pool_scope() -> Result<()> {
+    scoped_jobs::<RayonThreadPool>()
+}
+
+// Holds the only thread of `pool` and fills its queue of one, drop the sender to let go
+fn fill_bounded_pool(pool: &SharedQueueThreadPool) -> crossbeam::channel::Sender<()> {
+    let release = common::hold_worker(pool);
+    pool.try_spawn(|| ()).unwrap();
+    release
+}
+
+#[test]
+fn shared_queue_thread_pool_bounded() -> Result<()> {
+    let pool = Arc::new(SharedQueueThreadPool::bounded(1, 1, QueuePolicy::Reject)?);
+    let release = fill_bounded_pool(&pool);
+    match pool.try_spawn(|| ()) {
+        Err(KvStoreError::Busy(_)) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+    assert_eq!(pool.stats().queued, 1);
+    // `spawn` can't turn the job down, it waits
+    let (tx, rx) = crossbeam::channel::unbounded();
+    let waiting = Arc::clone(&pool);
+    thread::spawn(move || waiting.spawn(move || tx.send(()).unwrap()));
+    assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+    drop(release);
+    rx.recv().unwrap();
+    pool.shutdown();
+    match pool.try_spawn(|| ()) {
+        Err(KvStoreError::PoolShutdown) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+
+    let pool = SharedQueueThreadPool::bounded(1, 1, QueuePolicy::CallerRuns)?;
+    let release = fill_bounded_pool(&pool);
+    let caller = thread::current().id();
+    let (tx, rx) = crossbeam::channel::unbounded();
+    pool.try_spawn(move || tx.send(thread::current().id()).unwrap())?;
+    assert_eq!(rx.recv().unwrap(), caller);
+    drop(release);
+
+    let pool = Arc::new(SharedQueueThreadPool::bounded(1, 1, QueuePolicy::Block)?);
+    let release = fill_bounded_pool(&pool);
+    let (tx, rx) = crossbeam::channel::unbounded();
+    let blocked = Arc::clone(&pool);
+    thread::spawn(move || {
+        blocked.try_spawn(|| ()).unwrap();
+        tx.send(()).unwrap();
+    });
+    assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+    drop(release);
+    rx.recv().unwrap();
+
+    // shutting down doesn't wait for room in a full queue, a spawner waiting for it gives up
+    for &capacity in [0, 1].iter() {
+        let pool = Arc::new(SharedQueueThreadPool::bounded(
+            1,
+            capacity,
+            QueuePolicy::Block,
+        )?);
+        let release = common::hold_worker(&*pool);
+        if capacity > 0 {
+            pool.try_spawn(|| ())?;
+        }
+        let (tx, rx) = crossbeam::channel::unbounded();
+        let waiting = Arc::clone(&pool);
+        thread::spawn(move || tx.send(waiting.try_spawn(|| ())).unwrap());
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+        let (done_tx, done_rx) = crossbeam::channel::unbounded();
+        let closing = Arc::clone(&pool);
+        thread::spawn(move || {
+            closing.shutdown();
+            done_tx.send(()).unwrap();
+        });
+        done_rx
+            .recv_timeout(Duration::from_secs(1))
+            .expect("shutting down a full pool blocked");
+        match rx.recv_timeout(Duration::from_secs(1)).unwrap() {
+            Err(KvStoreError::PoolShutdown) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+        drop(release);
+        pool.join();
+    }
+    Ok(())
+}
+
+#[test]
+fn elastic_thread_pool_spawn_counter() -> Result<()> {
+    let pool = ElasticThreadPool::with_limits(1, 4, Duration::from_secs(1))?;
+    spawn_counter(pool)
+}
+
+#[test]
+fn elastic_thread_pool_panic_task() -> Result<()> {
+    spawn_panic_task::<ElasticThreadPool>()
+}
+
+#[test]
+fn elastic_thread_pool_shutdown() -> Result<()> {
+    shutdown_runs_queued_jobs::<ElasticThreadPool>()?;
+    shutdown_now_drops_queued_jobs::<ElasticThreadPool>()?;
+    drop_leaves_workers_running::<ElasticThreadPool>()
+}
+
+#[test]
+fn elastic_thread_pool_grows_and_shrinks() -> Result<()> {
+    let pool = ElasticThreadPool::with_limits(1, 4, Duration::from_millis(100))?;
+    assert_eq!(pool.stats().threads, 1);
+    assert!(ElasticThreadPool::with_limits(2, 1, Duration::from_millis(100)).is_err());
+
+    // 6 jobs which only finish together, 4 of them run at once
+    let (tx, rx) = crossbeam::channel::unbounded::<()>();
+    let (started_tx, started_rx) = crossbeam::channel::unbounded::<()>();
+    for _ in 0..6 {
+        let (rx, started_tx) = (rx.clone(), started_tx.clone());
+        pool.spawn(move || {
+            started_tx.send(()).unwrap();
+            let _ = rx.recv();
+        });
+    }
+    for _ in 0..4 {
+        started_rx.recv_timeout(Duration::from_secs(1)).unwrap();
+    }
+    assert!(started_rx.recv_timeout(Duration::from_millis(100)).is_err());
+    assert_eq!(pool.stats().threads, 4);
+    assert_eq!(pool.stats().queued, 2);
+
+    drop(tx);
+    for _ in 0..2 {
+        started_rx.recv_timeout(Duration::from_secs(1)).unwrap();
+    }
+    // back to the core size after the keep-alive
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(pool.stats().threads, 1);
+    Ok(())
+}
+
+#[test]
+fn elastic_thread_pool_without_core_workers() -> Result<()> {
+    // the only worker keeps timing out as the jobs come in, none of them is stranded
+    let pool = ElasticThreadPool::with_limits(0, 1, Duration::from_micros(100))?;
+    let (tx, rx) = crossbeam::channel::unbounded::<()>();
+    for i in 0..10_000 {
+        let tx = tx.clone();
+        pool.spawn(move || tx.send(()).unwrap());
+        rx.recv_timeout(Duration::from_secs(1)).unwrap();
+        thread::sleep(Duration::from_micros(i % 20 * 10));
+    }
+    // no one stays without jobs
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(pool.stats().threads, 0);
+    Ok(())
+}
+
+#[test]
+fn priority_thread_pool_spawn_counter() -> Result<()> {
+    let pool = PriorityThreadPool::new(4)?;
+    spawn_counter(pool)
+}
+
+#[test]
+fn priority_thread_pool_panic_task() -> Result<()> {
+    spawn_panic_task::<PriorityThreadPool>()
+}
+
+#[test]
+fn priority_thread_pool_shutdown() -> Result<()> {
+    shutdown_runs_queued_jobs::<PriorityThreadPool>()?;
+    shutdown_now_drops_queued_jobs::<PriorityThreadPool>()?;
+    drop_leaves_workers_running::<PriorityThreadPool>()
+}
+
+#[test]
+fn priority_thread_pool_order() -> Result<()> {
+    let pool = PriorityThreadPool::new(1)?;
+    let release = common::hold_worker(&pool);
+    let (tx, rx) = crossbeam::channel::unbounded();
+    let jobs = [
+        ("low", Priority::Low),
+        ("normal", Priority::Normal),
+        ("high", Priority::High),
+    ];
+    for &(name, priority) in jobs.iter() {
+        let tx = tx.clone();
+        pool.spawn_with_priority(priority, move || tx.send(name).unwrap());
+    }
+    // due after the high priority job, before the normal one
+    pool.spawn_with_deadline(Instant::now() + Duration::from_millis(50), move || {
+        tx.send("deadline").unwrap()
+    });
+    assert_eq!(pool.stats().queued, 4);
+
+    drop(release);
+    let order: Vec<&str> = rx.iter().take(4).collect();
+    assert_eq!(order, vec!["high", "deadline", "normal", "low"]);
+    Ok(())
+}
+
+#[test]
+fn priority_thread_pool_no_starvation() -> Result<()> {
+    let pool = PriorityThreadPool::new(1)?.max_wait(Priority::Low, Duration::from_millis(50));
+    let release = common::hold_worker(&pool);
+    let (tx, rx) = crossbeam::channel::unbounded();
+    let low = tx.clone();
+    pool.spawn_with_priority(Priority::Low, move || low.send("low").unwrap());
+    // the low priority job waited long enough to go first
+    thread::sleep(Duration::from_millis(100));
+    pool.spawn_with_priority(Priority::High, move || tx.send("high").unwrap());
+
+    drop(release);
+    let order: Vec<&str> = rx.iter().take(2).collect();
+    assert_eq!(order, vec!["low", "high"]);
+    Ok(())
+}
+
+fn scheduled_jobs<P: ThreadPool + Send + Sync + 'static>() -> Result<()> {
+    let pool = Arc::new(P::new(2)?);
+    let scheduler = Scheduler::new(&pool)?;
+    let (tx, rx) = crossbeam::channel::unbounded();
+    let start = Instant::now();
+    for &(name, delay) in [("second", 100), ("first", 50)].iter() {
+        let tx = tx.clone();
+        scheduler.schedule_after(Duration::from_millis(delay), move || tx.send(name).unwrap());
+    }
+    let late = tx.clone();
+    let cancelled = scheduler.schedule_after(Duration::from_millis(50), move || {
+        late.send("cancelled").unwrap();
+    });
+    assert!(cancelled.cancel());
+    assert!(!cancelled.cancel());
+    assert!(cancelled.is_cancelled());
+
+    assert_eq!(rx.recv_timeout(Duration::from_secs(1)).unwrap(), "first");
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert_eq!(rx.recv_timeout(Duration::from_secs(1)).unwrap(), "second");
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+    // a one-off job which ran can't be cancelled
+    let ran = scheduler.schedule_after(Duration::from_millis(0), move || tx.send("ran").unwrap());
+    assert_eq!(rx.recv_timeout(Duration::from_secs(1)).unwrap(), "ran");
+    assert!(!ran.cancel());
+    Ok(())
+}
+
+fn periodic_jobs<P: ThreadPool + Send + Sync + 'static>() -> Result<()> {
+    let pool = Arc::new(P::new(4)?);
+    let scheduler = Scheduler::new(&pool)?;
+    let runs = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&runs);
+    let every = scheduler.schedule_every(Duration::from_millis(20), move || {
+        counter.fetch_add(1, Ordering::SeqCst);
+    })?;
+    thread::sleep(Duration::from_millis(300));
+    assert!(every.cancel());
+    thread::sleep(Duration::from_millis(50));
+    let count = runs.load(Ordering::SeqCst);
+    assert!(count >= 5, "only {} runs", count);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(runs.load(Ordering::SeqCst), count);
+
+    // runs due while the last one is going are skipped
+    let (running, overlapped) = (
+        Arc::new(AtomicUsize::new(0)),
+        Arc::new(AtomicBool::new(false)),
+    );
+    let (now, overlap) = (Arc::clone(&running), Arc::clone(&overlapped));
+    let slow = scheduler.schedule_every(Duration::from_millis(10), move || {
+        if now.fetch_add(1, Ordering::SeqCst) > 0 {
+            overlap.store(true, Ordering::SeqCst);
+        }
+        thread::sleep(Duration::from_millis(50));
+        now.fetch_sub(1, Ordering::SeqCst);
+    })?;
+    thread::sleep(Duration::from_millis(300));
+    slow.cancel();
+    assert!(!overlapped.load(Ordering::SeqCst));
+
+    // a cancelled timer leaves the queue, dropping what its job holds
+    let held = Arc::new(());
+    let holder = Arc::clone(&held);
+    let every = scheduler.schedule_every(Duration::from_secs(60), move || {
+        let _ = &holder;
+    })?;
+    every.cancel();
+    drop(every);
+    assert_eq!(Arc::strong_count(&held), 1);
+
+    match scheduler.schedule_every(Duration::from_millis(0), || ()) {
+        Err(KvStoreError::InvalidRequest(_)) => {}
+        _ => panic!("zero interval accepted"),
+    }
+    Ok(())
+}
+
+#[test]
+fn thread_pool_scheduler() -> Result<()> {
+    scheduled_jobs::<SharedQueueThreadPool>()?;
+    scheduled_jobs::<RayonThreadPool>()?;
+    scheduled_jobs::<PriorityThreadPool>()?;
+    periodic_jobs::<SharedQueueThreadPool>()?;
+    periodic_jobs::<WorkStealingThreadPool>()
+}
+
+#[test]
+fn scheduler_drop_stops_timers() -> Result<()> {
+    let pool = Arc::new(SharedQueueThreadPool::new(1)?);
+    let scheduler = Scheduler::new(&pool)?.priority(Priority::Low);
+    let (tx, rx) = crossbeam::channel::unbounded::<()>();
+    let _handle = scheduler.schedule_after(Duration::from_millis(50), move || {
+        tx.send(()).unwrap();
+    });
+    drop(scheduler);
+    // the job was dropped along with the scheduler
+    assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+
+    // the scheduler doesn't keep the pool alive, nor its jobs once it's gone
+    let scheduler = Scheduler::new(&pool)?;
+    let (tx, rx) = crossbeam::channel::unbounded::<()>();
+    let _handle = scheduler.schedule_after(Duration::from_millis(50), move || {
+        tx.send(()).unwrap();
+    });
+    drop(pool);
+    assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+    Ok(())
+}
+
+fn pool_stats<P: ThreadPool>() -> Result<()> {
+    let (panic_tx, panic_rx) = crossbeam::channel::unbounded();
+    let pool = P::from_builder(ThreadPoolBuilder::new(2).panic_hook(move |payload| {
+        panic_tx.send(panic_message(payload)).unwrap();
+    }))?;
+
+    let (tx, rx) = crossbeam::channel::unbounded::<()>();
+    let (started_tx, started_rx) = crossbeam::channel::unbounded::<()>();
+    pool.spawn(move || {
+        started_tx.send(()).unwrap();
+        let _ = rx.recv();
+    });
+    started_rx.recv().unwrap();
+    assert_eq!(pool.stats().active, 1);
+    drop(tx);
+
+    for i in 0..3 {
+        pool.spawn(move || {
+            panic_control::disable_hook_in_current_thread();
+            panic!("boom {}", i);
+        });
+    }
+    for _ in 0..5 {
+        pool.spawn(|| ());
+    }
+    let mut panics: Vec<String> = (0..3).map(|_| panic_rx.recv().unwrap()).collect();
+    panics.sort();
+    assert_eq!(panics, vec!["boom 0", "boom 1", "boom 2"]);
+    // counted, but the spawner gets these
+    let handle = pool.spawn_with_handle(|| {
+        panic_control::disable_hook_in_current_thread();
+        panic!("handled");
+    });
+    assert!(handle.join().is_err());
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        pool.scope(|s| {
+            s.spawn(|| {
+                panic_control::disable_hook_in_current_thread();
+                panic!("scoped");
+            })
+        })
+    }));
+    assert!(result.is_err());
+
+    pool.shutdown();
+    pool.join();
+    let stats = pool.stats();
+    assert_eq!(stats.active, 0);
+    assert_eq!(stats.completed, 6);
+    assert_eq!(stats.panics, 5);
+    assert!(panic_rx.try_recv().is_err());
+    assert_eq!(stats.queued, 0);
+    Ok(())
+}
+
+#[test]
+fn thread_pool_stats() -> Result<()> {
+    pool_stats::<NaiveThreadPool>()?;
+    pool_stats::<SharedQueueThreadPool>()?;
+    pool_stats::<RayonThreadPool>()?;
+    pool_stats::<WorkStealingThreadPool>()?;
+    pool_stats::<ElasticThreadPool>()?;
+    pool_stats::<PriorityThreadPool>()
+}
+
+fn built_workers<P: ThreadPool>() -> Result<()> {
+    let started = Arc::new(AtomicUsize::new(0));
+    let stopped = Arc::new(AtomicUsize::new(0));
+    let (on_start, on_stop) = (Arc::clone(&started), Arc::clone(&stopped));
+    let pool: P = ThreadPoolBuilder::new(2)
+        .name_prefix("test-worker-")
+        .stack_size(4 * 1024 * 1024)
+        .start_hook(move |index| {
+            assert_eq!(
+                thread::current().name(),
+                Some(format!("test-worker-{}", index).as_str())
+            );
+            on_start.fetch_add(1, Ordering::SeqCst);
+        })
+        .stop_hook(move |_| {
+            on_stop.fetch_add(1, Ordering::SeqCst);
+        })
+        .build()?;
+
+    let name = pool
+        .spawn_with_handle(|| thread::current().name().map(str::to_owned))
+        .join()?;
+    assert!(name.unwrap().starts_with("test-worker-"));
+    pool.shutdown();
+    pool.join();
+    assert!(started.load(Ordering::SeqCst) > 0);
+    assert_eq!(
+        started.load(Ordering::SeqCst),
+        stopped.load(Ordering::SeqCst)
+    );
+    Ok(())
+}
+
+#[test]
+fn thread_pool_builder() -> Result<()> {
+    built_workers::<NaiveThreadPool>()?;
+    built_workers::<SharedQueueThreadPool>()?;
+    built_workers::<RayonThreadPool>()?;
+    built_workers::<WorkStealingThreadPool>()?;
+    built_workers::<ElasticThreadPool>()?;
+    built_workers::<PriorityThreadPool>()?;
+
+    let pool = SharedQueueThreadPool::bounded_from_builder(
+        ThreadPoolBuilder::new(1).name_prefix("bounded-"),
+        1,
+        QueuePolicy::Block,
+    )?;
+    let name = pool
+        .spawn_with_handle(|| thread::current().name().map(str::to_owned))
+        .join()?;
+    assert_eq!(name, Some("bounded-0".to_owned()));
+    let pool = ElasticThreadPool::with_limits_from_builder(
+        ThreadPoolBuilder::new(1).name_prefix("elastic-"),
+        1,
+        Duration::from_secs(1),
+    )?;
+    let name = pool
+        .spawn_with_handle(|| thread::current().name().map(str::to_owned))
+        .join()?;
+    assert_eq!(name, Some("elastic-0".to_owned()));
+
+    // a worker dying in its start hook is counted out
+    let pool: SharedQueueThreadPool = ThreadPoolBuilder::new(1)
+        .start_hook(|_| {
+            panic_control::disable_hook_in_current_thread();
+            panic!("no start");
+        })
+        .build()?;
+    pool.join();
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn thread_pool_cpu_affinity() -> Result<()> {
+    let pool: SharedQueueThreadPool = ThreadPoolBuilder::new(1).cpu_affinity(vec![0]).build()?;
+    let allowed = pool
+        .spawn_with_handle(|| {
+            let status = std::fs::read_to_string("/proc/thread-self/status").unwrap();
+            status
+                .lines()
+                .find(|l| l.starts_with("Cpus_allowed_list:"))
+                .map(|l| l["Cpus_allowed_list:".len()..].trim().to_owned())
+        })
+        .join()?;
+    assert_eq!(allowed, Some("0".to_owned()));
+
+    // past the cpu set, the worker runs unpinned
+    let pool: SharedQueueThreadPool = ThreadPoolBuilder::new(1).cpu_affinity(vec![4096]).build()?;
+    assert_eq!(pool.spawn_with_handle(|| 42).join()?, 42);
+    Ok(())
+}
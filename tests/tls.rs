@@ -0,0 +1,201 @@
+use assert_cmd::prelude::*;
+use crossbeam::channel::unbounded;
+use kvs::network::{tls, KvsClient, KvsServer};
+use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+use kvs::{KvStore, Result};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType, IsCa};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+struct TestPki {
+    ca: PathBuf,
+    server_cert: PathBuf,
+    server_key: PathBuf,
+    client_cert: PathBuf,
+    client_key: PathBuf,
+}
+
+fn gen_ca(name: &str) -> Certificate {
+    let mut params = CertificateParams::new(vec![]);
+    let mut dn = DistinguishedName::new();
+    dn.push(DnType::CommonName, name);
+    params.distinguished_name = dn;
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    Certificate::from_params(params).unwrap()
+}
+
+// Self-signed CA with a server and a client certificate signed by it
+fn gen_pki(dir: &Path) -> TestPki {
+    let ca = gen_ca("kvs test ca");
+    let server =
+        Certificate::from_params(CertificateParams::new(vec!["localhost".to_owned()])).unwrap();
+    let client =
+        Certificate::from_params(CertificateParams::new(vec!["client".to_owned()])).unwrap();
+
+    let pki = TestPki {
+        ca: dir.join("ca.pem"),
+        server_cert: dir.join("server.pem"),
+        server_key: dir.join("server.key"),
+        client_cert: dir.join("client.pem"),
+        client_key: dir.join("client.key"),
+    };
+    fs::write(&pki.ca, ca.serialize_pem().unwrap()).unwrap();
+    fs::write(
+        &pki.server_cert,
+        server.serialize_pem_with_signer(&ca).unwrap(),
+    )
+    .unwrap();
+    fs::write(&pki.server_key, server.serialize_private_key_pem()).unwrap();
+    fs::write(
+        &pki.client_cert,
+        client.serialize_pem_with_signer(&ca).unwrap(),
+    )
+    .unwrap();
+    fs::write(&pki.client_key, client.serialize_private_key_pem()).unwrap();
+    pki
+}
+
+fn access_server(client: &mut KvsClient) -> Result<()> {
+    client.handshake()?;
+    client.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(client.get("key1".to_owned())?, Some("value1".to_owned()));
+    client.quit()?;
+    Ok(())
+}
+
+#[test]
+fn tls_client_server() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let pki = gen_pki(temp_dir.path());
+    let addr: SocketAddr = "127.0.0.1:4121".parse().unwrap();
+    let store = KvStore::open(temp_dir.path())?;
+    let pool = SharedQueueThreadPool::new(2)?;
+    let (c_tx, s_rx) = unbounded();
+    let (s_tx, c_rx) = unbounded();
+    let config = tls::server_config(&pki.server_cert, &pki.server_key, None)?;
+    let mut server = KvsServer::new(store, pool).rx(c_rx).tx(c_tx).tls(config);
+    thread::spawn(move || {
+        server.listen(addr).unwrap();
+    });
+    thread::sleep(Duration::from_millis(500));
+
+    let config = tls::client_config(&pki.ca, None)?;
+    let mut client = KvsClient::new_tls(addr, &config, "localhost")?;
+    access_server(&mut client)?;
+
+    // the certificate is not issued for this name
+    let mut client = KvsClient::new_tls(addr, &config, "example.com")?;
+    assert!(client.handshake().is_err());
+
+    // server signed by an unknown CA
+    let other_dir = TempDir::new().expect("unable to create temporary working directory");
+    let other = gen_pki(other_dir.path());
+    let config = tls::client_config(&other.ca, None)?;
+    let mut client = KvsClient::new_tls(addr, &config, "localhost")?;
+    assert!(client.handshake().is_err());
+
+    s_tx.send(()).unwrap();
+    KvsClient::new(addr)?;
+    s_rx.recv().unwrap();
+    Ok(())
+}
+
+#[test]
+fn mutual_tls_client_server() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let pki = gen_pki(temp_dir.path());
+    let addr: SocketAddr = "127.0.0.1:4122".parse().unwrap();
+    let store = KvStore::open(temp_dir.path())?;
+    let pool = SharedQueueThreadPool::new(2)?;
+    let (c_tx, s_rx) = unbounded();
+    let (s_tx, c_rx) = unbounded();
+    let config = tls::server_config(&pki.server_cert, &pki.server_key, Some(&pki.ca))?;
+    let mut server = KvsServer::new(store, pool).rx(c_rx).tx(c_tx).tls(config);
+    thread::spawn(move || {
+        server.listen(addr).unwrap();
+    });
+    thread::sleep(Duration::from_millis(500));
+
+    let config = tls::client_config(&pki.ca, Some((&pki.client_cert, &pki.client_key)))?;
+    let mut client = KvsClient::new_tls(addr, &config, "localhost")?;
+    access_server(&mut client)?;
+
+    // no client certificate
+    let config = tls::client_config(&pki.ca, None)?;
+    let mut client = KvsClient::new_tls(addr, &config, "localhost")?;
+    assert!(client.handshake().is_err());
+
+    // plaintext is refused too
+    let mut client = KvsClient::new(addr)?;
+    assert!(client.handshake().is_err());
+
+    s_tx.send(()).unwrap();
+    KvsClient::new(addr)?;
+    s_rx.recv().unwrap();
+    Ok(())
+}
+
+#[test]
+fn cli_access_server_tls() {
+    let temp_dir = TempDir::new().unwrap();
+    let pki = gen_pki(temp_dir.path());
+    let addr = "127.0.0.1:4123";
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(["--engine", "kvs", "--addr", addr])
+        .arg("--tls-cert")
+        .arg(&pki.server_cert)
+        .arg("--tls-key")
+        .arg(&pki.server_key)
+        .arg("--tls-ca")
+        .arg(&pki.ca)
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key1", "value1", "--addr", addr])
+        .arg("--tls-ca")
+        .arg(&pki.ca)
+        .arg("--tls-cert")
+        .arg(&pki.client_cert)
+        .arg("--tls-key")
+        .arg(&pki.client_key)
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "--addr", addr])
+        .arg("--tls-ca")
+        .arg(&pki.ca)
+        .arg("--tls-cert")
+        .arg(&pki.client_cert)
+        .arg("--tls-key")
+        .arg(&pki.client_key)
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value1\n");
+
+    // missing client certificate
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "--addr", addr])
+        .arg("--tls-ca")
+        .arg(&pki.ca)
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+
+    child.kill().expect("server exited before killed");
+    child.wait().unwrap();
+}
@@ -0,0 +1,190 @@
+use assert_cmd::prelude::*;
+use crossbeam::channel::{unbounded, Sender};
+use kvs::network::{
+    Follower, KvsClient, KvsServer, Replication, WatchEvent, WatchFilter, WatchHub, Watcher,
+};
+use kvs::thread_pool::{SharedQueueThreadPool, ThreadPool};
+use kvs::{KvStore, Result};
+use std::io::{BufRead, BufReader};
+use std::net::SocketAddr;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+// Serve `store` in the background, the returned closure stops the server
+fn start_server(
+    addr: SocketAddr,
+    store: KvStore,
+    replication: Arc<Replication>,
+    watch: Arc<WatchHub>,
+) -> Result<impl FnOnce()> {
+    let pool = SharedQueueThreadPool::new(4)?;
+    let (c_tx, s_rx) = unbounded();
+    let (s_tx, c_rx): (Sender<()>, _) = unbounded();
+    let mut server = KvsServer::new(store, pool)
+        .rx(c_rx)
+        .tx(c_tx)
+        .replication(replication)
+        .watch(watch);
+    thread::spawn(move || {
+        server.listen(addr).unwrap();
+    });
+    thread::sleep(Duration::from_millis(300));
+    Ok(move || {
+        s_tx.send(()).unwrap();
+        let _ = KvsClient::new(addr);
+        s_rx.recv().unwrap();
+    })
+}
+
+fn connect(addr: SocketAddr) -> Result<KvsClient> {
+    let mut client = KvsClient::new(addr)?;
+    client.handshake()?;
+    Ok(client)
+}
+
+fn watch(addr: SocketAddr, filter: WatchFilter) -> Result<Watcher> {
+    connect(addr)?.watch(filter)
+}
+
+fn set(k: &str, v: &str) -> WatchEvent {
+    WatchEvent::Set(k.to_owned(), v.to_owned())
+}
+
+#[test]
+fn watch_key_and_prefix() -> Result<()> {
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4201".parse().unwrap();
+    let hub = Arc::new(WatchHub::new());
+    let stop = start_server(
+        addr,
+        KvStore::open(dir.path())?,
+        Arc::new(Replication::leader()),
+        hub.clone(),
+    )?;
+
+    let mut key = watch(addr, WatchFilter::Key("config/a".to_owned()))?;
+    let mut prefix = watch(addr, WatchFilter::Prefix("config/".to_owned()))?;
+    assert_eq!(hub.watcher_count(), 2);
+
+    let mut client = connect(addr)?;
+    client.set("config/a".to_owned(), "1".to_owned())?;
+    client.set("config/ab".to_owned(), "2".to_owned())?;
+    client.set("other".to_owned(), "3".to_owned())?;
+    client.remove("config/a".to_owned())?;
+    // a failed write changes nothing
+    assert!(client.remove("config/a".to_owned()).is_err());
+    client.set("config/a".to_owned(), "4".to_owned())?;
+
+    assert_eq!(key.next().unwrap()?, set("config/a", "1"));
+    assert_eq!(
+        key.next().unwrap()?,
+        WatchEvent::Remove("config/a".to_owned())
+    );
+    assert_eq!(key.next().unwrap()?, set("config/a", "4"));
+    assert_eq!(prefix.next().unwrap()?, set("config/a", "1"));
+    assert_eq!(prefix.next().unwrap()?, set("config/ab", "2"));
+    assert_eq!(
+        prefix.next().unwrap()?,
+        WatchEvent::Remove("config/a".to_owned())
+    );
+    assert_eq!(prefix.next().unwrap()?, set("config/a", "4"));
+
+    // gone watchers are dropped on the next change, matching or not
+    drop(key);
+    drop(prefix);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while hub.watcher_count() > 0 {
+        assert!(Instant::now() < deadline, "watchers left behind");
+        client.set("other".to_owned(), "5".to_owned())?;
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    stop();
+    Ok(())
+}
+
+#[test]
+fn watch_follower() -> Result<()> {
+    let leader_dir = TempDir::new().expect("unable to create temporary working directory");
+    let follower_dir = TempDir::new().expect("unable to create temporary working directory");
+    let leader_addr: SocketAddr = "127.0.0.1:4202".parse().unwrap();
+    let follower_addr: SocketAddr = "127.0.0.1:4203".parse().unwrap();
+    let stop_leader = start_server(
+        leader_addr,
+        KvStore::open(leader_dir.path())?,
+        Arc::new(Replication::leader()),
+        Arc::new(WatchHub::new()),
+    )?;
+
+    let store = KvStore::open(follower_dir.path())?;
+    let replication = Arc::new(Replication::follower());
+    let hub = Arc::new(WatchHub::new());
+    let follower = Follower::new(leader_addr, store.clone(), replication.clone())
+        .watch(hub.clone())
+        .spawn();
+    let stop_follower = start_server(follower_addr, store, replication.clone(), hub)?;
+
+    // replicated writes reach the follower's watchers
+    let mut watcher = watch(follower_addr, WatchFilter::Prefix(String::new()))?;
+    connect(leader_addr)?.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(watcher.next().unwrap()?, set("key1", "value1"));
+
+    replication.promote();
+    follower.join().unwrap();
+    stop_follower();
+    stop_leader();
+    Ok(())
+}
+
+#[test]
+fn cli_watch() {
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut server = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4204"])
+        .current_dir(&dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    let mut watcher = Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["watch", "config/", "--prefix", "--addr", "127.0.0.1:4204"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let (tx, rx) = unbounded();
+    let stdout = watcher.stdout.take().unwrap();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            if tx.send(line.unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+    thread::sleep(Duration::from_millis(500));
+
+    for args in &[
+        vec!["set", "config/a", "1"],
+        vec!["set", "other", "2"],
+        vec!["rm", "config/a"],
+    ] {
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(args)
+            .args(["--addr", "127.0.0.1:4204"])
+            .assert()
+            .success();
+    }
+    let timeout = Duration::from_secs(5);
+    assert_eq!(rx.recv_timeout(timeout).unwrap(), "set config/a 1");
+    assert_eq!(rx.recv_timeout(timeout).unwrap(), "rm config/a");
+
+    watcher.kill().expect("watcher exited before killed");
+    watcher.wait().unwrap();
+    server.kill().expect("server exited before killed");
+    server.wait().unwrap();
+}